@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chip8::analysis::Analysis;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Analyze {
+    /// Rom to analyze
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+
+    /// Write the control flow graph in Graphviz DOT format to this file
+    #[structopt(long = "cfg-dot", parse(from_os_str))]
+    cfg_dot: Option<PathBuf>,
+
+    /// Write the subroutine call graph in Graphviz DOT format to this file
+    #[structopt(long = "calls-dot", parse(from_os_str))]
+    calls_dot: Option<PathBuf>,
+
+    /// Write the control flow graph and call graph as JSON to this file
+    #[structopt(long = "json", parse(from_os_str))]
+    json: Option<PathBuf>,
+}
+
+pub fn run(opts: Analyze) {
+    let prog = fs::read(&opts.rom).expect("cannot read rom");
+    let analysis = Analysis::new(&prog);
+
+    println!(
+        "{} basic blocks, {} subroutines",
+        analysis.blocks.len(),
+        analysis.subroutines.len()
+    );
+
+    println!("\nunreachable regions:");
+    for r in &analysis.unreachable {
+        println!("  {:#05X}..{:#05X} ({} bytes)", r.start, r.end, r.len());
+    }
+
+    println!("\nunresolved computed jumps:");
+    for addr in &analysis.unresolved_jumps {
+        println!("  {:#05X}", addr);
+    }
+
+    println!("\nsuspicious targets:");
+    for s in &analysis.suspicious {
+        match s.target {
+            Some(target) => println!("  {:#05X}: {} ({:#05X})", s.addr, s.kind, target),
+            None => println!("  {:#05X}: {}", s.addr, s.kind),
+        }
+    }
+
+    if let Some(path) = opts.cfg_dot {
+        fs::write(path, analysis.cfg_dot()).expect("cannot write cfg");
+    }
+
+    if let Some(path) = opts.calls_dot {
+        fs::write(path, analysis.call_graph_dot()).expect("cannot write call graph");
+    }
+
+    if let Some(path) = opts.json {
+        fs::write(path, analysis.to_json().to_string()).expect("cannot write json");
+    }
+}
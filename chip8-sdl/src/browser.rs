@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chip8::chip8::{PROGRAM_START_PC, RAM_SIZE};
+use chip8::db::RomDatabase;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+use crate::config::{self, Action};
+use crate::gamepad::Gamepads;
+use crate::text::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::watch::SOURCE_EXTENSIONS;
+
+/// How many of the games played last are remembered.
+const MAX_RECENT: usize = 10;
+
+/// Files larger than this don't fit in RAM so they're not roms, unless
+/// they're sources.
+const MAX_ROM_SIZE: u64 = (RAM_SIZE - PROGRAM_START_PC) as u64;
+
+// the same text size of the menus
+const SCALE: i32 = 2;
+
+/// The games played last, the latest first, kept in `chip8/recent.txt` in
+/// the XDG data directory.
+pub struct Recent {
+    path: Option<PathBuf>,
+    roms: Vec<PathBuf>,
+}
+
+/// A line of the list of the browser.
+enum Line {
+    Heading(String),
+    Rom {
+        path: PathBuf,
+        title: String,
+        author: Option<String>,
+    },
+}
+
+/// Screen listing the recent games and the roms of the configured
+/// directories, shown when no rom is given and when leaving a game.
+pub struct Browser {
+    dirs: Vec<PathBuf>,
+    lines: Vec<Line>,
+    /// Index in `lines` of the rom selected, if there's any.
+    selected: usize,
+    /// Index in `lines` of the first line shown.
+    top: usize,
+    /// Why the last rom couldn't be played.
+    error: Option<String>,
+}
+
+impl Recent {
+    pub fn load() -> Self {
+        let path = config::xdg_dir("XDG_DATA_HOME", ".local/share").map(|d| d.join("recent.txt"));
+        let roms = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .map(|s| {
+                s.lines()
+                    .filter(|l| !l.trim().is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Recent { path, roms }
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        &self.roms
+    }
+
+    /// Move `rom` to the top of the list and save it.
+    pub fn add(&mut self, rom: &Path) {
+        let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+        self.roms.retain(|r| *r != rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(MAX_RECENT);
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let mut data = String::new();
+        for rom in &self.roms {
+            data += &format!("{}\n", rom.display());
+        }
+
+        let saved = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, data));
+        if let Err(e) = saved {
+            eprintln!("cannot save recent games: {}", e);
+        }
+    }
+}
+
+impl Browser {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        Browser {
+            dirs,
+            lines: vec![],
+            selected: 0,
+            top: 0,
+            error: None,
+        }
+    }
+
+    /// Show `error` until another rom is picked.
+    pub fn set_error(&mut self, error: String) {
+        eprintln!("{}", error);
+        self.error = Some(error);
+    }
+
+    /// Show the browser until a rom is picked or dropped on the window, none
+    /// if the window is closed.
+    pub fn run(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        event_pump: &mut EventPump,
+        gamepads: &mut Gamepads,
+        db: &RomDatabase,
+        recent: &Recent,
+        hotkeys: &HashMap<Keycode, Action>,
+    ) -> Option<PathBuf> {
+        self.scan(db, recent);
+        canvas
+            .window_mut()
+            .set_title(env!("CARGO_PKG_NAME"))
+            .unwrap();
+
+        loop {
+            for event in event_pump.poll_iter() {
+                let picked = match event {
+                    Event::Quit { .. } => return None,
+                    Event::DropFile { filename, .. } => Some(PathBuf::from(filename)),
+                    Event::KeyDown {
+                        keycode: Some(kc), ..
+                    } => {
+                        if hotkeys.get(&kc) == Some(&Action::Quit) {
+                            return None;
+                        }
+                        self.keydown(kc)
+                    }
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Left,
+                        y,
+                        ..
+                    } => self.click(y),
+                    _ => {
+                        // the controllers plugged before starting are
+                        // opened by their events
+                        gamepads.event(&event);
+                        None
+                    }
+                };
+
+                if picked.is_some() {
+                    self.error = None;
+                    return picked;
+                }
+            }
+
+            self.draw(canvas);
+            canvas.present();
+
+            ::std::thread::sleep(Duration::from_millis(16));
+        }
+    }
+
+    /// List the recent games and the roms of every directory again, with
+    /// the titles in the database.
+    fn scan(&mut self, db: &RomDatabase, recent: &Recent) {
+        self.lines.clear();
+
+        if !recent.roms().is_empty() {
+            self.lines.push(Line::Heading("RECENT".to_string()));
+            for path in recent.roms() {
+                self.lines.push(rom_line(path, db));
+            }
+        }
+
+        for dir in &self.dirs {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.lines
+                        .push(Line::Heading(format!("{}: {}", dir.display(), e)));
+                    continue;
+                }
+            };
+
+            let mut paths = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| is_rom(p))
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            self.lines.push(Line::Heading(dir.display().to_string()));
+            for path in paths {
+                self.lines.push(rom_line(&path, db));
+            }
+        }
+
+        self.top = 0;
+        let first = self.roms().next();
+        self.selected = first.unwrap_or(0);
+    }
+
+    fn roms(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| matches!(l, Line::Rom { .. }))
+            .map(|(i, _)| i)
+    }
+
+    /// Handle a key: Up and Down, Page Up and Page Down move the selection
+    /// and Enter plays the rom selected.
+    fn keydown(&mut self, kc: Keycode) -> Option<PathBuf> {
+        let roms = self.roms().collect::<Vec<_>>();
+        let current = roms.iter().position(|i| *i == self.selected)?;
+
+        let next = match kc {
+            Keycode::Up => current.saturating_sub(1),
+            Keycode::Down => current + 1,
+            Keycode::PageUp => current.saturating_sub(10),
+            Keycode::PageDown => current + 10,
+            Keycode::Home => 0,
+            Keycode::End => roms.len() - 1,
+            Keycode::Return | Keycode::KpEnter => return self.selected_path(),
+            _ => return None,
+        };
+        self.selected = roms[next.min(roms.len() - 1)];
+
+        None
+    }
+
+    /// Play the rom clicked, if any.
+    fn click(&mut self, y: i32) -> Option<PathBuf> {
+        let row = (y - list_y()).div_euclid(line_height());
+        if row < 0 {
+            return None;
+        }
+
+        let i = self.top + row as usize;
+        match self.lines.get(i) {
+            Some(Line::Rom { .. }) => {
+                self.selected = i;
+                self.selected_path()
+            }
+            _ => None,
+        }
+    }
+
+    fn selected_path(&self) -> Option<PathBuf> {
+        match self.lines.get(self.selected) {
+            Some(Line::Rom { path, .. }) => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    fn draw(&mut self, canvas: &mut Canvas<Window>) {
+        let (w, h) = canvas.output_size().unwrap();
+        let columns = ((w as i32 - 16) / ((GLYPH_WIDTH + 1) * SCALE)).max(1) as usize;
+        let text = |canvas: &mut Canvas<Window>, y: i32, color: Color, s: &str| {
+            let s = s.chars().take(columns).collect::<String>();
+            draw_text(canvas, 8, y, SCALE as u32, color, &s);
+        };
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        text(canvas, 8, Color::RGB(0xFF, 0xD7, 0x40), "CHIP-8 GAMES");
+        // the error takes the two lines above the list
+        if let Some(error) = &self.error {
+            let chars = error.chars().collect::<Vec<_>>();
+            for (i, chunk) in chars.chunks(columns).take(2).enumerate() {
+                let y = 8 + (i as i32 + 1) * line_height();
+                let line = chunk.iter().collect::<String>();
+                text(canvas, y, Color::RGB(0xFF, 0x50, 0x50), &line);
+            }
+        }
+
+        let footer = h as i32 - 8 - line_height();
+        let rows = ((footer - list_y()) / line_height()).max(1) as usize;
+
+        // keep the selection in sight
+        if self.selected < self.top {
+            self.top = self.selected.saturating_sub(1);
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows;
+        }
+
+        if self.lines.is_empty() {
+            let hint = [
+                "no roms yet: set rom_dirs in the",
+                "configuration or pass --rom-dir",
+            ];
+            for (i, line) in hint.iter().enumerate() {
+                let y = list_y() + i as i32 * line_height();
+                text(canvas, y, Color::RGB(0xFF, 0xFF, 0xFF), line);
+            }
+        }
+
+        for (row, line) in self.lines.iter().skip(self.top).take(rows).enumerate() {
+            let y = list_y() + row as i32 * line_height();
+
+            match line {
+                Line::Heading(heading) => text(canvas, y, Color::RGB(0x90, 0x90, 0x90), heading),
+                Line::Rom { title, author, .. } => {
+                    let cursor = if self.top + row == self.selected {
+                        '>'
+                    } else {
+                        ' '
+                    };
+                    let line = match author {
+                        Some(author) => format!("{} {} - {}", cursor, title, author),
+                        None => format!("{} {}", cursor, title),
+                    };
+                    text(canvas, y, Color::RGB(0xFF, 0xFF, 0xFF), &line);
+                }
+            }
+        }
+
+        text(
+            canvas,
+            footer,
+            Color::RGB(0x90, 0x90, 0x90),
+            "enter play   or drop a rom here",
+        );
+    }
+}
+
+/// The line of the rom at `path`, with its title and author if it's in
+/// the database.
+fn rom_line(path: &Path, db: &RomDatabase) -> Line {
+    let info = fs::read(path).ok().and_then(|data| db.lookup(&data));
+    let name = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    );
+
+    Line::Rom {
+        path: path.to_path_buf(),
+        title: info.map_or(name, |i| i.title.clone()),
+        author: info.and_then(|i| i.author.clone()),
+    }
+}
+
+/// Whether the file at `path` may be a rom or a source to build one.
+fn is_rom(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_none_or(|n| n.to_string_lossy().starts_with('.'));
+    if hidden {
+        return false;
+    }
+
+    let source = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SOURCE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+
+    match fs::metadata(path) {
+        Ok(meta) => meta.is_file() && (source || meta.len() <= MAX_ROM_SIZE),
+        Err(_) => false,
+    }
+}
+
+fn line_height() -> i32 {
+    (GLYPH_HEIGHT + 3) * SCALE
+}
+
+/// Where the list starts, below the title and the error.
+fn list_y() -> i32 {
+    8 + 3 * line_height()
+}
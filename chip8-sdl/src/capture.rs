@@ -0,0 +1,334 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chip8::audio::{Synth, WavRecorder};
+use chip8::capture::{render, AnimationFormat, AnimationRecorder, AvDump, Y4mWriter};
+use chip8::detect;
+use chip8::display::{AntiFlicker, Display};
+use chip8::filters::{Filter, Pipeline, Upscaler, UPSCALER_NAMES};
+use chip8::headless::{InputScript, Runner};
+use chip8::palette::Palette;
+
+use structopt::StructOpt;
+
+use crate::{screen, utils};
+
+#[derive(Debug, StructOpt)]
+pub struct Capture {
+    /// Rom to run
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+
+    /// Number of frames to run
+    #[structopt(long = "frames", default_value = "600")]
+    frames: usize,
+
+    /// Seed of the random number generator
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Script of the keys to press while running
+    #[structopt(long = "input", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Frequency of the emulator, defaults to the one recommended by the rom
+    /// database or 500 for unknown roms
+    #[structopt(short = "f", long = "frequency")]
+    freq: Option<usize>,
+
+    /// Additional rom database in JSON or TOML format
+    #[structopt(long = "rom-db", parse(from_os_str))]
+    rom_db: Option<PathBuf>,
+
+    /// How many times larger than the screen the captures are
+    #[structopt(long = "scale", default_value = "8")]
+    scale: usize,
+
+    /// Upscaler used to render the captures
+    #[structopt(long = "upscaler", default_value = "nearest", possible_values = &UPSCALER_NAMES)]
+    upscaler: Upscaler,
+
+    /// Filters applied to the captures in order
+    #[structopt(long = "filter", number_of_values = 1)]
+    filters: Vec<Filter>,
+
+    /// Anti-flicker processing of the screen
+    #[structopt(long = "anti-flicker", default_value = "off")]
+    anti_flicker: AntiFlicker,
+
+    /// Name of the builtin palette to use, defaults to the one in the rom
+    /// database
+    #[structopt(long = "palette")]
+    palette: Option<String>,
+
+    /// Background color as #RRGGBB
+    #[structopt(long = "bg", parse(try_from_str = screen::parse_color))]
+    bg: Option<[u8; 3]>,
+
+    /// Foreground color as #RRGGBB
+    #[structopt(long = "fg", parse(try_from_str = screen::parse_color))]
+    fg: Option<[u8; 3]>,
+
+    /// Write a PNG of the last frame to this file
+    #[structopt(long = "png", parse(from_os_str))]
+    png: Option<PathBuf>,
+
+    /// Write the run as an animated GIF to this file
+    #[structopt(long = "gif", parse(from_os_str))]
+    gif: Option<PathBuf>,
+
+    /// Write the run as an animated PNG to this file
+    #[structopt(long = "apng", parse(from_os_str))]
+    apng: Option<PathBuf>,
+
+    /// Write the run as raw Y4M video to this file
+    #[structopt(long = "y4m", parse(from_os_str))]
+    y4m: Option<PathBuf>,
+
+    /// Write the sound of the run as WAV to this file
+    #[structopt(long = "wav", visible_alias = "record-audio", parse(from_os_str))]
+    wav: Option<PathBuf>,
+}
+
+type CaptureFile = BufWriter<File>;
+
+/// The captures taken while playing, toggled by hotkeys.
+pub struct Recorder {
+    dir: PathBuf,
+    /// Prefix of the file names, the name of the rom.
+    name: String,
+    format: AnimationFormat,
+    pipeline: Pipeline,
+    animation: Option<(PathBuf, AnimationRecorder<CaptureFile>)>,
+    dump: Option<(PathBuf, AvDump<CaptureFile, CaptureFile>)>,
+}
+
+pub fn run(opts: Capture) {
+    let prog = fs::read(&opts.rom).expect("cannot read rom");
+    let db = utils::load_rom_db(opts.rom_db.as_deref());
+    let info = db.lookup(&prog);
+
+    let (quirks, ipf) = match info {
+        Some(info) => (info.quirks, info.instructions_per_frame),
+        None => (detect::detect(&prog).quirks, None),
+    };
+    let cycles_per_frame = match (opts.freq, ipf) {
+        (None, Some(ipf)) => ipf,
+        (freq, _) => freq.unwrap_or(500) / 60,
+    };
+
+    let palette = match &opts.palette {
+        Some(name) => {
+            Palette::find_builtin(name).unwrap_or_else(|| panic!("unknown palette {}", name))
+        }
+        None => info.and_then(|i| i.palette()).unwrap_or_default(),
+    };
+    let palette = palette.with_colors(opts.bg, opts.fg);
+
+    let script = match &opts.input {
+        Some(path) => {
+            let script = fs::read_to_string(path).expect("cannot read input script");
+            InputScript::parse(&script).unwrap_or_else(|e| panic!("invalid input script: {}", e))
+        }
+        None => InputScript::default(),
+    };
+
+    let pipeline = Pipeline {
+        upscaler: opts.upscaler,
+        scale: opts.scale,
+        filters: opts.filters.clone(),
+    };
+
+    let create = |path: &PathBuf| BufWriter::new(File::create(path).expect("cannot create file"));
+
+    let mut animations = vec![];
+    if let Some(path) = &opts.gif {
+        animations.push(AnimationRecorder::new(create(path), AnimationFormat::Gif));
+    }
+    if let Some(path) = &opts.apng {
+        animations.push(AnimationRecorder::new(create(path), AnimationFormat::Apng));
+    }
+
+    let mut y4m = opts.y4m.as_ref().map(|path| Y4mWriter::new(create(path)));
+    let mut wav = opts
+        .wav
+        .as_ref()
+        .map(|path| WavRecorder::new(create(path), Synth::default()).expect("cannot write wav"));
+
+    let mut runner = Runner::new(&prog, opts.seed, cycles_per_frame).expect("rom too big");
+    runner.chip8_mut().set_quirks(quirks);
+    let mut display = Display::new(opts.anti_flicker);
+    let mut img = None;
+
+    for _ in 0..opts.frames {
+        script.apply(runner.frame(), runner.chip8_mut());
+        runner.run_frame();
+
+        let dirty = runner.chip8_mut().take_dirty();
+        display.update(runner.chip8().framebuffer(), dirty);
+
+        let beeping = runner.beeped();
+        let frame = render(&pipeline, &display, &palette, beeping);
+
+        for animation in &mut animations {
+            animation.add_frame(&frame).expect("cannot write animation");
+        }
+        if let Some(y4m) = &mut y4m {
+            y4m.add_frame(&frame).expect("cannot write video");
+        }
+        if let Some(wav) = &mut wav {
+            wav.add_frame(beeping).expect("cannot write wav");
+        }
+
+        img = Some(frame);
+    }
+
+    for animation in animations {
+        println!("{} distinct frames", animation.frames());
+        animation.finish().expect("cannot write animation");
+    }
+    if let Some(y4m) = y4m {
+        y4m.finish().expect("cannot write video");
+    }
+    if let Some(wav) = wav {
+        wav.finish().expect("cannot write wav");
+    }
+
+    if let (Some(path), Some(img)) = (&opts.png, img) {
+        img.write_png(create(path)).expect("cannot write png");
+    }
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, rom: &Path, format: AnimationFormat, pipeline: Pipeline) -> Recorder {
+        let name = rom
+            .file_stem()
+            .map_or_else(|| "chip8".to_string(), |n| n.to_string_lossy().into_owned());
+
+        Recorder {
+            dir,
+            name,
+            format,
+            pipeline,
+            animation: None,
+            dump: None,
+        }
+    }
+
+    /// Whether the screen has to be rendered for the recordings at every
+    /// frame.
+    pub fn is_recording(&self) -> bool {
+        self.animation.is_some() || self.dump.is_some()
+    }
+
+    /// Save a PNG of the screen, returning its path.
+    pub fn screenshot(
+        &self,
+        display: &Display,
+        palette: &Palette,
+        beeping: bool,
+    ) -> io::Result<PathBuf> {
+        let path = self.path("png");
+        let img = render(&self.pipeline, display, palette, beeping);
+
+        img.write_png(self.create(&path)?)?;
+        Ok(path)
+    }
+
+    /// Start or finish the animation, returning its path once it's finished.
+    pub fn toggle_animation(&mut self) -> io::Result<Option<PathBuf>> {
+        match self.animation.take() {
+            Some((path, animation)) => {
+                animation.finish()?;
+                Ok(Some(path))
+            }
+            None => {
+                let path = self.path(self.format.extension());
+                let animation = AnimationRecorder::new(self.create(&path)?, self.format);
+                self.animation = Some((path, animation));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Start or finish the video dump, returning the path of the video once
+    /// it's finished, the sound is next to it.
+    pub fn toggle_dump(&mut self) -> io::Result<Option<PathBuf>> {
+        match self.dump.take() {
+            Some((path, dump)) => {
+                dump.finish()?;
+                Ok(Some(path))
+            }
+            None => {
+                let path = self.path("y4m");
+                let dump = AvDump::new(
+                    self.create(&path)?,
+                    self.create(&path.with_extension("wav"))?,
+                )?;
+                self.dump = Some((path, dump));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Record the frame shown for the next sixtieth of second.
+    pub fn add_frame(
+        &mut self,
+        display: &Display,
+        palette: &Palette,
+        beeping: bool,
+    ) -> io::Result<()> {
+        if !self.is_recording() {
+            return Ok(());
+        }
+
+        let img = render(&self.pipeline, display, palette, beeping);
+
+        if let Some((_, animation)) = &mut self.animation {
+            animation.add_frame(&img)?;
+        }
+        if let Some((_, dump)) = &mut self.dump {
+            dump.add_frame(&img, beeping)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop the recordings without finishing them, e.g. once they cannot be
+    /// written anymore.
+    pub fn cancel(&mut self) {
+        self.animation = None;
+        self.dump = None;
+    }
+
+    /// Finish the recordings still going on, the errors are only printed
+    /// since the game is over by then.
+    pub fn stop(&mut self) {
+        if self.animation.is_some() {
+            if let Err(e) = self.toggle_animation() {
+                eprintln!("cannot write recording: {}", e);
+            }
+        }
+        if self.dump.is_some() {
+            if let Err(e) = self.toggle_dump() {
+                eprintln!("cannot write video: {}", e);
+            }
+        }
+    }
+
+    fn path(&self, extension: &str) -> PathBuf {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+
+        self.dir
+            .join(format!("{}-{}.{}", self.name, now, extension))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<CaptureFile> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(BufWriter::new(File::create(path)?))
+    }
+}
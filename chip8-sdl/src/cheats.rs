@@ -0,0 +1,211 @@
+use chip8::cheats::{Cheat, CheatKind, MemorySearch, SearchFilter};
+use chip8::Chip8;
+
+use rand::Rng;
+
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::text::{draw_text, GLYPH_HEIGHT};
+
+// candidates are listed only when they're few enough to be useful
+const MAX_LISTED_CANDIDATES: usize = 8;
+
+/// Menu to toggle the cheats and search the RAM for new ones, the emulation
+/// is paused while it's open.
+pub struct CheatMenu {
+    open: bool,
+    selected: usize,
+    search: Option<MemorySearch>,
+    /// Hex digits of the value searched for, while it's being typed.
+    value: Option<String>,
+    /// Outcome of the last save.
+    message: Option<String>,
+}
+
+impl CheatMenu {
+    pub fn new() -> Self {
+        CheatMenu {
+            open: false,
+            selected: 0,
+            search: None,
+            value: None,
+            message: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// Show `message` at the bottom of the menu until the next key.
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    /// Handle a key pressed while the menu is open, returns true if the
+    /// cheats should be saved.
+    pub fn keydown<R: Rng>(
+        &mut self,
+        kc: Keycode,
+        cheats: &mut Vec<Cheat>,
+        chip8: &Chip8<R>,
+    ) -> bool {
+        self.message = None;
+
+        if let Some(value) = &mut self.value {
+            match kc {
+                Keycode::Return | Keycode::KpEnter => {
+                    let filter = u8::from_str_radix(value, 16).ok().map(SearchFilter::Equal);
+                    self.value = None;
+
+                    if let (Some(filter), Some(search)) = (filter, &mut self.search) {
+                        search.filter(chip8.ram(), filter);
+                    }
+                }
+                Keycode::Backspace if value.is_empty() => self.value = None,
+                Keycode::Backspace => {
+                    value.pop();
+                }
+                _ => {
+                    if let Some(digit) = hex_digit(kc).filter(|_| value.len() < 2) {
+                        value.push(digit);
+                    }
+                }
+            }
+
+            return false;
+        }
+
+        let filter = match kc {
+            Keycode::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                None
+            }
+            Keycode::Down => {
+                self.selected = (self.selected + 1).min(cheats.len().saturating_sub(1));
+                None
+            }
+            Keycode::Return | Keycode::Space => {
+                if let Some(cheat) = cheats.get_mut(self.selected) {
+                    let enabled = cheat.enabled();
+                    cheat.set_enabled(!enabled);
+                }
+                None
+            }
+            Keycode::N => {
+                self.search = Some(MemorySearch::new(chip8.ram()));
+                None
+            }
+            Keycode::U => Some(SearchFilter::Unchanged),
+            Keycode::C => Some(SearchFilter::Changed),
+            Keycode::I => Some(SearchFilter::Increased),
+            Keycode::D => Some(SearchFilter::Decreased),
+            Keycode::Equals if self.search.is_some() => {
+                self.value = Some(String::new());
+                None
+            }
+            Keycode::F => {
+                let candidates = self.search.as_ref().map_or(&[][..], |s| s.candidates());
+
+                if candidates.len() <= MAX_LISTED_CANDIDATES {
+                    for &addr in candidates {
+                        let value = chip8.ram()[addr];
+                        let name = format!("Freeze {:#05X}", addr);
+
+                        let mut cheat = Cheat::new(name, CheatKind::Freeze, vec![(addr, value)]);
+                        cheat.set_enabled(true);
+                        cheats.push(cheat);
+                    }
+                }
+                None
+            }
+            Keycode::W => return true,
+            _ => None,
+        };
+
+        if let (Some(filter), Some(search)) = (filter, &mut self.search) {
+            search.filter(chip8.ram(), filter);
+        }
+
+        false
+    }
+
+    pub fn draw<R: Rng>(&self, canvas: &mut Canvas<Window>, cheats: &[Cheat], chip8: &Chip8<R>) {
+        let (w, h) = canvas.output_size().unwrap();
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xD0));
+        canvas.fill_rect(Rect::new(0, 0, w, h)).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        let mut lines = vec!["CHEATS (F2 to close)".to_string(), String::new()];
+
+        if cheats.is_empty() {
+            lines.push("no cheats for this rom".to_string());
+        }
+
+        for (i, cheat) in cheats.iter().enumerate() {
+            let cursor = if i == self.selected { '>' } else { ' ' };
+            let enabled = if cheat.enabled() { 'x' } else { ' ' };
+
+            lines.push(format!("{} [{}] {}", cursor, enabled, cheat.name));
+        }
+
+        lines.push(String::new());
+        match &self.search {
+            None => lines.push("no search in progress".to_string()),
+            Some(search) => {
+                let candidates = search.candidates();
+                lines.push(format!("search: {} candidates", candidates.len()));
+
+                if candidates.len() <= MAX_LISTED_CANDIDATES {
+                    for addr in candidates {
+                        lines.push(format!("  {:#05X} = {:02X}", addr, chip8.ram()[*addr]));
+                    }
+                }
+            }
+        }
+
+        lines.push(String::new());
+        if let Some(value) = &self.value {
+            lines.push(format!("equal to: {}_", value));
+            lines.push("type a hex value, enter to search".to_string());
+        } else {
+            lines.push("up/down select   enter toggle   w save".to_string());
+            lines.push("n new search   c changed   u unchanged".to_string());
+            lines.push("i increased   d decreased   = equal to".to_string());
+            lines.push("f freeze found".to_string());
+        }
+
+        if let Some(message) = &self.message {
+            lines.push(String::new());
+            lines.push(message.clone());
+        }
+
+        let scale = 2;
+        for (i, line) in lines.iter().enumerate() {
+            let y = 8 + i as i32 * (GLYPH_HEIGHT + 3) * scale as i32;
+            draw_text(canvas, 8, y, scale, Color::RGB(0xFF, 0xFF, 0xFF), line);
+        }
+    }
+}
+
+/// The hex digit of the key, from the main keys or the keypad.
+fn hex_digit(kc: Keycode) -> Option<char> {
+    let name = kc.name();
+    let name = name.strip_prefix("Keypad ").unwrap_or(&name);
+
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_hexdigit() => Some(c.to_ascii_uppercase()),
+        _ => None,
+    }
+}
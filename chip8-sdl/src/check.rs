@@ -0,0 +1,234 @@
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip8::db::RomDatabase;
+use chip8::detect;
+use chip8::headless::{InputScript, Outcome, Runner, Supervisor};
+
+use structopt::StructOpt;
+
+use crate::utils;
+
+#[derive(Debug, StructOpt)]
+pub struct Check {
+    /// Directory with the roms to check
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Number of frames to run every rom for
+    #[structopt(long = "frames", default_value = "600")]
+    frames: usize,
+
+    /// Seed of the random number generator
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Script of the keys to press while running every rom
+    #[structopt(long = "input", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Frequency of the emulator for the roms that aren't in the database
+    #[structopt(short = "f", long = "frequency", default_value = "500")]
+    freq: usize,
+
+    /// Number of roms to check at the same time, defaults to the number of
+    /// cpus
+    #[structopt(short = "j", long = "jobs")]
+    jobs: Option<usize>,
+
+    /// Additional rom database in JSON or TOML format
+    #[structopt(long = "rom-db", parse(from_os_str))]
+    rom_db: Option<PathBuf>,
+
+    /// Write a JUnit XML report to this file
+    #[structopt(long = "junit", parse(from_os_str))]
+    junit: Option<PathBuf>,
+}
+
+struct Checked {
+    name: String,
+    /// `Err` if the rom couldn't even be loaded.
+    result: Result<Outcome, String>,
+    elapsed: Duration,
+}
+
+pub fn run(opts: Check) {
+    let mut roms = fs::read_dir(&opts.dir)
+        .expect("cannot read rom directory")
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect::<Vec<_>>();
+    roms.sort();
+
+    let script = match &opts.input {
+        Some(path) => {
+            let script = fs::read_to_string(path).expect("cannot read input script");
+            InputScript::parse(&script).unwrap_or_else(|e| panic!("invalid input script: {}", e))
+        }
+        None => InputScript::default(),
+    };
+
+    let db = utils::load_rom_db(opts.rom_db.as_deref());
+
+    let jobs = opts
+        .jobs
+        .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .max(1);
+
+    // crashes are part of the report, don't print them as they happen
+    panic::set_hook(Box::new(|_| {}));
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![]);
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let rom = match roms.get(i) {
+                    Some(rom) => rom,
+                    None => break,
+                };
+
+                let checked = check(rom, &db, &script, &opts);
+                results.lock().unwrap().push((i, checked));
+            });
+        }
+    });
+
+    let _ = panic::take_hook();
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    let results = results.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+
+    let name_width = results.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for c in &results {
+        let (status, details) = match &c.result {
+            Ok(Outcome::Ok) => ("OK", String::new()),
+            Ok(Outcome::Crashed { pc, msg }) => ("PANICKED", format!("{} at {:#05X}", msg, pc)),
+            Ok(Outcome::Stalled { pc }) => ("STALLED", format!("stuck at {:#05X}", pc)),
+            Ok(Outcome::Blank) => ("BLANK", String::new()),
+            Err(e) => ("ERROR", e.clone()),
+        };
+
+        println!("{:<w$}  {:<8}  {}", c.name, status, details, w = name_width);
+    }
+
+    let ok = results
+        .iter()
+        .filter(|c| c.result == Ok(Outcome::Ok))
+        .count();
+    println!("\n{} of {} roms ok", ok, results.len());
+
+    if let Some(path) = opts.junit {
+        fs::write(path, junit_report(&results)).expect("cannot write junit report");
+    }
+}
+
+fn check(rom: &Path, db: &RomDatabase, script: &InputScript, opts: &Check) -> Checked {
+    let name = rom
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+    let start = Instant::now();
+
+    let result = fs::read(rom)
+        .map_err(|e| format!("cannot read rom: {}", e))
+        .and_then(|prog| {
+            let (quirks, cycles_per_frame) = match db.lookup(&prog) {
+                Some(info) => (
+                    info.quirks,
+                    info.instructions_per_frame.unwrap_or(opts.freq / 60),
+                ),
+                None => (detect::detect(&prog).quirks, opts.freq / 60),
+            };
+
+            let mut runner = Runner::new(&prog, opts.seed, cycles_per_frame)
+                .ok_or_else(|| "rom doesn't fit in memory".to_string())?;
+            runner.chip8_mut().set_quirks(quirks);
+
+            let mut supervisor = Supervisor::new(runner);
+            for _ in 0..opts.frames {
+                if !supervisor.is_running() {
+                    break;
+                }
+
+                supervisor.run_frame(script);
+            }
+
+            Ok(supervisor.outcome())
+        });
+
+    Checked {
+        name,
+        result,
+        elapsed: start.elapsed(),
+    }
+}
+
+fn junit_report(results: &[Checked]) -> String {
+    let failures = results
+        .iter()
+        .filter(|c| matches!(c.result, Ok(Outcome::Stalled { .. }) | Ok(Outcome::Blank)))
+        .count();
+    let errors = results
+        .iter()
+        .filter(|c| matches!(c.result, Ok(Outcome::Crashed { .. }) | Err(_)))
+        .count();
+    let time = results.iter().map(|c| c.elapsed.as_secs_f64()).sum::<f64>();
+
+    let mut out = String::new();
+    out += "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+    out += &format!(
+        "<testsuite name=\"chip8\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        failures,
+        errors,
+        time
+    );
+
+    for c in results {
+        out += &format!(
+            "  <testcase classname=\"roms\" name=\"{}\" time=\"{:.3}\"",
+            escape_xml(&c.name),
+            c.elapsed.as_secs_f64()
+        );
+
+        let (tag, msg) = match &c.result {
+            Ok(Outcome::Ok) => {
+                out += "/>\n";
+                continue;
+            }
+            Ok(Outcome::Crashed { .. }) | Err(_) => ("error", failure_message(&c.result)),
+            Ok(_) => ("failure", failure_message(&c.result)),
+        };
+
+        out += &format!(
+            ">\n    <{} message=\"{}\"/>\n  </testcase>\n",
+            tag,
+            escape_xml(&msg)
+        );
+    }
+
+    out += "</testsuite>\n";
+    out
+}
+
+fn failure_message(result: &Result<Outcome, String>) -> String {
+    match result {
+        Ok(outcome) => outcome.to_string(),
+        Err(e) => e.clone(),
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,535 @@
+//! Settings read from a TOML file, by default `chip8/config.toml` in the XDG
+//! config directory.
+//!
+//! The top level settings apply to every rom, the tables under `roms`
+//! override them for the roms with the given SHA-1 or file name:
+//!
+//! ```toml
+//! frequency = 700
+//! fast_forward = "uncapped"
+//! palette = "amber"
+//! filters = ["scanlines", "bloom:0.8"]
+//!
+//! keymap = "azerty"
+//!
+//! [keys]
+//! 5 = ["KeyW", "ArrowUp"]
+//!
+//! [audio]
+//! waveform = "triangle"
+//! volume = 0.1
+//!
+//! [gamepad]
+//! dead_zone = 0.4
+//! pads = [{ a = "5" }, { a = "6" }]
+//!
+//! [hotkeys]
+//! screenshot = "O"
+//!
+//! [roms.PONG]
+//! frequency = 400
+//! quirks = { vf_reset = true }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use sdl2::keyboard::Keycode;
+
+use chip8::audio::Waveform;
+use chip8::capture::AnimationFormat;
+use chip8::display::AntiFlicker;
+use chip8::filters::{Filter, Upscaler};
+use chip8::input::{self, Keymap, Padmap};
+use chip8::Quirks;
+
+use crate::control::Speed;
+use crate::screen::{self, Scaling};
+
+/// Things the frontend does at the press of a key rather than feeding it to
+/// the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Pause,
+    FrameAdvance,
+    SoftReset,
+    HardReset,
+    /// Fast forward while held.
+    FastForward,
+    ToggleFastForward,
+    SlowMotion,
+    Remap,
+    Fullscreen,
+    Cheats,
+    AntiFlicker,
+    Palette,
+    SaveState,
+    LoadState,
+    Record,
+    Dump,
+    Screenshot,
+    Debugger,
+    /// Leave the game for the rom browser.
+    Browser,
+}
+
+const DEFAULT_HOTKEYS: [(Action, Keycode); 20] = [
+    (Action::Quit, Keycode::Escape),
+    (Action::Pause, Keycode::P),
+    (Action::FrameAdvance, Keycode::N),
+    (Action::SoftReset, Keycode::F6),
+    (Action::HardReset, Keycode::F8),
+    (Action::FastForward, Keycode::Space),
+    (Action::ToggleFastForward, Keycode::L),
+    (Action::SlowMotion, Keycode::M),
+    (Action::Remap, Keycode::F1),
+    (Action::Fullscreen, Keycode::F11),
+    (Action::Cheats, Keycode::F2),
+    (Action::AntiFlicker, Keycode::F3),
+    (Action::Palette, Keycode::F4),
+    (Action::SaveState, Keycode::F5),
+    (Action::LoadState, Keycode::F7),
+    (Action::Record, Keycode::F9),
+    (Action::Dump, Keycode::F10),
+    (Action::Screenshot, Keycode::F12),
+    (Action::Debugger, Keycode::Backquote),
+    (Action::Browser, Keycode::Backspace),
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub settings: Settings,
+    /// Settings of single roms keyed by SHA-1 or file name.
+    pub roms: BTreeMap<String, Settings>,
+}
+
+/// Every setting is optional, what's missing falls back to the command line
+/// defaults or to the rom database.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub frequency: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    pub fast_forward: Option<Speed>,
+    #[serde(deserialize_with = "parsed")]
+    pub slow_motion: Option<Speed>,
+    /// Whether the status line is shown while paused, fast forwarding or in
+    /// slow motion.
+    pub status_line: Option<bool>,
+    pub quirks: Option<Quirks>,
+    /// Whether the RPL flags of `FX75` survive the soft resets.
+    pub persistent_flags: Option<bool>,
+    pub window_width: Option<u32>,
+    pub window_height: Option<u32>,
+    #[serde(deserialize_with = "parsed")]
+    pub scaling: Option<Scaling>,
+    #[serde(deserialize_with = "parsed")]
+    pub anti_flicker: Option<AntiFlicker>,
+    #[serde(deserialize_with = "parsed")]
+    pub upscaler: Option<Upscaler>,
+    #[serde(deserialize_with = "filters")]
+    pub filters: Option<Vec<Filter>>,
+    pub filter_scale: Option<usize>,
+    pub palette: Option<String>,
+    pub palette_file: Option<PathBuf>,
+    #[serde(deserialize_with = "color")]
+    pub bg: Option<[u8; 3]>,
+    #[serde(deserialize_with = "color")]
+    pub fg: Option<[u8; 3]>,
+    pub audio: AudioSettings,
+    pub gamepad: GamepadSettings,
+    /// Preset the keys start from.
+    #[serde(deserialize_with = "parsed")]
+    pub keymap: Option<Keymap>,
+    /// Host keys of the hex keys, replacing the ones of the preset.
+    #[serde(deserialize_with = "keys")]
+    pub keys: BTreeMap<u8, Vec<String>>,
+    #[serde(deserialize_with = "hotkeys")]
+    pub hotkeys: BTreeMap<Action, Keycode>,
+    pub save_state_dir: Option<PathBuf>,
+    pub capture_dir: Option<PathBuf>,
+    pub capture_scale: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    pub record_format: Option<AnimationFormat>,
+    /// Command building the roms from source files, see `--assembler`.
+    pub assembler: Option<String>,
+    /// Directories listed by the rom browser.
+    pub rom_dirs: Option<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    /// Frequency of the tone in Hz.
+    pub tone: Option<f32>,
+    pub volume: Option<f32>,
+    #[serde(deserialize_with = "parsed")]
+    pub waveform: Option<Waveform>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadSettings {
+    /// Fraction of the range of the sticks ignored around the center.
+    pub dead_zone: Option<f32>,
+    /// Hex keys of the inputs of the gamepad of every player, on top of the
+    /// ones of the rom database.
+    #[serde(deserialize_with = "pads")]
+    pub pads: Vec<BTreeMap<String, u8>>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    path: PathBuf,
+    msg: String,
+}
+
+impl Config {
+    /// Load the configuration at `path` or, when missing, the default one if
+    /// it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let err = |msg: String| ConfigError {
+            path: path.clone(),
+            msg,
+        };
+
+        let data = fs::read_to_string(&path).map_err(|e| err(e.to_string()))?;
+        Config::parse(&data).map_err(err)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut table: toml::value::Table = toml::from_str(s).map_err(|e| e.to_string())?;
+
+        let roms = match table.remove("roms") {
+            Some(roms) => roms
+                .try_into::<BTreeMap<String, Settings>>()
+                .map_err(|e| format!("roms: {}", e))?,
+            None => BTreeMap::new(),
+        };
+        // the hashes are looked up in lowercase, the file names as they are
+        let roms = roms
+            .into_iter()
+            .map(|(key, rom)| {
+                if is_sha1(&key) {
+                    (key.to_lowercase(), rom)
+                } else {
+                    (key, rom)
+                }
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let settings: Settings = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())?;
+
+        settings.check_hotkeys()?;
+        for (key, rom) in &roms {
+            let mut merged = settings.clone();
+            merged.merge(rom.clone());
+            merged
+                .check_hotkeys()
+                .map_err(|e| format!("roms.{}: {}", key, e))?;
+        }
+
+        Ok(Config { settings, roms })
+    }
+
+    /// The settings of the rom at `path` with the given SHA-1: the top level
+    /// ones overridden by the ones for its file name and then by the ones
+    /// for its hash.
+    pub fn settings_for(&self, path: &Path, sha1: &str) -> Settings {
+        let mut settings = self.settings.clone();
+
+        let name = path.file_name().map(|n| n.to_string_lossy());
+        if let Some(rom) = name.and_then(|n| self.roms.get(&*n)) {
+            settings.merge(rom.clone());
+        }
+        if let Some(rom) = self.roms.get(&sha1.to_lowercase()) {
+            settings.merge(rom.clone());
+        }
+
+        settings
+    }
+}
+
+impl Action {
+    /// The name of the action in the configuration file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Pause => "pause",
+            Action::FrameAdvance => "frame_advance",
+            Action::SoftReset => "soft_reset",
+            Action::HardReset => "hard_reset",
+            Action::FastForward => "fast_forward",
+            Action::ToggleFastForward => "toggle_fast_forward",
+            Action::SlowMotion => "slow_motion",
+            Action::Remap => "remap",
+            Action::Fullscreen => "fullscreen",
+            Action::Cheats => "cheats",
+            Action::AntiFlicker => "anti_flicker",
+            Action::Palette => "palette",
+            Action::SaveState => "save_state",
+            Action::LoadState => "load_state",
+            Action::Record => "record",
+            Action::Dump => "dump",
+            Action::Screenshot => "screenshot",
+            Action::Debugger => "debugger",
+            Action::Browser => "browser",
+        }
+    }
+
+    /// Whether the action controls the emulation, the keys of these actions
+    /// go to the menus while they're open.
+    pub fn is_control(self) -> bool {
+        matches!(
+            self,
+            Action::Pause
+                | Action::FrameAdvance
+                | Action::SoftReset
+                | Action::HardReset
+                | Action::FastForward
+                | Action::ToggleFastForward
+                | Action::SlowMotion
+                | Action::Browser
+        )
+    }
+}
+
+impl Settings {
+    /// Override these settings with the ones set in `other`.
+    pub fn merge(&mut self, other: Settings) {
+        macro_rules! merge {
+            ($($field:ident).+) => {
+                if other.$($field).+.is_some() {
+                    self.$($field).+ = other.$($field).+;
+                }
+            };
+        }
+
+        merge!(frequency);
+        merge!(fast_forward);
+        merge!(slow_motion);
+        merge!(status_line);
+        merge!(quirks);
+        merge!(persistent_flags);
+        merge!(window_width);
+        merge!(window_height);
+        merge!(scaling);
+        merge!(anti_flicker);
+        merge!(upscaler);
+        merge!(filters);
+        merge!(filter_scale);
+        merge!(palette);
+        merge!(palette_file);
+        merge!(bg);
+        merge!(fg);
+        merge!(audio.tone);
+        merge!(audio.volume);
+        merge!(audio.waveform);
+        merge!(gamepad.dead_zone);
+        merge!(keymap);
+        merge!(save_state_dir);
+        merge!(capture_dir);
+        merge!(capture_scale);
+        merge!(record_format);
+        merge!(assembler);
+        merge!(rom_dirs);
+
+        for (i, pad) in other.gamepad.pads.into_iter().enumerate() {
+            match self.gamepad.pads.get_mut(i) {
+                Some(bindings) => bindings.extend(pad),
+                None => self.gamepad.pads.push(pad),
+            }
+        }
+        self.keys.extend(other.keys);
+        self.hotkeys.extend(other.hotkeys);
+    }
+
+    /// The preset with the keys of the settings on top.
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = self.keymap.clone().unwrap_or_default();
+        keymap.apply(&self.keys);
+        keymap
+    }
+
+    /// The gamepads of every player: the given ones, usually from the rom
+    /// database, with the bindings of the settings on top.
+    pub fn padmaps(&self, mut padmaps: Vec<Padmap>) -> Vec<Padmap> {
+        if padmaps.is_empty() {
+            padmaps.push(Padmap::default());
+        }
+
+        for (i, pad) in self.gamepad.pads.iter().enumerate() {
+            if i >= padmaps.len() {
+                padmaps.push(Padmap::default());
+            }
+            padmaps[i].apply(pad);
+        }
+
+        padmaps
+    }
+
+    /// The action of every hotkey, the defaults of the actions not bound in
+    /// the settings included.
+    pub fn hotkeys(&self) -> HashMap<Keycode, Action> {
+        self.hotkey_bindings()
+            .map(|(action, key)| (key, action))
+            .collect()
+    }
+
+    /// Make sure no two actions are bound to the same key, one of them would
+    /// be unreachable otherwise.
+    fn check_hotkeys(&self) -> Result<(), String> {
+        let mut bound = HashMap::new();
+        for (action, key) in self.hotkey_bindings() {
+            if let Some(other) = bound.insert(key, action) {
+                return Err(format!(
+                    "hotkeys: {} and {} are both bound to {}",
+                    other.name(),
+                    action.name(),
+                    key.name()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn hotkey_bindings(&self) -> impl Iterator<Item = (Action, Keycode)> + '_ {
+        DEFAULT_HOTKEYS
+            .iter()
+            .map(move |(action, key)| (*action, *self.hotkeys.get(action).unwrap_or(key)))
+    }
+}
+
+/// `chip8/config.toml` in `$XDG_CONFIG_HOME` or in `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|d| d.join("config.toml"))
+}
+
+/// The `chip8` directory in the XDG base directory of the given variable,
+/// falling back to `fallback` in the home directory.
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = env::var_os(var)
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(fallback)))?;
+
+    Some(base.join("chip8"))
+}
+
+fn parsed<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let s = String::deserialize(d)?;
+    s.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn filters<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<Filter>>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|f| f.parse().map_err(de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn color<'de, D: Deserializer<'de>>(d: D) -> Result<Option<[u8; 3]>, D::Error> {
+    let s = String::deserialize(d)?;
+    screen::parse_color(&s).map(Some).map_err(de::Error::custom)
+}
+
+/// Either a single key or a list of them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyNames {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn keys<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeMap<u8, Vec<String>>, D::Error> {
+    BTreeMap::<String, KeyNames>::deserialize(d)?
+        .into_iter()
+        .map(|(hex, names)| {
+            let hex_key = input::parse_hex_key(&hex)
+                .ok_or_else(|| de::Error::custom(format!("{} is not an hex key", hex)))?;
+
+            let names = match names {
+                KeyNames::One(name) => vec![name],
+                KeyNames::Many(names) => names,
+            };
+            if let Some(name) = names.iter().find(|n| !input::is_code(n)) {
+                return Err(de::Error::custom(format!("unknown key {}", name)));
+            }
+
+            Ok((hex_key, names))
+        })
+        .collect()
+}
+
+fn pads<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<BTreeMap<String, u8>>, D::Error> {
+    Vec::<BTreeMap<String, String>>::deserialize(d)?
+        .into_iter()
+        .map(|pad| {
+            pad.into_iter()
+                .map(|(pad_input, hex)| {
+                    if !input::is_pad_input(&pad_input) {
+                        return Err(de::Error::custom(format!(
+                            "unknown gamepad input {}",
+                            pad_input
+                        )));
+                    }
+                    let hex_key = input::parse_hex_key(&hex)
+                        .ok_or_else(|| de::Error::custom(format!("{} is not an hex key", hex)))?;
+
+                    Ok((pad_input, hex_key))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn hotkeys<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeMap<Action, Keycode>, D::Error> {
+    BTreeMap::<Action, String>::deserialize(d)?
+        .into_iter()
+        .map(|(action, name)| Ok((action, keycode(&name).map_err(de::Error::custom)?)))
+        .collect()
+}
+
+/// Whether `key` of the `roms` table is a SHA-1 rather than a file name.
+fn is_sha1(key: &str) -> bool {
+    key.len() == 40 && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The key with the given SDL name, like `A`, `Up` or `Keypad 5`.
+fn keycode(name: &str) -> Result<Keycode, String> {
+    Keycode::from_name(name).ok_or_else(|| format!("unknown key {}", name))
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.msg)
+    }
+}
+
+impl std::error::Error for ConfigError {}
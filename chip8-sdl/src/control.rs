@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::text::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// How long the messages of the status line stay on screen.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// How fast the emulation runs compared to real time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// As fast as the host allows.
+    Uncapped,
+    Times(f32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSpeedError(String);
+
+/// Pause, frame advance, fast forward and slow motion, driven by the
+/// hotkeys.
+pub struct Control {
+    paused: bool,
+    /// Whether a single frame has to be run even though it's paused.
+    advance: bool,
+    fast_forward_held: bool,
+    fast_forward_toggled: bool,
+    slow_motion: bool,
+    fast_forward_speed: Speed,
+    slow_motion_speed: Speed,
+    /// Frames of emulation owed, it has a fractional part in slow motion.
+    due: f32,
+    /// Message shown on the status line along with when it was set.
+    message: Option<(String, Instant)>,
+    show_status: bool,
+}
+
+impl Control {
+    pub fn new(fast_forward_speed: Speed, slow_motion_speed: Speed, show_status: bool) -> Self {
+        Control {
+            paused: false,
+            advance: false,
+            fast_forward_held: false,
+            fast_forward_toggled: false,
+            slow_motion: false,
+            fast_forward_speed,
+            slow_motion_speed,
+            due: 0.0,
+            message: None,
+            show_status,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Run the next frame and pause again.
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance = true;
+    }
+
+    pub fn hold_fast_forward(&mut self, held: bool) {
+        self.fast_forward_held = held;
+    }
+
+    pub fn toggle_fast_forward(&mut self) {
+        self.fast_forward_toggled = !self.fast_forward_toggled;
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.fast_forward_held || self.fast_forward_toggled {
+            self.fast_forward_speed
+        } else if self.slow_motion {
+            self.slow_motion_speed
+        } else {
+            Speed::Times(1.0)
+        }
+    }
+
+    /// The frames of emulation to run before showing the next frame, none
+    /// when the speed is uncapped and they have to be run for as long as a
+    /// frame lasts.
+    pub fn frames(&mut self) -> Option<usize> {
+        if self.paused {
+            self.due = 0.0;
+            return Some(usize::from(std::mem::take(&mut self.advance)));
+        }
+
+        match self.speed() {
+            Speed::Uncapped => None,
+            Speed::Times(times) => {
+                self.due += times;
+                let frames = self.due.floor();
+                self.due -= frames;
+                Some(frames as usize)
+            }
+        }
+    }
+
+    /// Show `message` on the status line for a while.
+    pub fn flash(&mut self, message: impl Into<String>) {
+        self.message = Some((message.into(), Instant::now()));
+    }
+
+    /// What the status line shows, if anything.
+    pub fn status(&self) -> Option<String> {
+        if !self.show_status {
+            return None;
+        }
+
+        let mut parts = vec![];
+
+        if self.paused {
+            parts.push("paused".to_string());
+        } else if self.fast_forward_held || self.fast_forward_toggled {
+            parts.push(format!("fast forward {}", self.fast_forward_speed));
+        } else if self.slow_motion {
+            parts.push(format!("slow motion {}", self.slow_motion_speed));
+        }
+
+        if let Some((message, at)) = &self.message {
+            if at.elapsed() < MESSAGE_DURATION {
+                parts.push(message.clone());
+            }
+        }
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" - "))
+        }
+    }
+
+    /// Whether the status line changes by itself, so the window has to be
+    /// presented again.
+    pub fn status_changing(&self) -> bool {
+        self.message
+            .as_ref()
+            .is_some_and(|(_, at)| at.elapsed() < MESSAGE_DURATION + Duration::from_millis(100))
+    }
+
+    /// Draw the status line at the bottom of the window.
+    pub fn draw(&self, canvas: &mut Canvas<Window>) {
+        let status = match self.status() {
+            Some(status) => status,
+            None => return,
+        };
+
+        let (_, h) = canvas.output_size().unwrap();
+        let scale = 2;
+        let height = (GLYPH_HEIGHT + 4) * scale;
+        let width = (GLYPH_WIDTH + 1) * scale * status.len() as i32 + 4 * scale;
+        let y = h as i32 - height;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xA0));
+        canvas
+            .fill_rect(Rect::new(0, y, width as u32, height as u32))
+            .unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        draw_text(
+            canvas,
+            2 * scale,
+            y + 2 * scale,
+            scale as u32,
+            Color::RGB(0xFF, 0xFF, 0xFF),
+            &status,
+        );
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Uncapped => write!(f, "uncapped"),
+            Speed::Times(times) => write!(f, "{}x", times),
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = ParseSpeedError;
+
+    /// Parse `uncapped` or a multiplier of the normal speed like `4` or
+    /// `0.5x`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "uncapped" {
+            return Ok(Speed::Uncapped);
+        }
+
+        s.strip_suffix('x')
+            .unwrap_or(s)
+            .parse()
+            .ok()
+            .filter(|times: &f32| *times > 0.0 && times.is_finite())
+            .map(Speed::Times)
+            .ok_or_else(|| ParseSpeedError(s.to_string()))
+    }
+}
+
+impl fmt::Display for ParseSpeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid speed {}, expected uncapped or a multiplier like 4x",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseSpeedError {}
@@ -0,0 +1,376 @@
+use std::collections::BTreeSet;
+
+use chip8::chip8::RAM_SIZE;
+use chip8::disasm::Instr;
+use chip8::input::KEYPAD;
+use chip8::Chip8;
+
+use rand::Rng;
+
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::text::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// Instructions listed before and after the selected one.
+const DISASM_CONTEXT: usize = 10;
+/// Rows of 8 bytes shown of the RAM pointed by I.
+const RAM_ROWS: usize = 6;
+/// Column of the disassembly, the registers are on its left.
+const DISASM_COLUMN: i32 = 34;
+
+const WHITE: Color = Color::RGB(0xFF, 0xFF, 0xFF);
+const GREY: Color = Color::RGB(0x90, 0x90, 0x90);
+const YELLOW: Color = Color::RGB(0xFF, 0xD7, 0x40);
+const RED: Color = Color::RGB(0xFF, 0x50, 0x50);
+
+/// What a click on the overlay does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// Toggle the breakpoint at the address.
+    Instr(usize),
+    Step,
+    Continue,
+    Stop,
+}
+
+/// Where the text goes, in characters.
+struct Grid {
+    scale: i32,
+}
+
+/// Overlay with the registers, the stack, the keys, a disassembly around
+/// the PC and the RAM pointed by I. The program can be stopped, run an
+/// instruction at a time or until a breakpoint.
+pub struct Debugger {
+    open: bool,
+    /// Whether the program is stopped, otherwise it runs until a breakpoint.
+    stopped: bool,
+    breakpoints: BTreeSet<usize>,
+    /// Address of the instruction selected in the disassembly, it follows
+    /// the PC whenever the program stops.
+    cursor: usize,
+    /// The clickable areas of the last overlay drawn.
+    targets: Vec<(Rect, Target)>,
+    cycles_per_frame: usize,
+    /// Instructions stepped through since the timers were last ticked.
+    cycles: usize,
+}
+
+impl Debugger {
+    /// The timers tick every `cycles_per_frame` instructions stepped
+    /// through, like they do every frame while the program runs.
+    pub fn new(cycles_per_frame: usize) -> Self {
+        Debugger {
+            open: false,
+            stopped: false,
+            breakpoints: BTreeSet::new(),
+            cursor: 0,
+            targets: vec![],
+            cycles_per_frame,
+            cycles: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Whether the program is stopped in the debugger, the keys go to the
+    /// debugger rather than to the speed and reset hotkeys then.
+    pub fn is_stopped(&self) -> bool {
+        self.open && self.stopped
+    }
+
+    /// Open the debugger stopping the program. Once open it stops the
+    /// program if it's running and closes the debugger otherwise.
+    pub fn toggle(&mut self, pc: usize) {
+        if self.open && self.stopped {
+            self.open = false;
+        } else {
+            self.open = true;
+            self.stop(pc);
+        }
+    }
+
+    /// Whether the program has to stop before the instruction at `pc`, to be
+    /// called before every cycle.
+    pub fn check(&mut self, pc: usize) -> bool {
+        if self.open && !self.stopped && self.breakpoints.contains(&pc) {
+            self.stop(pc);
+        }
+
+        self.is_stopped()
+    }
+
+    /// Handle a key pressed while the program is stopped: S runs the next
+    /// instruction, C continues up to the next breakpoint, B toggles the
+    /// breakpoint at the selected instruction and Up and Down move the
+    /// selection. Returns whether the key has been used, the others go to
+    /// the program, e.g. to step past a `FX0A`.
+    pub fn keydown<R: Rng>(&mut self, kc: Keycode, chip8: &mut Chip8<R>) -> bool {
+        match kc {
+            Keycode::S => self.step(chip8),
+            Keycode::C => self.resume(chip8),
+            Keycode::B => self.toggle_breakpoint(self.cursor),
+            Keycode::Up => self.cursor = self.cursor.saturating_sub(2),
+            Keycode::Down => self.cursor = (self.cursor + 2).min(RAM_SIZE - 2),
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Handle a click at `(x, y)` in the window: on an instruction it toggles
+    /// its breakpoint, on the buttons it steps, continues or stops the
+    /// program.
+    pub fn click<R: Rng>(&mut self, x: i32, y: i32, chip8: &mut Chip8<R>) {
+        let target = self
+            .targets
+            .iter()
+            .find(|(rect, _)| rect.contains_point((x, y)))
+            .map(|(_, target)| *target);
+
+        match target {
+            Some(Target::Instr(addr)) => {
+                self.cursor = addr;
+                self.toggle_breakpoint(addr);
+            }
+            Some(Target::Step) => self.step(chip8),
+            Some(Target::Continue) => self.resume(chip8),
+            Some(Target::Stop) => self.stop(chip8.pc()),
+            None => {}
+        }
+    }
+
+    fn stop(&mut self, pc: usize) {
+        self.stopped = true;
+        self.cursor = pc;
+        self.cycles = 0;
+    }
+
+    fn step<R: Rng>(&mut self, chip8: &mut Chip8<R>) {
+        self.cycle(chip8);
+        self.cursor = chip8.pc();
+    }
+
+    fn resume<R: Rng>(&mut self, chip8: &mut Chip8<R>) {
+        // run the instruction stopped at first, or its breakpoint would stop
+        // the program right away
+        self.cycle(chip8);
+        self.stopped = false;
+    }
+
+    /// Run an instruction, ticking the timers once a frame worth of them has
+    /// run so that programs waiting on the delay timer can be stepped.
+    fn cycle<R: Rng>(&mut self, chip8: &mut Chip8<R>) {
+        chip8.emulate_cycle();
+
+        self.cycles += 1;
+        if self.cycles >= self.cycles_per_frame {
+            chip8.decrease_timers();
+            self.cycles = 0;
+        }
+    }
+
+    fn toggle_breakpoint(&mut self, addr: usize) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn draw<R: Rng>(&mut self, canvas: &mut Canvas<Window>, chip8: &Chip8<R>) {
+        let (w, h) = canvas.output_size().unwrap();
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xC0));
+        canvas.fill_rect(Rect::new(0, 0, w, h)).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        // the text is doubled when there's room for it
+        let grid = Grid {
+            scale: if w >= 1280 && h >= 640 { 2 } else { 1 },
+        };
+        let mut text = |column: i32, row: usize, color: Color, s: &str| {
+            grid.text(canvas, column, row, color, s)
+        };
+
+        self.targets.clear();
+
+        let state = if self.stopped { "STOPPED" } else { "RUNNING" };
+        text(0, 0, YELLOW, &format!("DEBUGGER - {}", state));
+
+        let mut row = 2;
+        for (line, values) in chip8.registers().chunks(4).enumerate() {
+            let cells = values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("V{:X} {:02X}", line * 4 + i, v))
+                .collect::<Vec<_>>();
+            text(0, row, WHITE, &cells.join("  "));
+            row += 1;
+        }
+
+        let (delay, sound) = chip8.timers();
+        let stack = chip8.stack();
+        text(
+            0,
+            row,
+            WHITE,
+            &format!(
+                "I {:#05X}  PC {:#05X}  SP {}",
+                chip8.i_reg(),
+                chip8.pc(),
+                stack.len()
+            ),
+        );
+        text(
+            0,
+            row + 1,
+            WHITE,
+            &format!("DT {:02X}  ST {:02X}", delay, sound),
+        );
+        row += 3;
+
+        // innermost call first
+        text(0, row, GREY, "STACK");
+        let calls = stack
+            .iter()
+            .rev()
+            .map(|addr| format!("{:#05X}", addr))
+            .collect::<Vec<_>>();
+        for (i, addrs) in calls.chunks(4).enumerate() {
+            text(6, row + i, WHITE, &addrs.join(" "));
+        }
+        row += calls.len().div_ceil(4).max(1) + 1;
+
+        text(0, row, GREY, "KEYS");
+        let keys = chip8.keys();
+        for (i, hex_keys) in KEYPAD.chunks(4).enumerate() {
+            let line = hex_keys
+                .iter()
+                .map(|k| {
+                    if keys[usize::from(*k)] {
+                        format!("{:X}", k)
+                    } else {
+                        ".".to_string()
+                    }
+                })
+                .collect::<Vec<_>>();
+            text(6, row + i, WHITE, &line.join(" "));
+        }
+        row += 5;
+
+        let i_reg = usize::from(chip8.i_reg());
+        text(0, row, GREY, "RAM AT I");
+        for r in 0..RAM_ROWS {
+            let addr = i_reg + r * 8;
+            let bytes = match chip8.ram().get(addr..(addr + 8).min(RAM_SIZE)) {
+                Some(bytes) if !bytes.is_empty() => bytes,
+                _ => break,
+            };
+            let bytes = bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>();
+            text(
+                0,
+                row + 1 + r,
+                WHITE,
+                &format!("{:#05X} {}", addr, bytes.join(" ")),
+            );
+        }
+
+        let first = self
+            .cursor
+            .saturating_sub(DISASM_CONTEXT * 2)
+            .min(RAM_SIZE - (DISASM_CONTEXT * 4 + 2));
+        for i in 0..=DISASM_CONTEXT * 2 {
+            let addr = first + i * 2;
+            let word = u16::from_be_bytes([chip8.ram()[addr], chip8.ram()[addr + 1]]);
+            let instr = match Instr::fetch(chip8.ram(), addr) {
+                Some(instr) => instr.to_string(),
+                None => format!("DW   {:#06X}", word),
+            };
+
+            let breakpoint = if self.breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let pc = if addr == chip8.pc() { '>' } else { ' ' };
+            let color = if addr == chip8.pc() {
+                YELLOW
+            } else if self.breakpoints.contains(&addr) {
+                RED
+            } else if addr == self.cursor {
+                WHITE
+            } else {
+                GREY
+            };
+
+            let line = format!("{}{}{:#05X}  {:04X}  {}", breakpoint, pc, addr, word, instr);
+            let rect = text(DISASM_COLUMN, i, color, &line);
+            self.targets.push((rect, Target::Instr(addr)));
+        }
+
+        let bottom = ((h as i32 - 8) / grid.line_height() - 1).max(0) as usize;
+        let mut column = 0;
+        let buttons: &[(&str, Target)] = if self.stopped {
+            &[("[STEP]", Target::Step), ("[CONTINUE]", Target::Continue)]
+        } else {
+            &[("[STOP]", Target::Stop)]
+        };
+        for (label, target) in buttons {
+            let rect = text(column, bottom, YELLOW, label);
+            self.targets.push((rect, *target));
+            column += label.len() as i32 + 1;
+        }
+
+        let help = if self.stopped {
+            "S step  C continue  B breakpoint  UP/DOWN select"
+        } else {
+            "click an instruction to toggle its breakpoint"
+        };
+        text(column + 1, bottom, GREY, help);
+
+        let cursor = self
+            .targets
+            .iter()
+            .find(|(_, target)| *target == Target::Instr(self.cursor));
+        if let Some((rect, _)) = cursor {
+            canvas.set_draw_color(WHITE);
+            canvas.draw_rect(*rect).unwrap();
+        }
+    }
+}
+
+impl Grid {
+    fn char_width(&self) -> i32 {
+        (GLYPH_WIDTH + 1) * self.scale
+    }
+
+    fn line_height(&self) -> i32 {
+        (GLYPH_HEIGHT + 3) * self.scale
+    }
+
+    /// Draw `s` at the given row and column, returning the area it covers.
+    fn text(
+        &self,
+        canvas: &mut Canvas<Window>,
+        column: i32,
+        row: usize,
+        color: Color,
+        s: &str,
+    ) -> Rect {
+        let x = 8 + column * self.char_width();
+        let y = 8 + row as i32 * self.line_height();
+        draw_text(canvas, x, y, self.scale as u32, color, s);
+
+        let width = s.len() as i32 * self.char_width();
+        Rect::new(x, y, width.max(1) as u32, self.line_height() as u32)
+    }
+}
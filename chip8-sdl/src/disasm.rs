@@ -0,0 +1,50 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chip8::disasm::disassemble;
+use chip8::headless::Runner;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Disasm {
+    /// Rom to disassemble
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+
+    /// Run the rom for this many frames before disassembling it so that data
+    /// regions observed at runtime are printed as data
+    #[structopt(long = "frames", default_value = "0")]
+    frames: usize,
+
+    /// Seed of the random number generator
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Frequency of the emulator
+    #[structopt(short = "f", long = "frequency", default_value = "500")]
+    freq: usize,
+}
+
+pub fn run(opts: Disasm) {
+    let prog = fs::read(&opts.rom).expect("cannot read rom");
+
+    let mut runner = Runner::new(&prog, opts.seed, opts.freq / 60).expect("rom too big");
+    for _ in 0..opts.frames {
+        runner.run_frame();
+    }
+
+    for e in runner.chip8_mut().take_events() {
+        eprintln!("warning: {}", e);
+    }
+
+    let accesses = if opts.frames > 0 {
+        Some(runner.chip8().accesses())
+    } else {
+        None
+    };
+
+    for line in disassemble(&prog, accesses) {
+        println!("{}", line);
+    }
+}
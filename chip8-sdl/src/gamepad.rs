@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use chip8::input::Padmap;
+
+use sdl2::controller::{Axis, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+/// The game controllers plugged in, the first one plugged is the first
+/// player and so on.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    /// The gamepad of every player, the players without one use the first.
+    padmaps: Vec<Padmap>,
+    /// Fraction of the range of the axes ignored around the center.
+    dead_zone: f32,
+    /// The hex key held by every input of every controller, by the id of the
+    /// controller.
+    held: HashMap<(u32, String), u8>,
+    /// What happened to the controllers since the last `take_messages`.
+    messages: Vec<String>,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem, padmaps: Vec<Padmap>, dead_zone: f32) -> Self {
+        Gamepads {
+            subsystem,
+            controllers: vec![],
+            padmaps,
+            dead_zone,
+            held: HashMap::new(),
+            messages: vec![],
+        }
+    }
+
+    /// Use the gamepads of another game, the keys held are forgotten.
+    pub fn set_padmaps(&mut self, padmaps: Vec<Padmap>, dead_zone: f32) {
+        self.padmaps = padmaps;
+        self.dead_zone = dead_zone;
+        self.held.clear();
+    }
+
+    /// Handle the controller events, returning the hex keys pressed and
+    /// released. SDL sends an added event for the controllers plugged
+    /// before starting too.
+    pub fn event(&mut self, event: &Event) -> Vec<(u8, bool)> {
+        let mut changes = vec![];
+
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(*which) {
+                Ok(controller) => {
+                    self.messages.push(format!(
+                        "controller {} connected: {}",
+                        self.controllers.len() + 1,
+                        controller.name()
+                    ));
+                    self.controllers.push(controller);
+                }
+                Err(e) => self.messages.push(format!("cannot open controller: {}", e)),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let held = self.held.keys().filter(|(id, _)| id == which).cloned();
+                for (id, input) in held.collect::<Vec<_>>() {
+                    self.set(id, &input, false, &mut changes);
+                }
+
+                if let Some(i) = self
+                    .controllers
+                    .iter()
+                    .position(|c| c.instance_id() == *which)
+                {
+                    let controller = self.controllers.remove(i);
+                    self.messages.push(format!(
+                        "controller {} disconnected: {}",
+                        i + 1,
+                        controller.name()
+                    ));
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.set(*which, &button.string(), true, &mut changes)
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.set(*which, &button.string(), false, &mut changes)
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                let value = f32::from(*value) / f32::from(i16::MAX);
+                let active = |v: f32| v > self.dead_zone;
+
+                match axis {
+                    Axis::TriggerLeft | Axis::TriggerRight => {
+                        self.set(*which, &axis.string(), active(value), &mut changes);
+                    }
+                    _ => {
+                        let (neg, pos) = (active(-value), active(value));
+                        self.set(*which, &format!("{}-", axis.string()), neg, &mut changes);
+                        self.set(*which, &format!("{}+", axis.string()), pos, &mut changes);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        changes
+    }
+
+    /// The controllers connected and disconnected since the last call, for
+    /// the status line.
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// Press or release the hex key of `input` of the controller `id`
+    /// unless it already is.
+    fn set(&mut self, id: u32, input: &str, pressed: bool, changes: &mut Vec<(u8, bool)>) {
+        let key = (id, input.to_string());
+
+        if !pressed {
+            if let Some(hex_key) = self.held.remove(&key) {
+                changes.push((hex_key, false));
+            }
+            return;
+        }
+
+        if self.held.contains_key(&key) {
+            return;
+        }
+
+        let player = self.controllers.iter().position(|c| c.instance_id() == id);
+        let padmap = player
+            .and_then(|p| self.padmaps.get(p))
+            .or_else(|| self.padmaps.first());
+
+        if let Some(hex_key) = padmap.and_then(|p| p.hex_key(input)) {
+            self.held.insert(key, hex_key);
+            changes.push((hex_key, true));
+        }
+    }
+}
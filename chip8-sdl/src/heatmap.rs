@@ -0,0 +1,99 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use chip8::headless::Runner;
+use chip8::heatmap::{Heatmap as RamHeatmap, VramTimeline};
+use chip8::image::GifEncoder;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Heatmap {
+    /// Rom to run
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+
+    /// Number of frames to run
+    #[structopt(long = "frames", default_value = "600")]
+    frames: usize,
+
+    /// Seed of the random number generator
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Frequency of the emulator
+    #[structopt(short = "f", long = "frequency", default_value = "500")]
+    freq: usize,
+
+    /// Fraction of heat kept by every byte at each frame
+    #[structopt(long = "decay", default_value = "0.9")]
+    decay: f32,
+
+    /// Size in pixels of every byte in the heatmap
+    #[structopt(long = "scale", default_value = "8")]
+    scale: usize,
+
+    /// Directory where to write a PNG of the heatmap for every frame
+    #[structopt(long = "png-dir", parse(from_os_str))]
+    png_dir: Option<PathBuf>,
+
+    /// Write the heatmap as an animated GIF to this file
+    #[structopt(long = "gif", parse(from_os_str))]
+    gif: Option<PathBuf>,
+
+    /// Write the chart of the VRAM changes per frame as a PNG to this file
+    #[structopt(long = "timeline", parse(from_os_str))]
+    timeline: Option<PathBuf>,
+}
+
+pub fn run(opts: Heatmap) {
+    let prog = fs::read(&opts.rom).expect("cannot read rom");
+
+    let mut runner = Runner::new(&prog, opts.seed, opts.freq / 60).expect("rom too big");
+    let mut heatmap = RamHeatmap::new(opts.decay);
+    let mut timeline = VramTimeline::new();
+
+    if let Some(dir) = &opts.png_dir {
+        fs::create_dir_all(dir).expect("cannot create png directory");
+    }
+
+    let mut gif = opts.gif.as_ref().map(|path| {
+        let f = BufWriter::new(File::create(path).expect("cannot create gif"));
+        let side = chip8::heatmap::HEATMAP_SIDE * opts.scale;
+
+        GifEncoder::new(f, side, side).expect("cannot write gif")
+    });
+
+    for _ in 0..opts.frames {
+        runner.run_frame();
+
+        heatmap.update(runner.chip8().accesses());
+        timeline.update(runner.chip8());
+
+        if opts.png_dir.is_none() && gif.is_none() {
+            continue;
+        }
+
+        let img = heatmap.render(opts.scale);
+
+        if let Some(dir) = &opts.png_dir {
+            let path = dir.join(format!("heatmap-{:05}.png", runner.frame()));
+            let f = BufWriter::new(File::create(path).expect("cannot create png"));
+            img.write_png(f).expect("cannot write png");
+        }
+
+        if let Some(gif) = &mut gif {
+            // gif delays are in hundredths of second, 2 is the closest to 60fps
+            gif.add_frame(&img, 2).expect("cannot write gif");
+        }
+    }
+
+    if let Some(path) = opts.timeline {
+        let f = BufWriter::new(File::create(path).expect("cannot create timeline"));
+        timeline
+            .render(2, 128)
+            .write_png(f)
+            .expect("cannot write timeline");
+    }
+}
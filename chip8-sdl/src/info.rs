@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chip8::chip8::{PROGRAM_START_PC, RAM_SIZE, STACK_SIZE};
+use chip8::detect;
+use chip8::quirks::QUIRK_NAMES;
+use chip8::stats::RomStats;
+
+use serde_json::json;
+use structopt::StructOpt;
+
+use crate::utils;
+
+#[derive(Debug, StructOpt)]
+pub struct Info {
+    /// Rom to inspect
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+
+    /// Additional rom database in JSON or TOML format to look the rom up in
+    #[structopt(long = "rom-db", parse(from_os_str))]
+    rom_db: Option<PathBuf>,
+
+    /// Print the report as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+pub fn run(opts: Info) {
+    let prog = fs::read(&opts.rom).expect("cannot read rom");
+
+    let stats = RomStats::new(&prog);
+    let detection = detect::detect(&prog);
+    let db = utils::load_rom_db(opts.rom_db.as_deref());
+    let rom_info = db.lookup(&prog);
+
+    if opts.json {
+        let report = json!({
+            "stats": stats.to_json(),
+            "detection": {
+                "platform": detection.platform,
+                "quirks": detection.quirks,
+                "confidence": detection.confidence,
+                "findings": detection.findings.iter().map(|f| json!({
+                    "addr": f.addr,
+                    "text": f.evidence.to_string(),
+                })).collect::<Vec<_>>(),
+            },
+            "database": rom_info,
+        });
+
+        println!("{}", report);
+        return;
+    }
+
+    if let Some(info) = rom_info {
+        print!("{}", info.title);
+        if let Some(author) = &info.author {
+            print!(" by {}", author);
+        }
+        if let Some(year) = info.year {
+            print!(" ({})", year);
+        }
+        println!(", {} rom from the database", info.platform);
+        println!();
+    }
+
+    println!("size:    {} bytes", stats.size);
+    println!("sha1:    {}", stats.sha1);
+    println!("sha256:  {}", stats.sha256);
+    if !stats.fits {
+        println!(
+            "warning: the rom doesn't fit in the {} bytes available",
+            RAM_SIZE - PROGRAM_START_PC
+        );
+    }
+
+    println!("\nsubroutines: {}", stats.subroutines);
+    match stats.max_call_depth {
+        Some(d) => println!("max call depth: {} (stack has {} entries)", d, STACK_SIZE),
+        None => println!("max call depth: unbounded, some subroutines are recursive"),
+    }
+    if stats.may_overflow_stack() {
+        println!("warning: the calls might overflow the stack");
+    }
+
+    let digits = stats
+        .font_digits
+        .iter()
+        .map(|d| format!("{:X}", d))
+        .collect::<Vec<_>>();
+    println!(
+        "font digits: {} ({} computed at runtime)",
+        if digits.is_empty() {
+            "none".to_string()
+        } else {
+            digits.join(" ")
+        },
+        stats.dynamic_font_refs
+    );
+
+    println!("\ninvalid instructions:");
+    for (addr, word) in &stats.invalid {
+        println!("  {:#05X}: {:04X}", addr, word);
+    }
+
+    println!("\nopcodes:");
+    for (pattern, count) in &stats.histogram {
+        println!("  {}  {}", pattern, count);
+    }
+
+    println!("\ndetected platform: {}", detection.platform);
+    println!("recommended quirks:");
+
+    for (i, name) in QUIRK_NAMES.iter().enumerate() {
+        let enabled = detection.quirks.get(i);
+        println!("  {:<10} {}", name, if enabled { "on" } else { "off" });
+    }
+
+    println!("confidence: {:.0}%", detection.confidence * 100.0);
+
+    println!("\nfindings:");
+    for f in &detection.findings {
+        println!("  {}", f);
+    }
+}
@@ -1,142 +1,1013 @@
+mod analyze;
+mod browser;
+mod capture;
+mod cheats;
+mod check;
+mod config;
+mod control;
+mod debugger;
+mod disasm;
+mod gamepad;
+mod heatmap;
+mod info;
+mod quirks;
+mod remap;
+mod screen;
+mod text;
 mod utils;
+mod watch;
 
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::mouse::MouseButton;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::{AudioSubsystem, EventPump};
 
-use structopt::StructOpt;
+use chip8::audio::{self, Synth, WavRecorder};
+use chip8::capture::{AnimationFormat, ANIMATION_FORMATS};
+use chip8::cheats::CheatFile;
+use chip8::db::RomDatabase;
+use chip8::display::{AntiFlicker, Display, ANTI_FLICKER_MODES};
+use chip8::filters::{Filter, Pipeline, Upscaler, UPSCALER_NAMES};
+use chip8::input::{Keymap, PRESETS};
+use chip8::palette::Palette;
+use chip8::state::SaveState;
+use chip8::Chip8;
 
-const KEY_MAPPINGS: [Keycode; 16] = [
-    Keycode::X,
-    Keycode::Num1,
-    Keycode::Num2,
-    Keycode::Num3,
-    Keycode::Q,
-    Keycode::W,
-    Keycode::E,
-    Keycode::A,
-    Keycode::S,
-    Keycode::D,
-    Keycode::Z,
-    Keycode::C,
-    Keycode::Num4,
-    Keycode::R,
-    Keycode::F,
-    Keycode::V,
-];
+use config::{Action, Config, Settings};
+
+use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 struct App {
-    /// Game rom to play
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
+    /// Game rom to play, the rom browser is shown without one
     #[structopt(parse(from_os_str))]
-    rom: PathBuf,
+    rom: Option<PathBuf>,
+
+    /// Directory of roms listed by the rom browser, it can be repeated.
+    #[structopt(long = "rom-dir", number_of_values = 1, parse(from_os_str))]
+    rom_dirs: Vec<PathBuf>,
+
+    /// Frequency of the emulator, defaults to the one recommended by the rom
+    /// database or 500 for unknown roms
+    #[structopt(short = "f", long = "frequency")]
+    freq: Option<usize>,
+
+    /// Additional rom database in JSON or TOML format, its entries take
+    /// precedence over the builtin ones
+    #[structopt(long = "rom-db", parse(from_os_str))]
+    rom_db: Option<PathBuf>,
+
+    /// File with the cheats to use, the cheat menu is opened with F2
+    #[structopt(long = "cheats", parse(from_os_str))]
+    cheats: Option<PathBuf>,
+
+    /// Configuration file, defaults to chip8/config.toml in the XDG config
+    /// directory
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Preset of the keys: qwerty (the default), numpad, azerty or dvorak.
+    /// The keys can be remapped for every game with F1.
+    #[structopt(long = "keymap", possible_values = &PRESETS)]
+    keymap: Option<Keymap>,
+
+    /// Fraction of the range of the analog sticks of the gamepads ignored
+    /// around the center, 0.3 by default.
+    #[structopt(long = "dead-zone")]
+    dead_zone: Option<f32>,
+
+    /// Speed of the fast forward (Space while held, L to toggle): uncapped
+    /// or a multiplier like 4x, the default.
+    #[structopt(long = "fast-forward")]
+    fast_forward: Option<control::Speed>,
+
+    /// Speed of the slow motion (M to toggle), 0.5x by default.
+    #[structopt(long = "slow-motion")]
+    slow_motion: Option<control::Speed>,
+
+    /// How the screen is scaled to the window, aspect by default.
+    #[structopt(long = "scaling", possible_values = &screen::SCALINGS)]
+    scaling: Option<screen::Scaling>,
+
+    /// Anti-flicker processing of the screen: off (the default),
+    /// phosphor[:persistence], blend[:frames] or wait-draw.
+    #[structopt(long = "anti-flicker")]
+    anti_flicker: Option<AntiFlicker>,
+
+    /// Upscaler used when rendering the screen with filters, nearest by
+    /// default.
+    #[structopt(long = "upscaler", possible_values = &UPSCALER_NAMES)]
+    upscaler: Option<Upscaler>,
+
+    /// Filters applied to the screen in order: scanlines, grid, bloom or
+    /// barrel, each optionally followed by :strength.
+    #[structopt(long = "filter", number_of_values = 1)]
+    filters: Vec<Filter>,
+
+    /// How many times the screen is upscaled before applying the filters, 4
+    /// by default.
+    #[structopt(long = "filter-scale")]
+    filter_scale: Option<usize>,
+
+    /// Name of the palette to start with, overrides the one in the rom
+    /// database.
+    #[structopt(long = "palette")]
+    palette: Option<String>,
+
+    /// TOML file with more palettes.
+    #[structopt(long = "palette-file", parse(from_os_str))]
+    palette_file: Option<PathBuf>,
+
+    /// Background color as #RRGGBB, overrides the one of the palette.
+    #[structopt(long = "bg", parse(try_from_str = screen::parse_color))]
+    bg: Option<[u8; 3]>,
+
+    /// Foreground color as #RRGGBB, overrides the one of the palette.
+    #[structopt(long = "fg", parse(try_from_str = screen::parse_color))]
+    fg: Option<[u8; 3]>,
+
+    /// Directory where screenshots (F12), recordings (F9) and video dumps
+    /// (F10) are saved, the current one by default.
+    #[structopt(long = "capture-dir", parse(from_os_str))]
+    capture_dir: Option<PathBuf>,
+
+    /// How many times larger than the screen the captures are, 8 by default.
+    #[structopt(long = "capture-scale")]
+    capture_scale: Option<usize>,
+
+    /// Format of the recordings, gif by default.
+    #[structopt(long = "record-format", possible_values = &ANIMATION_FORMATS)]
+    record_format: Option<AnimationFormat>,
 
-    /// Frequency of the emulator
-    #[structopt(short = "f", long = "frequency", default_value = "500")]
-    freq: usize,
+    /// Directory where the save states (F5 to save, F7 to load) are kept,
+    /// defaults to chip8/states in the XDG data directory.
+    #[structopt(long = "save-state-dir", parse(from_os_str))]
+    save_state_dir: Option<PathBuf>,
+
+    /// Record the sound of the whole session to this WAV file.
+    #[structopt(long = "record-audio", parse(from_os_str))]
+    record_audio: Option<PathBuf>,
+
+    /// Reload the rom whenever it changes, building it again if it's a
+    /// source file. Build errors are shown over the game.
+    #[structopt(long = "watch")]
+    watch: bool,
+
+    /// Command building the roms from .8o, .asm and .s sources, {src} and
+    /// {out} stand for the source and the rom, e.g. "octo {src} {out}".
+    /// Without one the .asm and .s sources are built by the builtin
+    /// assembler.
+    #[structopt(long = "assembler")]
+    assembler: Option<String>,
+
+    /// RAM kept when the rom is reloaded by --watch, as a range like
+    /// 0x300-0x3FF.
+    #[structopt(long = "keep-ram", number_of_values = 1)]
+    keep_ram: Vec<watch::RamRange>,
+
+    /// Keep V0 to VF and I when the rom is reloaded by --watch.
+    #[structopt(long = "keep-registers")]
+    keep_registers: bool,
+
+    /// Save state restored when the rom is loaded and every time it's
+    /// reloaded by --watch, with the new program written over it.
+    #[structopt(long = "watch-state", parse(from_os_str))]
+    watch_state: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print unreachable regions and suspicious jump targets of a rom and
+    /// export its control flow graph
+    Analyze(analyze::Analyze),
+
+    /// Run a rom headless and save screenshots, GIF or APNG recordings and
+    /// Y4M video with WAV audio of it
+    Capture(capture::Capture),
+
+    /// Run all the roms of a directory headless and report the ones that
+    /// crash, get stuck or show nothing
+    Check(check::Check),
+
+    /// Disassemble a rom, optionally running it first to tell code and data
+    /// apart
+    Disasm(disasm::Disasm),
+
+    /// Run a rom headless and export a heatmap of its RAM accesses and a
+    /// timeline of its VRAM changes
+    Heatmap(heatmap::Heatmap),
+
+    /// Print statistics of a rom and guess the platform and quirks it has
+    /// been written for
+    Info(info::Info),
+
+    /// Run a rom headless under every quirks combination to find out which
+    /// quirks it depends on
+    Quirks(quirks::Quirks),
+}
+
+/// What lives as long as the window, shared by all the games played in it.
+struct Frontend<'a> {
+    app: &'a App,
+    config: &'a Config,
+    db: &'a RomDatabase,
+    audio_subsystem: AudioSubsystem,
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    event_pump: EventPump,
+    gamepads: gamepad::Gamepads,
+    /// The sound of the whole session, see `--record-audio`.
+    audio_recorder: Option<WavRecorder<BufWriter<File>>>,
+    recent: browser::Recent,
+}
+
+/// What to do once a game is over.
+enum Outcome {
+    Quit,
+    /// Go back to the rom browser.
+    Browse,
+    /// Play another rom, e.g. one dropped on the window.
+    Load(PathBuf),
+    /// The rom cannot be played, e.g. it's missing or too large.
+    Failed(String),
 }
 
 fn main() {
-    let app = App::from_args();
+    let mut app = App::from_args();
 
-    let mut rom = File::open(app.rom).expect("cannot open rom");
+    if let Some(cmd) = app.cmd.take() {
+        return match cmd {
+            Command::Analyze(opts) => analyze::run(opts),
+            Command::Capture(opts) => capture::run(opts),
+            Command::Check(opts) => check::run(opts),
+            Command::Disasm(opts) => disasm::run(opts),
+            Command::Heatmap(opts) => heatmap::run(opts),
+            Command::Info(opts) => info::run(opts),
+            Command::Quirks(opts) => quirks::run(opts),
+        };
+    }
 
-    let mut prog = vec![];
-    rom.read_to_end(&mut prog).expect("cannot read rom");
+    // a bad configuration is reported by the rom browser, like a bad rom
+    let (config, config_error) = match Config::load(app.config.as_deref()) {
+        Ok(config) => (config, None),
+        Err(e) => (Config::default(), Some(e)),
+    };
+    let db = utils::load_rom_db(app.rom_db.as_deref());
 
-    let mut chip8 = chip8::Chip8::with_program(rand::thread_rng(), &prog).unwrap();
+    // the settings of no rom in particular, for what outlives the games
+    let mut settings = config.settings.clone();
+    settings.merge(app.settings());
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
+    let gamepads = gamepad::Gamepads::new(
+        sdl_context.game_controller().unwrap(),
+        settings.padmaps(vec![]),
+        settings.gamepad.dead_zone.unwrap_or(0.3),
+    );
 
     let window = video_subsystem
-        .window(env!("CARGO_PKG_NAME"), 640, 320)
+        .window(
+            env!("CARGO_PKG_NAME"),
+            settings.window_width.unwrap_or(640),
+            settings.window_height.unwrap_or(320),
+        )
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
 
+    let audio_recorder = app.record_audio.as_ref().map(|path| {
+        let f = BufWriter::new(File::create(path).expect("cannot create audio recording"));
+        WavRecorder::new(f, synth(&settings, audio::SAMPLE_RATE))
+            .expect("cannot write audio recording")
+    });
+
+    let canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+
+    let mut frontend = Frontend {
+        app: &app,
+        config: &config,
+        db: &db,
+        audio_subsystem: sdl_context.audio().unwrap(),
+        canvas,
+        texture_creator: &texture_creator,
+        event_pump: sdl_context.event_pump().unwrap(),
+        gamepads,
+        audio_recorder,
+        recent: browser::Recent::load(),
+    };
+
+    let mut browser = browser::Browser::new(settings.rom_dirs.clone().unwrap_or_default());
+    let hotkeys = settings.hotkeys();
+
+    let mut rom = app.rom.clone();
+    if let Some(e) = config_error {
+        browser.set_error(format!("cannot load config {}", e));
+        rom = None;
+    }
+    loop {
+        let path = match rom.take() {
+            Some(path) => path,
+            None => {
+                let picked = browser.run(
+                    &mut frontend.canvas,
+                    &mut frontend.event_pump,
+                    &mut frontend.gamepads,
+                    &db,
+                    &frontend.recent,
+                    &hotkeys,
+                );
+                match picked {
+                    Some(path) => path,
+                    None => break,
+                }
+            }
+        };
+
+        match play(&mut frontend, &path) {
+            Outcome::Quit => break,
+            Outcome::Browse => {}
+            Outcome::Load(next) => rom = Some(next),
+            Outcome::Failed(e) => {
+                browser.set_error(format!("cannot load {}: {}", path.display(), e))
+            }
+        }
+    }
+
+    if let Some(audio_recorder) = frontend.audio_recorder.take() {
+        audio_recorder
+            .finish()
+            .expect("cannot write audio recording");
+    }
+}
+
+/// Play the rom at `rom` in the window until it's closed or another rom is
+/// picked.
+fn play(frontend: &mut Frontend, rom: &Path) -> Outcome {
+    let (app, config, db) = (frontend.app, frontend.config, frontend.db);
+    let canvas = &mut frontend.canvas;
+    let event_pump = &mut frontend.event_pump;
+    let gamepads = &mut frontend.gamepads;
+    let audio_recorder = &mut frontend.audio_recorder;
+
+    let assembler = app
+        .assembler
+        .clone()
+        .or_else(|| config.settings.assembler.clone());
+
+    let mut seed = rand::random();
+    let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(seed), &watch::IDLE_PROGRAM).unwrap();
+
+    // the rom has to be built before the settings of the rom can be found
+    let loaded = watch::build(rom, assembler.as_deref())
+        .and_then(|prog| chip8.load_program(&prog).map_err(|e| e.to_string()));
+    let mut build_error = None;
+    if let Err(e) = loaded {
+        if !app.watch {
+            return Outcome::Failed(e);
+        }
+        // keep the window open to show the error until the source is fixed
+        build_error = Some(e);
+    }
+
+    let prog = chip8.program().1.to_vec();
+    // until the first build succeeds the rom is known by its path, the game
+    // starts over once it's built to pick its settings, keys, states and cheats
+    let built = build_error.is_none();
+    let rom_sha1 = if built {
+        chip8::hash::sha1(&prog)
+    } else {
+        chip8::hash::sha1(rom.to_string_lossy().as_bytes())
+    };
+
+    let mut settings = config.settings_for(rom, &rom_sha1);
+
+    // the keys remapped in game are kept apart from the configuration file
+    let keymap_path = config::xdg_dir("XDG_CONFIG_HOME", ".config")
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("keymaps")
+        .join(format!("{}.toml", rom_sha1));
+    if keymap_path.exists() {
+        match Config::load(Some(&keymap_path)) {
+            Ok(keymap) => settings.merge(keymap.settings),
+            Err(e) => return Outcome::Failed(format!("keymap {}: {}", keymap_path.display(), e)),
+        }
+    }
+
+    settings.merge(app.settings());
+
+    let rom_info = db.lookup(&prog);
+    let mut title = env!("CARGO_PKG_NAME").to_string();
+    let mut cycles_per_frame = settings.frequency.unwrap_or(500) / 60;
+    let mut palettes = Palette::builtin();
+    if let Some(path) = &settings.palette_file {
+        let loaded = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| Palette::parse_file(&file).map_err(|e| e.to_string()));
+        match loaded {
+            Ok(loaded) => palettes.extend(loaded),
+            Err(e) => return Outcome::Failed(format!("palettes {}: {}", path.display(), e)),
+        }
+    }
+    let mut palette = Palette::default();
+
+    if let Some(info) = rom_info {
+        chip8.set_quirks(info.quirks);
+
+        if let (None, Some(ipf)) = (settings.frequency, info.instructions_per_frame) {
+            cycles_per_frame = ipf;
+        }
+
+        if let Some(p) = info.palette() {
+            palette = p;
+        }
+
+        title = format!("{} - {}", title, info.title);
+
+        print!("{}", info.title);
+        if let Some(author) = &info.author {
+            print!(" by {}", author);
+        }
+        if let Some(year) = info.year {
+            print!(" ({})", year);
+        }
+        println!();
+
+        for (key, desc) in &info.keys {
+            println!("  {:>3}  {}", key, desc);
+        }
+    }
+
+    if let Some(quirks) = settings.quirks {
+        chip8.set_quirks(quirks);
+    }
+
+    let persistent_flags = settings.persistent_flags.unwrap_or(false);
+    chip8.set_persistent_flags(persistent_flags);
+
+    // hard resets start over with a new machine, with the same quirks
+    let quirks = chip8.quirks();
+    let new_chip8 = |seed: u64, prog: &[u8]| {
+        let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(seed), prog).unwrap();
+        chip8.set_quirks(quirks);
+        chip8.set_persistent_flags(persistent_flags);
+        chip8
+    };
+
+    let mut keymap = settings.keymap();
+    let padmaps = settings.padmaps(rom_info.map(|i| i.padmaps()).unwrap_or_default());
+    let hotkeys = settings.hotkeys();
+
+    let save_state_path = settings
+        .save_state_dir
+        .clone()
+        .or_else(|| config::xdg_dir("XDG_DATA_HOME", ".local/share").map(|d| d.join("states")))
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(format!("{}.{}", rom_sha1, chip8::state::EXTENSION));
+
+    let mut watcher = if app.watch {
+        Some(watch::Watcher::new(rom))
+    } else {
+        None
+    };
+    let watch_state = app.watch_state.as_ref().map(|path| {
+        fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| SaveState::from_bytes(&data).map_err(|e| e.to_string()))
+            .map_err(|e| format!("watch state {}: {}", path.display(), e))
+    });
+    let keep = watch::Keep {
+        ram: app.keep_ram.clone(),
+        registers: app.keep_registers,
+        state: match watch_state.transpose() {
+            Ok(state) => state,
+            Err(e) => return Outcome::Failed(e),
+        },
+    };
+    if keep.state.is_some() && build_error.is_none() {
+        if let Err(e) = watch::reload(&mut chip8, &prog, &keep) {
+            return Outcome::Failed(e.to_string());
+        }
+    }
+
+    let mut cheat_file = match &app.cheats {
+        Some(path) if path.exists() => {
+            let loaded = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|cheats| CheatFile::parse(&cheats).map_err(|e| e.to_string()));
+            match loaded {
+                Ok(cheat_file) => cheat_file,
+                Err(e) => return Outcome::Failed(format!("cheats {}: {}", path.display(), e)),
+            }
+        }
+        _ => CheatFile::default(),
+    };
+    let mut cheat_menu = cheats::CheatMenu::new();
+    let mut remap_menu = remap::RemapMenu::new();
+    let mut debugger = debugger::Debugger::new(cycles_per_frame);
+    let mut control = control::Control::new(
+        settings.fast_forward.unwrap_or(control::Speed::Times(4.0)),
+        settings.slow_motion.unwrap_or(control::Speed::Times(0.5)),
+        settings.status_line.unwrap_or(true),
+    );
+
+    gamepads.set_padmaps(padmaps, settings.gamepad.dead_zone.unwrap_or(0.3));
+
+    canvas.window_mut().set_title(&title).unwrap();
+    // the window is shared by all the games, only the ones with a size of
+    // their own resize it
+    let size = (settings.window_width, settings.window_height);
+    if size != (config.settings.window_width, config.settings.window_height) {
+        let (w, h) = canvas.window().size();
+        canvas
+            .window_mut()
+            .set_size(size.0.unwrap_or(w), size.1.unwrap_or(h))
+            .unwrap();
+    }
+
     let audio_spec = AudioSpecDesired {
-        freq: Some(44100),
+        freq: Some(audio::SAMPLE_RATE as i32),
         channels: Some(1), // mono
         samples: None,     // default sample size
     };
 
-    let audio_device = audio_subsystem
+    let audio_device = frontend
+        .audio_subsystem
         .open_playback(None, &audio_spec, |spec| {
-            self::utils::SquareWave::new(240.0 / spec.freq as f32, 0.0, 0.25)
+            self::utils::Buzzer::new(synth(&settings, spec.freq as u32))
         })
         .unwrap();
 
-    let mut canvas = window.into_canvas().build().unwrap();
+    if let Some(name) = &settings.palette {
+        match palettes.iter().find(|p| p.name == *name) {
+            Some(p) => palette = p.clone(),
+            None => return Outcome::Failed(format!("unknown palette {}", name)),
+        }
+    }
+
+    // only the games that could be played are remembered
+    if built {
+        frontend.recent.add(rom);
+    }
+    let palette = palette.with_colors(settings.bg, settings.fg);
+
+    // the palette of the rom, or the one with custom colors, can be switched
+    // back to as well
+    let mut palette_ix = match palettes.iter().position(|p| *p == palette) {
+        Some(i) => i,
+        None => {
+            palettes.insert(0, palette);
+            0
+        }
+    };
 
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
+    let upscaler = settings.upscaler.unwrap_or(Upscaler::Nearest);
+    let filters = settings.filters.clone().unwrap_or_default();
+    // without filters the renderer can do the upscaling by itself
+    let scale = if filters.is_empty() && upscaler == Upscaler::Nearest {
+        1
+    } else {
+        settings.filter_scale.unwrap_or(4)
+    };
+    let mut recorder = capture::Recorder::new(
+        settings
+            .capture_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(".")),
+        rom,
+        settings.record_format.unwrap_or(AnimationFormat::Gif),
+        Pipeline {
+            upscaler,
+            scale: settings.capture_scale.unwrap_or(8),
+            filters: filters.clone(),
+        },
+    );
+    let pipeline = Pipeline {
+        upscaler,
+        scale,
+        filters,
+    };
+    let mut screen = screen::Screen::new(
+        frontend.texture_creator,
+        pipeline,
+        settings.scaling.unwrap_or(screen::Scaling::Aspect),
+        screen::to_color(palettes[palette_ix].background()),
+        screen::to_color(palettes[palette_ix].foreground()),
+    );
+    let mut display = Display::new(settings.anti_flicker.unwrap_or_default());
+
+    // whether the window has to be presented again even if the screen didn't
+    // change, e.g. because the cheat menu was open
+    let mut redraw = true;
+    let mut beeping = false;
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let frame_duration = Duration::new(0, 1_000_000_000u32 / 60);
 
     loop {
+        let start = Instant::now();
+
         for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => return,
+            let menu_open = cheat_menu.is_open() || remap_menu.is_open() || debugger.is_stopped();
+            let action = match event {
+                Event::Window { .. } => {
+                    redraw = true;
+                    continue;
+                }
+                Event::Quit { .. } => Action::Quit,
+                Event::DropFile { filename, .. } => {
+                    recorder.stop();
+                    return Outcome::Load(PathBuf::from(filename));
+                }
                 Event::KeyDown {
-                    keycode: Some(kc), ..
-                } => {
-                    let hex_key = KEY_MAPPINGS.iter().position(|m| *m == kc);
-                    if let Some(hex_key) = hex_key {
-                        chip8.keypress(hex_key as u8);
+                    keycode: Some(kc),
+                    scancode,
+                    repeat,
+                    ..
+                } => match hotkeys.get(&kc) {
+                    Some(action) if !(action.is_control() && menu_open) => {
+                        if repeat {
+                            continue;
+                        }
+                        *action
                     }
-                }
+                    _ if remap_menu.is_open() => {
+                        remap_menu.keydown(kc, scancode, &mut keymap);
+                        continue;
+                    }
+                    _ if cheat_menu.is_open() => {
+                        let cheats = cheat_file.cheats_mut(&rom_sha1);
+                        let save = cheat_menu.keydown(kc, cheats, &chip8);
+
+                        if save {
+                            let message = match &app.cheats {
+                                Some(path) => match fs::write(path, cheat_file.to_string()) {
+                                    Ok(()) => format!("cheats saved to {}", path.display()),
+                                    Err(e) => format!("cannot write cheats: {}", e),
+                                },
+                                None => "pass --cheats to save the cheats".to_string(),
+                            };
+                            cheat_menu.set_message(message);
+                        }
+                        continue;
+                    }
+                    _ => {
+                        if debugger.is_stopped() && debugger.keydown(kc, &mut chip8) {
+                            redraw = true;
+                            continue;
+                        }
+
+                        if let Some(code) = scancode.and_then(remap::code) {
+                            for hex_key in keymap.hex_keys(code) {
+                                chip8.keypress(hex_key);
+                            }
+                        }
+                        continue;
+                    }
+                },
                 Event::KeyUp {
-                    keycode: Some(kc), ..
+                    keycode,
+                    scancode: Some(scancode),
+                    ..
                 } => {
-                    let hex_key = KEY_MAPPINGS.iter().position(|m| *m == kc);
-                    if let Some(hex_key) = hex_key {
-                        chip8.keyrelease(hex_key as u8);
+                    if keycode.and_then(|kc| hotkeys.get(&kc)) == Some(&Action::FastForward) {
+                        control.hold_fast_forward(false);
+                    }
+
+                    if let Some(code) = remap::code(scancode) {
+                        for hex_key in keymap.hex_keys(code) {
+                            chip8.keyrelease(hex_key);
+                        }
+                    }
+                    continue;
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } if debugger.is_open() => {
+                    debugger.click(x, y, &mut chip8);
+                    redraw = true;
+                    continue;
+                }
+                _ => {
+                    for (hex_key, pressed) in gamepads.event(&event) {
+                        if pressed {
+                            chip8.keypress(hex_key);
+                        } else {
+                            chip8.keyrelease(hex_key);
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            match action {
+                Action::Quit => {
+                    recorder.stop();
+                    return Outcome::Quit;
+                }
+                Action::Browser => {
+                    recorder.stop();
+                    return Outcome::Browse;
+                }
+                Action::Pause => {
+                    control.toggle_pause();
+                    redraw = true;
+                }
+                Action::FrameAdvance => control.advance_frame(),
+                Action::SoftReset => {
+                    chip8.reset();
+                    control.flash("reset");
+                }
+                Action::HardReset => {
+                    seed = rand::random();
+                    let prog = chip8.program().1.to_vec();
+                    chip8 = new_chip8(seed, &prog);
+                    control.flash(format!("hard reset, seed {}", seed));
+                }
+                Action::FastForward => control.hold_fast_forward(true),
+                Action::ToggleFastForward => control.toggle_fast_forward(),
+                Action::SlowMotion => control.toggle_slow_motion(),
+                Action::Fullscreen => {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    window.set_fullscreen(fullscreen).unwrap();
+                }
+                Action::AntiFlicker => {
+                    let current = display.mode().name();
+                    let i = ANTI_FLICKER_MODES.iter().position(|m| *m == current);
+                    let next =
+                        ANTI_FLICKER_MODES[i.map_or(0, |i| (i + 1) % ANTI_FLICKER_MODES.len())];
+
+                    display.set_mode(next.parse().unwrap());
+                    control.flash(format!("anti-flicker {}", display.mode()));
+                }
+                Action::Palette => {
+                    palette_ix = (palette_ix + 1) % palettes.len();
+                    control.flash(format!("palette {}", palettes[palette_ix].name));
+                }
+                Action::SaveState => {
+                    let saved = save_state_path
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|()| fs::write(&save_state_path, chip8.save_state().to_bytes()));
+                    match saved {
+                        Ok(()) => control.flash("state saved"),
+                        Err(e) => control.flash(format!("cannot save state: {}", e)),
+                    }
+                }
+                Action::LoadState => {
+                    let loaded = fs::read(&save_state_path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|data| SaveState::from_bytes(&data).map_err(|e| e.to_string()));
+                    match loaded {
+                        Ok(state) => {
+                            chip8.load_state(&state);
+                            control.flash("state loaded");
+                        }
+                        Err(e) => control.flash(format!("cannot load state: {}", e)),
+                    }
+                }
+                Action::Screenshot => {
+                    match recorder.screenshot(&display, &palettes[palette_ix], chip8.beep()) {
+                        Ok(path) => control.flash(format!("screenshot {}", file_name(&path))),
+                        Err(e) => control.flash(format!("cannot save screenshot: {}", e)),
+                    }
+                }
+                Action::Record => match recorder.toggle_animation() {
+                    Ok(Some(path)) => control.flash(format!("recorded {}", file_name(&path))),
+                    Ok(None) => control.flash("recording"),
+                    Err(e) => control.flash(format!("cannot record: {}", e)),
+                },
+                Action::Dump => match recorder.toggle_dump() {
+                    Ok(Some(path)) => control.flash(format!("dumped {}", file_name(&path))),
+                    Ok(None) => control.flash("dumping video"),
+                    Err(e) => control.flash(format!("cannot dump video: {}", e)),
+                },
+                Action::Cheats => {
+                    cheat_menu.toggle();
+                    redraw = true;
+                }
+                Action::Debugger => {
+                    debugger.toggle(chip8.pc());
+                    redraw = true;
+                }
+                Action::Remap => {
+                    remap_menu.toggle();
+                    redraw = true;
+
+                    if !remap_menu.is_open() {
+                        match save_keymap(&keymap_path, &keymap) {
+                            Ok(()) => control.flash("keys saved"),
+                            Err(e) => control.flash(format!("cannot save keys: {}", e)),
+                        }
                     }
                 }
-                _ => {}
             }
         }
 
-        for _ in 0..app.freq / 60 {
-            chip8.emulate_cycle();
+        let messages = gamepads.take_messages();
+        if !messages.is_empty() {
+            control.flash(messages.join(", "));
+        }
+
+        if watcher.as_mut().is_some_and(|w| w.changed()) {
+            let reloaded = watch::build(rom, assembler.as_deref()).and_then(|prog| {
+                watch::reload(&mut chip8, &prog, &keep).map_err(|e| e.to_string())
+            });
+
+            match reloaded {
+                Ok(()) if !built => {
+                    recorder.stop();
+                    return Outcome::Load(rom.to_path_buf());
+                }
+                Ok(()) => {
+                    build_error = None;
+                    control.flash("reloaded");
+                }
+                Err(e) => {
+                    eprintln!("cannot build {}: {}", rom.display(), e);
+                    build_error = Some(e);
+                }
+            }
+            redraw = true;
         }
 
-        canvas.clear();
+        let menu_open = cheat_menu.is_open() || remap_menu.is_open() || debugger.is_stopped();
+        let frames = if menu_open { Some(0) } else { control.frames() };
+
+        let mut ran = 0;
+        while frames.map_or(start.elapsed() < frame_duration, |frames| ran < frames) {
+            for cheat in cheat_file.cheats_mut(&rom_sha1) {
+                cheat.apply(&mut chip8);
+            }
+
+            for _ in 0..cycles_per_frame {
+                if debugger.check(chip8.pc()) {
+                    break;
+                }
+                chip8.emulate_cycle();
+            }
 
-        for (y, x, p) in chip8.pixels() {
-            if *p == 1 {
-                canvas.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
-            } else {
-                canvas.set_draw_color(Color::RGB(0, 0, 0));
+            beeping = chip8.beep();
+            if let Some(Err(e)) = audio_recorder.as_mut().map(|r| r.add_frame(beeping)) {
+                *audio_recorder = None;
+                control.flash(format!("audio recording stopped: {}", e));
             }
 
-            canvas
-                .fill_rect(sdl2::rect::Rect::new(x as i32 * 10, y as i32 * 10, 10, 10))
-                .unwrap();
+            chip8.decrease_timers();
+            ran += 1;
+
+            if debugger.is_stopped() {
+                break;
+            }
+        }
+
+        if menu_open || control.is_paused() {
+            beeping = false;
+        }
+
+        for e in chip8.take_events() {
+            eprintln!("warning: {}", e);
         }
 
-        if chip8.beep() {
+        let palette = &palettes[palette_ix];
+        screen.set_colors(
+            screen::to_color(palette.background_while(beeping)),
+            screen::to_color(palette.foreground()),
+        );
+
+        let dirty = chip8.take_dirty();
+        let dirty = display.update(chip8.framebuffer(), dirty);
+        let updated = screen.update(&display, dirty);
+
+        if let Err(e) = recorder.add_frame(&display, palette, beeping) {
+            recorder.cancel();
+            control.flash(format!("recording stopped: {}", e));
+        }
+
+        if redraw
+            || updated
+            || menu_open
+            || debugger.is_open()
+            || control.status().is_some()
+            || control.status_changing()
+        {
+            screen.draw(canvas);
+            control.draw(canvas);
+
+            if let Some(error) = &build_error {
+                watch::draw_error(canvas, error);
+            }
+            if debugger.is_open() {
+                debugger.draw(canvas, &chip8);
+            }
+
+            if cheat_menu.is_open() {
+                cheat_menu.draw(canvas, cheat_file.cheats(&rom_sha1), &chip8);
+            }
+            if remap_menu.is_open() {
+                remap_menu.draw(canvas, &keymap);
+            }
+
+            canvas.present();
+            redraw = false;
+        }
+
+        if beeping {
             audio_device.resume();
         } else {
             audio_device.pause();
         }
 
-        canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        ::std::thread::sleep(frame_duration.saturating_sub(start.elapsed()));
+    }
+}
+
+/// The buzzer with the sound of `settings`.
+fn synth(settings: &Settings, sample_rate: u32) -> Synth {
+    Synth::new(
+        sample_rate,
+        settings.audio.waveform.unwrap_or_default(),
+        settings.audio.tone.unwrap_or(audio::TONE_FREQUENCY),
+        settings.audio.volume.unwrap_or(audio::VOLUME),
+    )
+}
+
+/// The file name of `path` for the status line, the directory would hardly
+/// fit.
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    )
+}
 
-        chip8.decrease_timers();
+/// Write all the keys of `keymap` in the format of the configuration file.
+fn save_keymap(path: &Path, keymap: &Keymap) -> io::Result<()> {
+    let mut data = "[keys]\n".to_string();
+    for (hex_key, codes) in keymap.bindings() {
+        let codes = codes
+            .iter()
+            .map(|c| format!("\"{}\"", c))
+            .collect::<Vec<_>>();
+        data += &format!("{:X} = [{}]\n", hex_key, codes.join(", "));
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, data)
+}
+
+impl App {
+    /// The settings given on the command line, they take precedence over
+    /// the ones of the configuration file.
+    fn settings(&self) -> Settings {
+        Settings {
+            frequency: self.freq,
+            fast_forward: self.fast_forward,
+            slow_motion: self.slow_motion,
+            keymap: self.keymap.clone(),
+            gamepad: config::GamepadSettings {
+                dead_zone: self.dead_zone,
+                ..Default::default()
+            },
+            scaling: self.scaling,
+            anti_flicker: self.anti_flicker,
+            upscaler: self.upscaler,
+            filters: Some(self.filters.clone()).filter(|f| !f.is_empty()),
+            filter_scale: self.filter_scale,
+            palette: self.palette.clone(),
+            palette_file: self.palette_file.clone(),
+            bg: self.bg,
+            fg: self.fg,
+            save_state_dir: self.save_state_dir.clone(),
+            capture_dir: self.capture_dir.clone(),
+            capture_scale: self.capture_scale,
+            record_format: self.record_format,
+            assembler: self.assembler.clone(),
+            rom_dirs: Some(self.rom_dirs.clone()).filter(|d| !d.is_empty()),
+            ..Settings::default()
+        }
     }
 }
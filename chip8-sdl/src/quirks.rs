@@ -0,0 +1,95 @@
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+
+use chip8::headless::InputScript;
+use chip8::lockstep;
+use chip8::quirks::QUIRK_NAMES;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Quirks {
+    /// Rom to test
+    #[structopt(parse(from_os_str))]
+    rom: PathBuf,
+
+    /// Number of frames to run every quirks combination for
+    #[structopt(long = "frames", default_value = "1800")]
+    frames: usize,
+
+    /// Seed of the random number generator
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Script of the keys to press while running
+    #[structopt(long = "input", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Frequency of the emulator
+    #[structopt(short = "f", long = "frequency", default_value = "500")]
+    freq: usize,
+}
+
+pub fn run(opts: Quirks) {
+    let prog = fs::read(&opts.rom).expect("cannot read rom");
+
+    let script = match &opts.input {
+        Some(path) => {
+            let script = fs::read_to_string(path).expect("cannot read input script");
+            InputScript::parse(&script).unwrap_or_else(|e| panic!("invalid input script: {}", e))
+        }
+        None => InputScript::default(),
+    };
+
+    // crashes are part of the report, don't print them as they happen
+    panic::set_hook(Box::new(|_| {}));
+    let report = lockstep::run(&prog, opts.seed, &script, opts.frames, opts.freq / 60);
+    let _ = panic::take_hook();
+
+    let report = report.expect("rom too big");
+
+    println!("first divergence from the default quirks:");
+    for (i, name) in QUIRK_NAMES.iter().enumerate() {
+        match report.quirk_divergence(i) {
+            Some(d) => {
+                let instr = d.instr.map_or("???".to_string(), |i| i.to_string());
+                println!(
+                    "  {:<10} frame {} cycle {} at {:#05X}: {}",
+                    name, d.frame, d.cycle, d.pc, instr
+                );
+            }
+            None => println!("  {:<10} never", name),
+        }
+    }
+
+    println!("\ncombinations:");
+    println!("  {}  outcome", QUIRK_NAMES.join(" "));
+    for c in &report.combinations {
+        let mut row = String::new();
+        for (i, name) in QUIRK_NAMES.iter().enumerate() {
+            let flag = if c.quirks.get(i) { "on" } else { "off" };
+            row += &format!("{:<w$} ", flag, w = name.len());
+        }
+
+        println!("  {} {}", row, c.outcome);
+    }
+
+    match report.suggested {
+        Some(quirks) => {
+            let enabled = QUIRK_NAMES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| quirks.get(*i))
+                .map(|(_, n)| *n)
+                .collect::<Vec<_>>();
+
+            if enabled.is_empty() {
+                println!("\nsuggested quirks: none");
+            } else {
+                println!("\nsuggested quirks: {}", enabled.join(", "));
+            }
+        }
+        None => println!("\nno quirks combination runs the rom sanely"),
+    }
+}
@@ -0,0 +1,229 @@
+use chip8::input::{Keymap, KEYPAD, PRESETS};
+
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::text::{draw_text, GLYPH_HEIGHT};
+
+// the labels of every key are cut to fit 4 keys per line
+const MAX_LABELS_LEN: usize = 9;
+
+/// Screen to bind the host keys to the hex keys, the emulation is paused
+/// while it's open.
+pub struct RemapMenu {
+    open: bool,
+    /// Index in `KEYPAD` of the hex key being bound.
+    selected: usize,
+    /// Index in `PRESETS` of the last preset picked.
+    preset: Option<usize>,
+}
+
+impl RemapMenu {
+    pub fn new() -> Self {
+        RemapMenu {
+            open: false,
+            selected: 0,
+            preset: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.selected = 0;
+    }
+
+    /// Handle a key pressed while the menu is open: Enter moves to the next
+    /// hex key, Backspace clears the current one, Tab cycles through the
+    /// presets and any other key is bound to the current hex key.
+    pub fn keydown(&mut self, kc: Keycode, scancode: Option<Scancode>, keymap: &mut Keymap) {
+        let hex_key = KEYPAD[self.selected];
+
+        match kc {
+            Keycode::Return => self.selected = (self.selected + 1) % KEYPAD.len(),
+            Keycode::Backspace => keymap.clear(hex_key),
+            Keycode::Tab => {
+                let i = self.preset.map_or(0, |i| (i + 1) % PRESETS.len());
+                *keymap = Keymap::preset(PRESETS[i]).unwrap();
+                self.preset = Some(i);
+            }
+            _ => {
+                if let Some(code) = scancode.and_then(code) {
+                    keymap.bind(hex_key, code);
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, keymap: &Keymap) {
+        let (w, h) = canvas.output_size().unwrap();
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 0xD0));
+        canvas.fill_rect(Rect::new(0, 0, w, h)).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        let mut lines = vec!["REMAP KEYS".to_string(), String::new()];
+
+        for (y, hex_keys) in KEYPAD.chunks(4).enumerate() {
+            let cells = hex_keys
+                .iter()
+                .enumerate()
+                .map(|(x, hex_key)| {
+                    let cursor = if y * 4 + x == self.selected { '>' } else { ' ' };
+                    let labels = keymap
+                        .keys(*hex_key)
+                        .iter()
+                        .map(|c| keymap.label(c))
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    let labels = labels.chars().take(MAX_LABELS_LEN).collect::<String>();
+
+                    format!(
+                        "{}{:X} {:<width$}",
+                        cursor,
+                        hex_key,
+                        labels,
+                        width = MAX_LABELS_LEN
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            lines.push(cells.join(" "));
+        }
+
+        if let Some(i) = self.preset {
+            lines.push(String::new());
+            lines.push(format!("preset: {}", PRESETS[i]));
+        }
+
+        lines.push(String::new());
+        lines.push("press a key to bind it".to_string());
+        lines.push("enter next   backspace clear".to_string());
+        lines.push("tab next preset".to_string());
+
+        let scale = 2;
+        for (i, line) in lines.iter().enumerate() {
+            let y = 8 + i as i32 * (GLYPH_HEIGHT + 3) * scale as i32;
+            draw_text(canvas, 8, y, scale, Color::RGB(0xFF, 0xFF, 0xFF), line);
+        }
+    }
+}
+
+/// The name of the key at the position of `scancode`, see `chip8::input`.
+pub fn code(scancode: Scancode) -> Option<&'static str> {
+    use Scancode::*;
+
+    let code = match scancode {
+        A => "KeyA",
+        B => "KeyB",
+        C => "KeyC",
+        D => "KeyD",
+        E => "KeyE",
+        F => "KeyF",
+        G => "KeyG",
+        H => "KeyH",
+        I => "KeyI",
+        J => "KeyJ",
+        K => "KeyK",
+        L => "KeyL",
+        M => "KeyM",
+        N => "KeyN",
+        O => "KeyO",
+        P => "KeyP",
+        Q => "KeyQ",
+        R => "KeyR",
+        S => "KeyS",
+        T => "KeyT",
+        U => "KeyU",
+        V => "KeyV",
+        W => "KeyW",
+        X => "KeyX",
+        Y => "KeyY",
+        Z => "KeyZ",
+        Num0 => "Digit0",
+        Num1 => "Digit1",
+        Num2 => "Digit2",
+        Num3 => "Digit3",
+        Num4 => "Digit4",
+        Num5 => "Digit5",
+        Num6 => "Digit6",
+        Num7 => "Digit7",
+        Num8 => "Digit8",
+        Num9 => "Digit9",
+        Kp0 => "Numpad0",
+        Kp1 => "Numpad1",
+        Kp2 => "Numpad2",
+        Kp3 => "Numpad3",
+        Kp4 => "Numpad4",
+        Kp5 => "Numpad5",
+        Kp6 => "Numpad6",
+        Kp7 => "Numpad7",
+        Kp8 => "Numpad8",
+        Kp9 => "Numpad9",
+        KpDivide => "NumpadDivide",
+        KpMultiply => "NumpadMultiply",
+        KpMinus => "NumpadSubtract",
+        KpPlus => "NumpadAdd",
+        KpEnter => "NumpadEnter",
+        KpPeriod => "NumpadDecimal",
+        Up => "ArrowUp",
+        Down => "ArrowDown",
+        Left => "ArrowLeft",
+        Right => "ArrowRight",
+        Space => "Space",
+        Return => "Enter",
+        Tab => "Tab",
+        Backspace => "Backspace",
+        LShift => "ShiftLeft",
+        RShift => "ShiftRight",
+        LCtrl => "ControlLeft",
+        RCtrl => "ControlRight",
+        LAlt => "AltLeft",
+        RAlt => "AltRight",
+        LGui => "MetaLeft",
+        RGui => "MetaRight",
+        Minus => "Minus",
+        Equals => "Equal",
+        LeftBracket => "BracketLeft",
+        RightBracket => "BracketRight",
+        Backslash => "Backslash",
+        Semicolon => "Semicolon",
+        Apostrophe => "Quote",
+        Grave => "Backquote",
+        Comma => "Comma",
+        Period => "Period",
+        Slash => "Slash",
+        NonUsBackslash => "IntlBackslash",
+        Insert => "Insert",
+        Delete => "Delete",
+        Home => "Home",
+        End => "End",
+        PageUp => "PageUp",
+        PageDown => "PageDown",
+        CapsLock => "CapsLock",
+        Application => "ContextMenu",
+        Escape => "Escape",
+        F1 => "F1",
+        F2 => "F2",
+        F3 => "F3",
+        F4 => "F4",
+        F5 => "F5",
+        F6 => "F6",
+        F7 => "F7",
+        F8 => "F8",
+        F9 => "F9",
+        F10 => "F10",
+        F11 => "F11",
+        F12 => "F12",
+        _ => return None,
+    };
+
+    Some(code)
+}
@@ -0,0 +1,173 @@
+//! Rendering of the interpreter screen through a streaming texture scaled to
+//! the window by the renderer.
+
+use std::str::FromStr;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use chip8::display::Display;
+use chip8::filters::Pipeline;
+use chip8::framebuffer::{Dirty, Framebuffer};
+
+/// How the screen is fit into the window, what's left is letterboxed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    /// The largest whole multiple of the screen size, every pixel has the
+    /// same size.
+    Integer,
+    /// As large as possible keeping the aspect ratio.
+    Aspect,
+    /// The whole window, regardless of the aspect ratio.
+    Stretch,
+}
+
+pub const SCALINGS: [&str; 3] = ["integer", "aspect", "stretch"];
+
+pub struct Screen<'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    texture: Texture<'a>,
+    texture_size: (usize, usize),
+    /// Width and height of the display, it follows the resolution of the
+    /// interpreter.
+    size: (usize, usize),
+    pipeline: Pipeline,
+    scaling: Scaling,
+    bg: Color,
+    fg: Color,
+    /// Whether the texture has to be rendered again regardless of the
+    /// display, e.g. because the colors changed.
+    stale: bool,
+}
+
+impl<'a> Screen<'a> {
+    pub fn new(
+        texture_creator: &'a TextureCreator<WindowContext>,
+        pipeline: Pipeline,
+        scaling: Scaling,
+        bg: Color,
+        fg: Color,
+    ) -> Self {
+        let vram = Framebuffer::new();
+        let size = (vram.width(), vram.height());
+
+        Screen {
+            texture_creator,
+            texture: create_texture(texture_creator, size),
+            texture_size: size,
+            size,
+            pipeline,
+            scaling,
+            bg,
+            fg,
+            stale: true,
+        }
+    }
+
+    pub fn set_colors(&mut self, bg: Color, fg: Color) {
+        if (bg, fg) != (self.bg, self.fg) {
+            self.bg = bg;
+            self.fg = fg;
+            self.stale = true;
+        }
+    }
+
+    /// Render the display through the filters and upload it to the texture
+    /// if anything changed, returns whether it did.
+    pub fn update(&mut self, display: &Display, dirty: Dirty) -> bool {
+        let size = (display.width(), display.height());
+        if size == self.size && dirty.is_empty() && !self.stale {
+            return false;
+        }
+        self.size = size;
+        self.stale = false;
+
+        let rgba = |c: Color| [c.r, c.g, c.b, 0xFF];
+        let image = self
+            .pipeline
+            .render_display(display, rgba(self.bg), rgba(self.fg));
+
+        let texture_size = (image.width, image.height);
+        if texture_size != self.texture_size {
+            self.texture = create_texture(self.texture_creator, texture_size);
+            self.texture_size = texture_size;
+        }
+
+        self.texture
+            .update(None, &image.pixels, image.width * 4)
+            .expect("cannot update screen texture");
+
+        true
+    }
+
+    /// Draw the screen scaled to the whole canvas.
+    pub fn draw(&self, canvas: &mut Canvas<Window>) {
+        let (w, h) = canvas.output_size().unwrap();
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas
+            .copy(&self.texture, None, self.viewport(w, h))
+            .unwrap();
+    }
+
+    /// Where the screen goes in an output `w` by `h` pixels large.
+    fn viewport(&self, w: u32, h: u32) -> Rect {
+        let (sw, sh) = (self.size.0 as u32, self.size.1 as u32);
+
+        let (vw, vh) = match self.scaling {
+            Scaling::Stretch => (w, h),
+            Scaling::Integer => {
+                let k = (w / sw).min(h / sh).max(1);
+                (sw * k, sh * k)
+            }
+            Scaling::Aspect => {
+                if u64::from(w) * u64::from(sh) > u64::from(h) * u64::from(sw) {
+                    (h * sw / sh, h)
+                } else {
+                    (w, w * sh / sw)
+                }
+            }
+        };
+
+        Rect::new(
+            (w as i32 - vw as i32) / 2,
+            (h as i32 - vh as i32) / 2,
+            vw.max(1),
+            vh.max(1),
+        )
+    }
+}
+
+fn create_texture(
+    texture_creator: &TextureCreator<WindowContext>,
+    (w, h): (usize, usize),
+) -> Texture<'_> {
+    texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, w as u32, h as u32)
+        .expect("cannot create screen texture")
+}
+
+/// Parse a color in the `#RRGGBB` format.
+pub fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    chip8::db::parse_hex_color(s).ok_or_else(|| format!("invalid color {}, expected #RRGGBB", s))
+}
+
+pub fn to_color([r, g, b]: [u8; 3]) -> Color {
+    Color::RGB(r, g, b)
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "aspect" => Ok(Scaling::Aspect),
+            "stretch" => Ok(Scaling::Stretch),
+            _ => Err(format!("unknown scaling {}", s)),
+        }
+    }
+}
@@ -1,29 +1,45 @@
+use std::fs;
+use std::path::Path;
+
 use sdl2::audio::AudioCallback;
 
-pub struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+use chip8::audio::Synth;
+use chip8::db::RomDatabase;
+
+/// The buzzer, it plays whenever the audio device isn't paused.
+pub struct Buzzer {
+    synth: Synth,
 }
 
-impl SquareWave {
-    pub fn new(phase_inc: f32, phase: f32, volume: f32) -> Self {
-        SquareWave {
-            phase_inc,
-            phase,
-            volume,
-        }
+impl Buzzer {
+    pub fn new(synth: Synth) -> Self {
+        Buzzer { synth }
     }
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Buzzer {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = self.synth.sample(true);
         }
     }
 }
+
+/// Load the builtin rom database along with the entries of the given JSON or
+/// TOML file.
+pub fn load_rom_db(extra: Option<&Path>) -> RomDatabase {
+    let mut db = RomDatabase::builtin();
+
+    if let Some(path) = extra {
+        let data = fs::read_to_string(path).expect("cannot read rom database");
+        let extra = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => RomDatabase::from_toml(&data),
+            _ => RomDatabase::from_json(&data),
+        };
+        db.merge(extra.unwrap_or_else(|e| panic!("cannot load rom database: {}", e)));
+    }
+
+    db
+}
@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+use chip8::state::SaveState;
+use chip8::{Chip8, ProgramError};
+
+use rand::Rng;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::text::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// Extensions of the files built before being loaded, the others are roms.
+/// Octo sources need an assembler command, the rest are assembled with
+/// `chip8::asm` unless one is given.
+pub const SOURCE_EXTENSIONS: [&str; 3] = ["8o", "asm", "s"];
+
+/// How often the watched file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Program run until the first build succeeds, a jump to itself.
+pub const IDLE_PROGRAM: [u8; 2] = [0x12, 0x00];
+
+/// A range of RAM addresses, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamRange {
+    start: usize,
+    end: usize,
+}
+
+/// What survives a reload of the program, everything else starts over.
+pub struct Keep {
+    pub ram: Vec<RamRange>,
+    /// Whether V0 to VF and I are kept.
+    pub registers: bool,
+    /// State restored after loading the program, the program itself is
+    /// written over the RAM of the state.
+    pub state: Option<SaveState>,
+}
+
+/// Keeps an eye on the modification time of a file.
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> Self {
+        Watcher {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            checked: Instant::now(),
+        }
+    }
+
+    /// Whether the file changed since the last call, it's checked at most
+    /// every `POLL_INTERVAL`.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+}
+
+/// Read the rom at `path`, building it first if it's a source file. The
+/// `assembler` command has `{src}` and `{out}` replaced by the source file
+/// and the rom to build, e.g. `octo {src} {out}`.
+pub fn build(path: &Path, assembler: Option<&str>) -> Result<Vec<u8>, String> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+
+    if !SOURCE_EXTENSIONS.contains(&ext.as_str()) {
+        return fs::read(path).map_err(|e| e.to_string());
+    }
+
+    match assembler {
+        Some(assembler) => run_assembler(assembler, path),
+        None if ext == "8o" => Err("octo sources need an assembler, see --assembler".to_string()),
+        None => {
+            let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            chip8::asm::assemble(&src).map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn run_assembler(assembler: &str, src: &Path) -> Result<Vec<u8>, String> {
+    let out = std::env::temp_dir().join(format!("chip8-build-{}.ch8", std::process::id()));
+
+    let args = assembler
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{src}", &src.to_string_lossy())
+                .replace("{out}", &out.to_string_lossy())
+        })
+        .collect::<Vec<_>>();
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| "empty assembler command".to_string())?;
+
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", program, e))?;
+
+    if !output.status.success() {
+        let mut message = String::from_utf8_lossy(&output.stderr).into_owned();
+        message += &String::from_utf8_lossy(&output.stdout);
+        if message.trim().is_empty() {
+            message = format!("{} failed: {}", program, output.status);
+        }
+        return Err(message.trim().to_string());
+    }
+
+    let rom = fs::read(&out).map_err(|e| format!("cannot read built rom: {}", e))?;
+    let _ = fs::remove_file(&out);
+
+    Ok(rom)
+}
+
+/// Load `program` in place of the current one keeping what `keep` asks
+/// for, the program starts over from its first instruction unless a state
+/// is restored.
+pub fn reload<R: Rng>(
+    chip8: &mut Chip8<R>,
+    program: &[u8],
+    keep: &Keep,
+) -> Result<(), ProgramError> {
+    let registers = *chip8.registers();
+    let i_reg = chip8.i_reg();
+    let ram = chip8.ram().to_vec();
+
+    chip8.load_program(program)?;
+
+    if let Some(state) = &keep.state {
+        chip8.load_state(state);
+
+        let (addr, program) = chip8.program();
+        let program = program.to_vec();
+        for (i, b) in program.into_iter().enumerate() {
+            chip8.poke(addr + i, b);
+        }
+    }
+
+    if keep.registers {
+        chip8.set_registers(registers, i_reg);
+    }
+
+    for range in &keep.ram {
+        for (addr, b) in ram.iter().enumerate().take(range.end + 1).skip(range.start) {
+            chip8.poke(addr, *b);
+        }
+    }
+
+    Ok(())
+}
+
+/// Draw the error of the last build over the top of the screen.
+pub fn draw_error(canvas: &mut Canvas<Window>, error: &str) {
+    let (w, _) = canvas.output_size().unwrap();
+    let scale = 2;
+    let line_height = (GLYPH_HEIGHT + 3) * scale;
+    let columns = ((w as i32 - 16) / ((GLYPH_WIDTH + 1) * scale)).max(1) as usize;
+
+    // wrap the long lines to the width of the window
+    let mut lines = vec!["BUILD FAILED".to_string()];
+    for line in error.lines() {
+        let chars = line.replace('\t', "    ").chars().collect::<Vec<_>>();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        for chunk in chars.chunks(columns) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+
+    let height = 16 + lines.len() as i32 * line_height;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0x40, 0, 0, 0xD0));
+    canvas.fill_rect(Rect::new(0, 0, w, height as u32)).unwrap();
+    canvas.set_blend_mode(BlendMode::None);
+
+    for (i, line) in lines.iter().enumerate() {
+        let y = 8 + i as i32 * line_height;
+        draw_text(
+            canvas,
+            8,
+            y,
+            scale as u32,
+            Color::RGB(0xFF, 0xFF, 0xFF),
+            line,
+        );
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FromStr for RamRange {
+    type Err = String;
+
+    /// Parse a range of addresses like `0x300-0x3FF` or a single address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid RAM range {}, expected one like 0x300-0x3FF", s);
+        let addr = |a: &str| {
+            let a = a.trim();
+            let a = a.strip_prefix("0x").unwrap_or(a);
+            usize::from_str_radix(a, 16)
+                .ok()
+                .filter(|a| *a < chip8::chip8::RAM_SIZE)
+        };
+
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (addr(start), addr(end)),
+            None => (addr(s), addr(s)),
+        };
+
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => Ok(RamRange { start, end }),
+            _ => Err(err()),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chip8::cheats::{Cheat, CheatFile, CheatKind, MemorySearch, SearchFilter};
+use chip8::Chip8;
+
+use rand::Rng;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{Document, Element, HtmlElement, HtmlInputElement, HtmlTextAreaElement};
+
+// candidates are listed only when they're few enough to be useful
+const MAX_LISTED_CANDIDATES: usize = 8;
+
+/// Create the panel where a cheat file can be pasted, the cheats for the
+/// current rom toggled and the RAM searched for new ones.
+pub fn cheats_panel<R: Rng + 'static>(
+    document: &Document,
+    rom_sha1: String,
+    cheats: Rc<RefCell<Vec<Cheat>>>,
+    chip8: Rc<RefCell<Chip8<R>>>,
+) -> Result<Element, JsValue> {
+    let panel = document.create_element("div")?;
+    panel.set_id("cheats");
+
+    let textarea = document
+        .create_element("textarea")?
+        .dyn_into::<HtmlTextAreaElement>()?;
+    textarea.set_class_name("nes-textarea");
+    textarea.set_placeholder("Paste a cheat file here");
+    panel.append_child(&textarea)?;
+
+    let list = document.create_element("div")?;
+
+    let refresh = {
+        let (document, list, cheats) = (document.clone(), list.clone(), cheats.clone());
+
+        Rc::new(move || {
+            list.set_text_content(None);
+            if cheats.borrow().is_empty() {
+                list.set_text_content(Some("No cheats for this game"));
+            }
+
+            for (i, cheat) in cheats.borrow().iter().enumerate() {
+                let entry = cheat_entry(&document, i, cheat, cheats.clone()).unwrap();
+                list.append_child(&entry).unwrap();
+            }
+        })
+    };
+
+    let load = {
+        let (list, cheats, refresh) = (list.clone(), cheats.clone(), refresh.clone());
+        button(document, "Load cheats", move || {
+            let file = match CheatFile::parse(&textarea.value()) {
+                Ok(file) => file,
+                Err(e) => {
+                    list.set_text_content(Some(&format!("Cannot load cheats: {}", e)));
+                    return;
+                }
+            };
+
+            *cheats.borrow_mut() = file.cheats(&rom_sha1).to_vec();
+            refresh();
+        })?
+    };
+
+    panel.append_child(&load)?;
+    panel.append_child(&list)?;
+    let search = search_panel(document, cheats, chip8, refresh)?;
+    panel.append_child(&search)?;
+
+    Ok(panel)
+}
+
+/// Create the buttons narrowing down the addresses of the RAM holding a
+/// value, the ones left can be frozen as new cheats.
+fn search_panel<R: Rng + 'static>(
+    document: &Document,
+    cheats: Rc<RefCell<Vec<Cheat>>>,
+    chip8: Rc<RefCell<Chip8<R>>>,
+    refresh_cheats: Rc<dyn Fn()>,
+) -> Result<Element, JsValue> {
+    let panel = document.create_element("div")?;
+    panel.set_id("memory-search");
+
+    let value = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    value.set_class_name("nes-input");
+    value.set_placeholder("Hex value");
+
+    let results = document.create_element("div")?;
+    results.set_text_content(Some("No search in progress"));
+
+    let search = Rc::new(RefCell::new(None::<MemorySearch>));
+
+    let show = {
+        let (document, results, search, chip8) = (
+            document.clone(),
+            results.clone(),
+            search.clone(),
+            chip8.clone(),
+        );
+
+        Rc::new(move || {
+            let search = search.borrow();
+            let search = match &*search {
+                Some(search) => search,
+                None => return,
+            };
+
+            let candidates = search.candidates();
+            let mut lines = vec![format!("{} candidates", candidates.len())];
+            if candidates.len() <= MAX_LISTED_CANDIDATES {
+                for addr in candidates {
+                    let value = chip8.borrow().ram()[*addr];
+                    lines.push(format!("{:#05X} = {:02X}", addr, value));
+                }
+            }
+
+            results.set_text_content(None);
+            for line in lines {
+                let div = document.create_element("div").unwrap();
+                div.set_text_content(Some(&line));
+                results.append_child(&div).unwrap();
+            }
+        })
+    };
+
+    let new_search = {
+        let (search, chip8, show) = (search.clone(), chip8.clone(), show.clone());
+        button(document, "New search", move || {
+            *search.borrow_mut() = Some(MemorySearch::new(chip8.borrow().ram()));
+            show();
+        })?
+    };
+    panel.append_child(&new_search)?;
+
+    let filters: [(&str, Option<SearchFilter>); 5] = [
+        ("Equal to", None),
+        ("Changed", Some(SearchFilter::Changed)),
+        ("Unchanged", Some(SearchFilter::Unchanged)),
+        ("Increased", Some(SearchFilter::Increased)),
+        ("Decreased", Some(SearchFilter::Decreased)),
+    ];
+    for (text, filter) in filters {
+        let (search, chip8, show, input) =
+            (search.clone(), chip8.clone(), show.clone(), value.clone());
+
+        let filter_button = button(document, text, move || {
+            // the value is only needed by the equality filter
+            let filter = match filter {
+                Some(filter) => filter,
+                None => {
+                    let v = input.value();
+                    let v = v.trim();
+                    match u8::from_str_radix(v.strip_prefix("0x").unwrap_or(v), 16) {
+                        Ok(v) => SearchFilter::Equal(v),
+                        Err(_) => return,
+                    }
+                }
+            };
+
+            if let Some(search) = &mut *search.borrow_mut() {
+                search.filter(chip8.borrow().ram(), filter);
+            }
+            show();
+        })?;
+
+        panel.append_child(&filter_button)?;
+        if filter.is_none() {
+            panel.append_child(&value)?;
+        }
+    }
+
+    let freeze = {
+        let search = search.clone();
+        button(document, "Freeze found", move || {
+            let search = search.borrow();
+            let candidates = search.as_ref().map_or(&[][..], |s| s.candidates());
+            if candidates.len() > MAX_LISTED_CANDIDATES {
+                return;
+            }
+
+            for &addr in candidates {
+                let value = chip8.borrow().ram()[addr];
+                let name = format!("Freeze {:#05X}", addr);
+
+                let mut cheat = Cheat::new(name, CheatKind::Freeze, vec![(addr, value)]);
+                cheat.set_enabled(true);
+                cheats.borrow_mut().push(cheat);
+            }
+            refresh_cheats();
+        })?
+    };
+    panel.append_child(&freeze)?;
+    panel.append_child(&results)?;
+
+    Ok(panel)
+}
+
+fn button<F: FnMut() + 'static>(
+    document: &Document,
+    text: &str,
+    on_click: F,
+) -> Result<HtmlElement, JsValue> {
+    let button = document
+        .create_element("button")?
+        .dyn_into::<HtmlElement>()?;
+    button.set_class_name("nes-btn");
+    button.set_inner_text(text);
+
+    let on_click = Closure::wrap(Box::new(on_click) as Box<dyn FnMut()>);
+    button.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    Ok(button)
+}
+
+fn cheat_entry(
+    document: &Document,
+    i: usize,
+    cheat: &Cheat,
+    cheats: Rc<RefCell<Vec<Cheat>>>,
+) -> Result<Element, JsValue> {
+    let label = document.create_element("label")?;
+
+    let checkbox = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    checkbox.set_type("checkbox");
+    checkbox.set_class_name("nes-checkbox");
+    checkbox.set_checked(cheat.enabled());
+
+    let name = document.create_element("span")?;
+    name.set_text_content(Some(&cheat.name));
+
+    label.append_child(&checkbox)?;
+    label.append_child(&name)?;
+
+    let on_change = Closure::wrap(Box::new(move |e: web_sys::Event| {
+        let checkbox = e.target().unwrap().dyn_into::<HtmlInputElement>().unwrap();
+
+        cheats.borrow_mut()[i].set_enabled(checkbox.checked());
+    }) as Box<dyn FnMut(web_sys::Event)>);
+
+    checkbox.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+    on_change.forget();
+
+    Ok(label)
+}
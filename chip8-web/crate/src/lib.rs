@@ -1,13 +1,27 @@
 mod beeper;
+mod cheats;
+mod remap;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-use wasm_bindgen;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
+use wasm_bindgen::{Clamped, JsCast};
 
-use web_sys::KeyboardEvent;
+use web_sys::{
+    Blob, BlobPropertyBag, CanvasRenderingContext2d, Element, ImageData, KeyboardEvent, Url,
+};
+
+use rand::Rng;
+
+use chip8::capture;
+use chip8::db::{RomDatabase, RomInfo};
+use chip8::display::{self as chip8_display, AntiFlicker, Display, ANTI_FLICKER_MODES};
+use chip8::filters::Pipeline;
+use chip8::framebuffer::Dirty;
+use chip8::input::Keymap;
+use chip8::palette::{rgba, Palette};
+use chip8::Chip8;
 
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
@@ -41,8 +55,17 @@ const GAMES: [(&str, &[u8]); 23] = [
 
 const FREQ: usize = 500;
 
-const KEY_MAPPINGS: [&str; 16] = [
-    "x", "1", "2", "3", "q", "w", "e", "a", "s", "d", "z", "c", "4", "r", "f", "v",
+/// How many times larger than the screen the screenshots can be.
+const SCREENSHOT_SCALES: [usize; 5] = [2, 4, 8, 16, 32];
+
+/// Filters that can be picked for the screen, as comma separated upscaler
+/// and filters.
+const FILTER_PRESETS: [(&str, &str); 5] = [
+    ("none", ""),
+    ("scanlines", "scanlines"),
+    ("lcd", "grid"),
+    ("crt", "scanlines,bloom,barrel"),
+    ("scale2x", "scale2x"),
 ];
 
 #[wasm_bindgen]
@@ -104,8 +127,137 @@ fn play_game(rom: &[u8]) -> Result<(), JsValue> {
         .unwrap()
         .append_child(&canvas)?;
 
-    let chip8 = chip8::Chip8::with_program(rand::thread_rng(), rom).unwrap();
+    let mut chip8 = chip8::Chip8::with_program(rand::thread_rng(), rom).unwrap();
+
+    let mut cycles_per_frame = FREQ / 60;
+    let mut palette = Palette::default();
+
+    if let Some(info) = RomDatabase::builtin().lookup(rom) {
+        chip8.set_quirks(info.quirks);
+        cycles_per_frame = info.instructions_per_frame.unwrap_or(cycles_per_frame);
+
+        if let Some(p) = info.palette() {
+            palette = p;
+        }
+
+        let rom_info = rom_info_panel(&document, info)?;
+        document
+            .get_element_by_id("game-container")
+            .unwrap()
+            .append_child(&rom_info)?;
+    }
+
     let chip8 = Rc::new(RefCell::new(chip8));
+    let display = Rc::new(RefCell::new(Display::new(AntiFlicker::Off)));
+    let pipeline = Rc::new(RefCell::new(Pipeline::default()));
+    let redraw = Rc::new(Cell::new(false));
+
+    // the palette of the rom comes first unless it's a builtin one
+    let mut palettes = Palette::builtin();
+    let palette_ix = match palettes.iter().position(|p| *p == palette) {
+        Some(i) => i,
+        None => {
+            palettes.insert(0, palette.clone());
+            0
+        }
+    };
+    let palette = Rc::new(RefCell::new(palette));
+
+    let settings = [
+        {
+            let display = display.clone();
+            labeled_select(
+                &document,
+                "anti-flicker",
+                "Anti-flicker",
+                &ANTI_FLICKER_MODES,
+                0,
+                move |mode| display.borrow_mut().set_mode(mode.parse().unwrap()),
+            )?
+        },
+        {
+            let (pipeline, redraw) = (pipeline.clone(), redraw.clone());
+            let names = FILTER_PRESETS.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+            labeled_select(&document, "filters", "Filters", &names, 0, move |name| {
+                let (_, stages) = FILTER_PRESETS.iter().find(|(n, _)| *n == name).unwrap();
+                *pipeline.borrow_mut() = parse_filter_preset(stages);
+                redraw.set(true);
+            })?
+        },
+        {
+            let palette = palette.clone();
+            let names = palettes.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+            labeled_select(
+                &document,
+                "palette",
+                "Palette",
+                &names,
+                palette_ix,
+                move |name| {
+                    *palette.borrow_mut() =
+                        palettes.iter().find(|p| p.name == name).unwrap().clone();
+                },
+            )?
+        },
+    ];
+
+    for setting in &settings {
+        document
+            .get_element_by_id("game-container")
+            .unwrap()
+            .append_child(setting)?;
+    }
+
+    let screenshot_scale = Rc::new(Cell::new(SCREENSHOT_SCALES[2]));
+    let screenshot = [
+        screenshot_button(
+            &document,
+            chip8.clone(),
+            display.clone(),
+            pipeline.clone(),
+            palette.clone(),
+            screenshot_scale.clone(),
+        )?,
+        {
+            let names = SCREENSHOT_SCALES
+                .iter()
+                .map(|s| format!("{}x", s))
+                .collect::<Vec<_>>();
+            labeled_select(
+                &document,
+                "screenshot-scale",
+                "Screenshot scale",
+                &names,
+                2,
+                move |name| screenshot_scale.set(name.trim_end_matches('x').parse().unwrap()),
+            )?
+        },
+    ];
+    for element in &screenshot {
+        document
+            .get_element_by_id("game-container")
+            .unwrap()
+            .append_child(element)?;
+    }
+
+    let cheats = Rc::new(RefCell::new(vec![]));
+    let cheats_panel = cheats::cheats_panel(
+        &document,
+        chip8::hash::sha1(rom),
+        cheats.clone(),
+        chip8.clone(),
+    )?;
+    document
+        .get_element_by_id("game-container")
+        .unwrap()
+        .append_child(&cheats_panel)?;
+
+    let keymap = Rc::new(RefCell::new(Keymap::default()));
+    let remap_panel = remap::remap_panel(&document, chip8::hash::sha1(rom), keymap.clone())?;
+    document
+        .get_element_by_id("game-container")
+        .unwrap()
+        .append_child(&remap_panel)?;
 
     let context = canvas
         .get_context("2d")
@@ -114,15 +266,19 @@ fn play_game(rom: &[u8]) -> Result<(), JsValue> {
         .dyn_into::<web_sys::CanvasRenderingContext2d>()
         .unwrap();
 
-    context.fill_rect(0.0, 0.0, 640.0, 320.0);
+    // the canvas keeps what has been drawn, only the rows that changed
+    // are drawn again at every frame unless the colors change
+    let mut colors = None;
 
     {
-        let chip8 = chip8.clone();
+        let (chip8, keymap) = (chip8.clone(), keymap.clone());
         let on_key_press = Closure::wrap(Box::new(move |e: KeyboardEvent| {
-            let hex_key = KEY_MAPPINGS.iter().position(|m| *m == e.key());
+            if is_typing(&e) {
+                return;
+            }
 
-            if let Some(hex_key) = hex_key {
-                chip8.borrow_mut().keypress(hex_key as u8);
+            for hex_key in keymap.borrow().hex_keys(&e.code()) {
+                chip8.borrow_mut().keypress(hex_key);
             }
         }) as Box<dyn FnMut(KeyboardEvent)>);
 
@@ -131,12 +287,14 @@ fn play_game(rom: &[u8]) -> Result<(), JsValue> {
     }
 
     {
-        let chip8 = chip8.clone();
+        let (chip8, keymap) = (chip8.clone(), keymap.clone());
         let on_key_release = Closure::wrap(Box::new(move |e: KeyboardEvent| {
-            let hex_key = KEY_MAPPINGS.iter().position(|m| *m == e.key());
+            if is_typing(&e) {
+                return;
+            }
 
-            if let Some(hex_key) = hex_key {
-                chip8.borrow_mut().keyrelease(hex_key as u8);
+            for hex_key in keymap.borrow().hex_keys(&e.code()) {
+                chip8.borrow_mut().keyrelease(hex_key);
             }
         }) as Box<dyn FnMut(KeyboardEvent)>);
 
@@ -149,20 +307,52 @@ fn play_game(rom: &[u8]) -> Result<(), JsValue> {
     register_animation_frame_loop(move || {
         let mut chip8 = chip8.borrow_mut();
 
-        for _ in 0..FREQ / 60 {
-            chip8.emulate_cycle();
+        for cheat in cheats.borrow_mut().iter_mut() {
+            cheat.apply(&mut chip8);
         }
 
-        for (y, x, p) in chip8.pixels() {
-            let style = if *p == 0 {
-                JsValue::from_str("black")
-            } else {
-                JsValue::from_str("white")
-            };
+        for _ in 0..cycles_per_frame {
+            chip8.emulate_cycle();
+        }
 
-            context.set_fill_style(&style);
+        for e in chip8.take_events() {
+            web_sys::console::warn_1(&JsValue::from_str(&e.to_string()));
+        }
 
-            context.fill_rect(x as f64 * 10.0, y as f64 * 10.0, 10.0, 10.0);
+        let dirty = chip8.take_dirty();
+        let mut display = display.borrow_mut();
+        let dirty = display.update(chip8.framebuffer(), dirty);
+
+        let (bg, fg) = {
+            let palette = palette.borrow();
+            (
+                rgba(palette.background_while(chip8.beep())),
+                rgba(palette.foreground()),
+            )
+        };
+
+        let recolored = colors.replace((bg, fg)) != Some((bg, fg));
+        let dirty = if redraw.replace(false) || recolored {
+            Dirty::all()
+        } else {
+            dirty
+        };
+
+        let mut pipeline = pipeline.borrow_mut();
+        if pipeline.is_identity() {
+            draw_dirty(&context, &display, dirty, bg, fg);
+        } else if !dirty.is_empty() {
+            // fill the whole canvas whatever the resolution
+            pipeline.scale = 640 / display.width();
+
+            let image = pipeline.render_display(&display, bg, fg);
+            let data = ImageData::new_with_u8_clamped_array_and_sh(
+                Clamped(&image.pixels),
+                image.width as u32,
+                image.height as u32,
+            )
+            .unwrap();
+            context.put_image_data(&data, 0.0, 0.0).unwrap();
         }
 
         if chip8.beep() {
@@ -177,6 +367,218 @@ fn play_game(rom: &[u8]) -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Create a button downloading a PNG of the screen rendered like the
+/// headless captures, with the filters and palette in use at the picked
+/// scale.
+fn screenshot_button<R: Rng + 'static>(
+    document: &web_sys::Document,
+    chip8: Rc<RefCell<Chip8<R>>>,
+    display: Rc<RefCell<Display>>,
+    pipeline: Rc<RefCell<Pipeline>>,
+    palette: Rc<RefCell<Palette>>,
+    scale: Rc<Cell<usize>>,
+) -> Result<Element, JsValue> {
+    let button = document
+        .create_element("button")?
+        .dyn_into::<web_sys::HtmlButtonElement>()?;
+    button.set_class_name("nes-btn setting");
+    button.set_text_content(Some("Screenshot"));
+
+    let document = document.clone();
+    let on_click = Closure::wrap(Box::new(move || {
+        let mut pipeline = pipeline.borrow().clone();
+        pipeline.scale = scale.get();
+
+        let img = capture::render(
+            &pipeline,
+            &display.borrow(),
+            &palette.borrow(),
+            chip8.borrow().beep(),
+        );
+        let mut png = vec![];
+        img.write_png(&mut png).unwrap();
+
+        let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(&png[..]));
+        let options = BlobPropertyBag::new();
+        options.set_type("image/png");
+        let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options).unwrap();
+        let url = Url::create_object_url_with_blob(&blob).unwrap();
+
+        let link = document
+            .create_element("a")
+            .unwrap()
+            .dyn_into::<web_sys::HtmlAnchorElement>()
+            .unwrap();
+        link.set_href(&url);
+        link.set_download("chip8.png");
+        link.click();
+
+        Url::revoke_object_url(&url).unwrap();
+    }) as Box<dyn FnMut()>);
+
+    button.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    Ok(button.into())
+}
+
+/// Whether the key goes to a text field, e.g. the cheat search value,
+/// rather than to the game. Checkboxes keep the keys going to the game.
+fn is_typing(e: &KeyboardEvent) -> bool {
+    let target = match e.target() {
+        Some(target) => target,
+        None => return false,
+    };
+
+    if let Some(input) = target.dyn_ref::<web_sys::HtmlInputElement>() {
+        input.type_() == "text"
+    } else {
+        target.dyn_ref::<web_sys::HtmlTextAreaElement>().is_some()
+    }
+}
+
+/// Create a select with the given options calling `on_change` with the
+/// picked one.
+fn labeled_select<S: AsRef<str>, F: FnMut(&str) + 'static>(
+    document: &web_sys::Document,
+    id: &str,
+    text: &str,
+    options: &[S],
+    selected: usize,
+    mut on_change: F,
+) -> Result<Element, JsValue> {
+    let label = document.create_element("label")?;
+    label.set_id(id);
+    label.set_class_name("setting");
+    label.set_text_content(Some(text));
+
+    let container = document.create_element("div")?;
+    container.set_class_name("nes-select");
+
+    let select = document
+        .create_element("select")?
+        .dyn_into::<web_sys::HtmlSelectElement>()?;
+
+    for option in options {
+        select.add_with_html_option_element(&web_sys::HtmlOptionElement::new_with_text(
+            option.as_ref(),
+        )?)?;
+    }
+    select.set_selected_index(selected as i32);
+
+    let on_change = Closure::wrap(Box::new(move |e: web_sys::Event| {
+        let select = e
+            .target()
+            .unwrap()
+            .dyn_into::<web_sys::HtmlSelectElement>()
+            .unwrap();
+
+        on_change(&select.value());
+    }) as Box<dyn FnMut(web_sys::Event)>);
+
+    select.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+    on_change.forget();
+
+    container.append_child(&select)?;
+    label.append_child(&container)?;
+
+    Ok(label)
+}
+
+/// Parse comma separated upscaler and filters of `FILTER_PRESETS`.
+fn parse_filter_preset(stages: &str) -> Pipeline {
+    let mut pipeline = Pipeline::default();
+
+    for stage in stages.split(',').filter(|s| !s.is_empty()) {
+        if let Ok(upscaler) = stage.parse() {
+            pipeline.upscaler = upscaler;
+        } else {
+            pipeline.filters.push(stage.parse().unwrap());
+        }
+    }
+
+    pipeline
+}
+
+/// Draw the dirty rows of the display as 10x10 pixels, one rectangle for the
+/// background of consecutive rows and one for every run of pixels with the
+/// same intensity.
+fn draw_dirty(
+    context: &CanvasRenderingContext2d,
+    display: &Display,
+    dirty: Dirty,
+    bg: [u8; 4],
+    fg: [u8; 4],
+) {
+    for rows in dirty.spans(display.height()) {
+        context.set_fill_style(&css_color(bg));
+        context.fill_rect(
+            0.0,
+            rows.start as f64 * 10.0,
+            display.width() as f64 * 10.0,
+            rows.len() as f64 * 10.0,
+        );
+
+        for y in rows {
+            let mut x = 0;
+            while x < display.width() {
+                let level = display.level(x, y);
+                let start = x;
+                while x < display.width() && display.level(x, y) == level {
+                    x += 1;
+                }
+
+                if level == 0 {
+                    continue;
+                }
+
+                context.set_fill_style(&css_color(chip8_display::mix(bg, fg, level)));
+                context.fill_rect(
+                    start as f64 * 10.0,
+                    y as f64 * 10.0,
+                    (x - start) as f64 * 10.0,
+                    10.0,
+                );
+            }
+        }
+    }
+}
+
+fn css_color(c: [u8; 4]) -> JsValue {
+    JsValue::from_str(&format!("rgb({}, {}, {})", c[0], c[1], c[2]))
+}
+
+/// Create the panel with the title of the game and what each key does.
+fn rom_info_panel(document: &web_sys::Document, info: &RomInfo) -> Result<Element, JsValue> {
+    let panel = document.create_element("div")?;
+    panel.set_id("rom-info");
+
+    let mut title = info.title.clone();
+    if let Some(author) = &info.author {
+        title += &format!(" by {}", author);
+    }
+    if let Some(year) = info.year {
+        title += &format!(" ({})", year);
+    }
+
+    let header = document.create_element("p")?;
+    header.set_text_content(Some(&title));
+    panel.append_child(&header)?;
+
+    let keys = document.create_element("ul")?;
+    keys.set_class_name("nes-list is-disc");
+
+    for (key, desc) in &info.keys {
+        let item = document.create_element("li")?;
+        item.set_text_content(Some(&format!("{}: {}", key, desc)));
+        keys.append_child(&item)?;
+    }
+
+    panel.append_child(&keys)?;
+
+    Ok(panel)
+}
+
 fn window() -> web_sys::Window {
     web_sys::window().expect("no global `window` exists")
 }
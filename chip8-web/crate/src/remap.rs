@@ -0,0 +1,189 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use chip8::input::{self, Keymap, KEYPAD, PRESETS};
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{Document, Element, HtmlElement, KeyboardEvent, Storage};
+
+/// Create the panel where the keys of the current rom are remapped, the
+/// keymap of every rom is kept in the local storage of the browser.
+pub fn remap_panel(
+    document: &Document,
+    rom_sha1: String,
+    keymap: Rc<RefCell<Keymap>>,
+) -> Result<Element, JsValue> {
+    let storage_key = format!("keymap-{}", rom_sha1);
+    if let Some(saved) = storage().and_then(|s| s.get_item(&storage_key).ok().flatten()) {
+        // a bad save is left alone until the keys are changed again
+        match Keymap::from_text(&saved) {
+            Ok(saved) => *keymap.borrow_mut() = saved,
+            Err(e) => web_sys::console::warn_1(&JsValue::from_str(&format!(
+                "cannot load the saved keys: {}",
+                e
+            ))),
+        }
+    }
+
+    let panel = document.create_element("div")?;
+    panel.set_id("remap");
+
+    let list = document.create_element("div")?;
+
+    // the hex key waiting for the next key pressed to be bound to it
+    let binding = Rc::new(Cell::new(None));
+
+    let refresh = {
+        let (document, list, keymap, binding) = (
+            document.clone(),
+            list.clone(),
+            keymap.clone(),
+            binding.clone(),
+        );
+
+        Rc::new(move || {
+            let keymap = keymap.borrow();
+
+            list.set_text_content(None);
+            for hex_key in &KEYPAD {
+                let waiting = binding.get() == Some(*hex_key);
+                let row = key_row(&document, *hex_key, &keymap, waiting).unwrap();
+                list.append_child(&row).unwrap();
+            }
+        })
+    };
+
+    // show the keys and save them once they've changed
+    let changed = {
+        let (keymap, refresh) = (keymap.clone(), refresh.clone());
+
+        Rc::new(move || {
+            refresh();
+
+            if let Some(storage) = storage() {
+                let _ = storage.set_item(&storage_key, &keymap.borrow().to_text());
+            }
+        })
+    };
+
+    let presets = {
+        let (keymap, changed) = (keymap.clone(), changed.clone());
+        crate::labeled_select(document, "keymap", "Keys", &PRESETS, 0, move |name| {
+            *keymap.borrow_mut() = Keymap::preset(name).unwrap();
+            changed();
+        })?
+    };
+
+    panel.append_child(&presets)?;
+    panel.append_child(&list)?;
+    refresh();
+
+    {
+        let (keymap, binding, refresh, changed) = (
+            keymap.clone(),
+            binding.clone(),
+            refresh.clone(),
+            changed.clone(),
+        );
+        let on_click = Closure::wrap(Box::new(move |e: web_sys::Event| {
+            let button = match e.target().and_then(|t| t.dyn_into::<HtmlElement>().ok()) {
+                Some(button) => button,
+                None => return,
+            };
+            let hex_key = match button
+                .get_attribute("data-key")
+                .and_then(|k| u8::from_str_radix(&k, 16).ok())
+            {
+                Some(hex_key) => hex_key,
+                None => return,
+            };
+
+            match button.get_attribute("data-action").as_deref() {
+                Some("bind") => {
+                    binding.set(Some(hex_key));
+                    refresh();
+                }
+                Some("clear") => {
+                    keymap.borrow_mut().clear(hex_key);
+                    changed();
+                }
+                _ => {}
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>);
+
+        list.add_event_listener_with_callback("click", on_click.as_ref().unchecked_ref())?;
+        on_click.forget();
+    }
+
+    {
+        let on_key_press = Closure::wrap(Box::new(move |e: KeyboardEvent| {
+            let hex_key = match binding.take() {
+                Some(hex_key) => hex_key,
+                None => return,
+            };
+
+            // keep the key from the game
+            e.stop_propagation();
+            e.prevent_default();
+
+            if input::is_code(&e.code()) {
+                keymap.borrow_mut().bind(hex_key, &e.code());
+            }
+            changed();
+        }) as Box<dyn FnMut(KeyboardEvent)>);
+
+        // in the capture phase to run before the handler of the game
+        document.add_event_listener_with_callback_and_bool(
+            "keydown",
+            on_key_press.as_ref().unchecked_ref(),
+            true,
+        )?;
+        on_key_press.forget();
+    }
+
+    Ok(panel)
+}
+
+fn key_row(
+    document: &Document,
+    hex_key: u8,
+    keymap: &Keymap,
+    waiting: bool,
+) -> Result<Element, JsValue> {
+    let row = document.create_element("div")?;
+    row.set_class_name("key");
+
+    let labels = document.create_element("span")?;
+    let text = if waiting {
+        "press a key...".to_string()
+    } else {
+        let keys = keymap.keys(hex_key);
+        keys.iter()
+            .map(|c| keymap.label(c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    labels.set_text_content(Some(&format!("{:X}: {}", hex_key, text)));
+
+    row.append_child(&labels)?;
+
+    for (action, text) in &[("bind", "Bind"), ("clear", "Clear")] {
+        let button = document
+            .create_element("button")?
+            .dyn_into::<HtmlElement>()?;
+        button.set_class_name("nes-btn");
+        button.set_inner_text(text);
+        button.set_attribute("data-key", &format!("{:X}", hex_key))?;
+        button.set_attribute("data-action", action)?;
+
+        row.append_child(&button)?;
+    }
+
+    Ok(row)
+}
+
+fn storage() -> Option<Storage> {
+    crate::window().local_storage().ok().flatten()
+}
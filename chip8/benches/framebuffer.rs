@@ -0,0 +1,172 @@
+//! Timings of sprite drawing and screen readout on the bundled games.
+//!
+//! Run with `cargo bench -p chip8`, it doesn't need any benchmark harness.
+
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chip8::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip8::framebuffer::Framebuffer;
+use chip8::headless::{InputScript, Runner, DEFAULT_CYCLES_PER_FRAME};
+
+const FRAMES: usize = 3000;
+const SPRITES: usize = 500_000;
+
+fn main() {
+    bench_draw_loop();
+
+    let games = Path::new(env!("CARGO_MANIFEST_DIR")).join("../games");
+    let mut games = fs::read_dir(games)
+        .expect("cannot read games")
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    games.sort();
+
+    // most games wait for a key before starting
+    let script =
+        InputScript::parse("30 5 down\n32 5 up\n60 4 down\n90 4 up\n120 6 down\n150 6 up\n")
+            .unwrap();
+
+    println!(
+        "\n{:<12} {:>12} {:>12} {:>12}",
+        "game", "emulation", "pixels()", "blit_rgba()"
+    );
+
+    let mut totals = [Duration::default(); 3];
+    for game in games {
+        let prog = fs::read(&game).unwrap();
+        let mut runner = Runner::new(&prog, 0, DEFAULT_CYCLES_PER_FRAME).unwrap();
+        let mut buf = vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT * 4];
+
+        let mut timings = [Duration::default(); 3];
+        for frame in 0..FRAMES {
+            script.apply(frame, runner.chip8_mut());
+
+            let start = Instant::now();
+            runner.run_frame();
+            timings[0] += start.elapsed();
+
+            // what the frontends used to do every frame
+            let start = Instant::now();
+            for (y, x, lit) in runner.chip8().pixels() {
+                let c = if lit { 0xFF } else { 0 };
+                let i = (y * DISPLAY_WIDTH + x) * 4;
+                buf[i..i + 4].copy_from_slice(&[c, c, c, 0xFF]);
+            }
+            black_box(&buf);
+            timings[1] += start.elapsed();
+
+            let start = Instant::now();
+            runner
+                .chip8()
+                .framebuffer()
+                .blit_rgba(&mut buf, [0, 0, 0, 0xFF], [0xFF; 4]);
+            black_box(&buf);
+            timings[2] += start.elapsed();
+        }
+
+        let name = game.file_name().unwrap().to_string_lossy();
+        println!(
+            "{:<12} {:>12?} {:>12?} {:>12?}",
+            name, timings[0], timings[1], timings[2]
+        );
+
+        for (t, d) in totals.iter_mut().zip(&timings) {
+            *t += *d;
+        }
+    }
+
+    println!(
+        "{:<12} {:>12?} {:>12?} {:>12?}",
+        "total", totals[0], totals[1], totals[2]
+    );
+}
+
+/// Sprites drawn all over the screen by the packed framebuffer and by the
+/// per pixel drawing it replaced, with and without clipping.
+fn bench_draw_loop() {
+    // the digits of the font, at positions crossing the edges now and then
+    #[rustfmt::skip]
+    let sprites = [
+        0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70,
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    ];
+    let stream = (0..SPRITES)
+        .map(|i| {
+            let start = (i % 4) * 5;
+            ((i * 3) % 70, (i * 7) % 36, &sprites[start..start + 5])
+        })
+        .collect::<Vec<_>>();
+
+    for clip in [false, true] {
+        let mut vram = [[0_u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        let start = Instant::now();
+        let mut collisions = 0;
+        for (x, y, sprite) in &stream {
+            collisions += usize::from(baseline_draw(&mut vram, *x, *y, sprite, clip));
+        }
+        let baseline = start.elapsed();
+        black_box(&vram);
+
+        let mut framebuffer = Framebuffer::new();
+        let start = Instant::now();
+        let mut packed_collisions = 0;
+        for (x, y, sprite) in &stream {
+            let (x, y) = (x % DISPLAY_WIDTH, y % DISPLAY_HEIGHT);
+            packed_collisions += usize::from(framebuffer.draw_sprite(x, y, sprite, clip));
+        }
+        let packed = start.elapsed();
+        black_box(&framebuffer);
+
+        assert_eq!(collisions, packed_collisions);
+        for (y, row) in vram.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                assert_eq!(*pixel == 1, framebuffer.get(x, y));
+            }
+        }
+
+        println!(
+            "draw {} sprites{}: per pixel {:?}, packed {:?} ({:.1}x)",
+            SPRITES,
+            if clip { " clipping" } else { "" },
+            baseline,
+            packed,
+            baseline.as_secs_f64() / packed.as_secs_f64()
+        );
+    }
+}
+
+/// The drawing of the screen before it was packed, a byte per pixel.
+fn baseline_draw(
+    vram: &mut [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    x: usize,
+    y: usize,
+    sprite: &[u8],
+    clip: bool,
+) -> bool {
+    let (x, y) = (x % DISPLAY_WIDTH, y % DISPLAY_HEIGHT);
+    let mut collision = 0;
+
+    for (i, sprite_row) in sprite.iter().enumerate() {
+        if clip && y + i >= DISPLAY_HEIGHT {
+            break;
+        }
+
+        let row = &mut vram[(y + i) % DISPLAY_HEIGHT];
+        for b in 0..8 {
+            if clip && x + b >= DISPLAY_WIDTH {
+                break;
+            }
+
+            let pixel = &mut row[(x + b) % DISPLAY_WIDTH];
+            let sprite_pixel = (sprite_row >> (7 - b)) & 0x1;
+
+            collision |= *pixel & sprite_pixel;
+            *pixel ^= sprite_pixel;
+        }
+    }
+
+    collision == 1
+}
@@ -0,0 +1,146 @@
+use std::ops::{BitOr, BitOrAssign, Range};
+
+use crate::chip8::RAM_SIZE;
+
+/// How a RAM byte has been accessed so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Access(u8);
+
+impl Access {
+    pub const NONE: Access = Access(0);
+
+    /// The byte has been fetched as part of an instruction.
+    pub const EXECUTED: Access = Access(1 << 0);
+    /// The byte is the first one of an executed instruction.
+    pub const OPCODE: Access = Access(1 << 1);
+    /// The byte has been read as part of a sprite by `Dxyn`.
+    pub const SPRITE_READ: Access = Access(1 << 2);
+    /// The byte has been loaded into a register by `Fx65`.
+    pub const REGS_READ: Access = Access(1 << 3);
+    /// The byte has been written by `Fx55`.
+    pub const REGS_WRITTEN: Access = Access(1 << 4);
+    /// The byte has been written by `Fx33`.
+    pub const BCD_WRITTEN: Access = Access(1 << 5);
+    /// The byte has been written after being executed.
+    pub const SELF_MODIFIED: Access = Access(1 << 6);
+
+    const DATA: Access = Access(
+        Access::SPRITE_READ.0
+            | Access::REGS_READ.0
+            | Access::REGS_WRITTEN.0
+            | Access::BCD_WRITTEN.0,
+    );
+
+    pub fn contains(self, other: Access) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Access) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Whether the byte has only been used as data and never executed.
+    pub fn is_data(self) -> bool {
+        self.intersects(Access::DATA) && !self.contains(Access::EXECUTED)
+    }
+}
+
+impl BitOr for Access {
+    type Output = Access;
+
+    fn bitor(self, rhs: Access) -> Access {
+        Access(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Access {
+    fn bitor_assign(&mut self, rhs: Access) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Number of times a RAM byte has been accessed, the counters wrap around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counts {
+    pub reads: u32,
+    pub writes: u32,
+    pub executes: u32,
+}
+
+/// Per byte record of how the RAM has been accessed by the running program.
+#[derive(Clone)]
+pub struct AccessMap {
+    accesses: [Access; RAM_SIZE],
+    counts: [Counts; RAM_SIZE],
+}
+
+impl AccessMap {
+    pub fn new() -> Self {
+        AccessMap {
+            accesses: [Access::NONE; RAM_SIZE],
+            counts: [Counts::default(); RAM_SIZE],
+        }
+    }
+
+    pub fn get(&self, addr: usize) -> Access {
+        self.accesses[addr]
+    }
+
+    pub fn counts(&self, addr: usize) -> Counts {
+        self.counts[addr]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Access)> + '_ {
+        self.accesses.iter().cloned().enumerate()
+    }
+
+    pub fn clear(&mut self) {
+        self.accesses = [Access::NONE; RAM_SIZE];
+        self.counts = [Counts::default(); RAM_SIZE];
+    }
+
+    pub(crate) fn execute(&mut self, pc: usize) {
+        self.accesses[pc] |= Access::EXECUTED | Access::OPCODE;
+        self.accesses[pc + 1] |= Access::EXECUTED;
+
+        for c in &mut self.counts[pc..pc + 2] {
+            c.executes = c.executes.wrapping_add(1);
+        }
+    }
+
+    pub(crate) fn read(&mut self, range: Range<usize>, access: Access) {
+        for addr in range {
+            self.accesses[addr] |= access;
+
+            let c = &mut self.counts[addr];
+            c.reads = c.reads.wrapping_add(1);
+        }
+    }
+
+    /// Mark the given range as written and return the addresses that have
+    /// been modified after being executed for the first time.
+    pub(crate) fn write(&mut self, range: Range<usize>, access: Access) -> Vec<usize> {
+        let mut self_modified = vec![];
+
+        for addr in range {
+            let a = &mut self.accesses[addr];
+            let c = &mut self.counts[addr];
+            c.writes = c.writes.wrapping_add(1);
+
+            if a.contains(Access::EXECUTED) && !a.contains(Access::SELF_MODIFIED) {
+                *a |= Access::SELF_MODIFIED;
+                self_modified.push(addr);
+            }
+
+            *a |= access;
+        }
+
+        self_modified
+    }
+}
+
+impl Default for AccessMap {
+    fn default() -> Self {
+        AccessMap::new()
+    }
+}
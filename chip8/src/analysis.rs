@@ -0,0 +1,554 @@
+//! Static analysis of CHIP-8 programs.
+//!
+//! The program is explored by recursive traversal starting from
+//! `PROGRAM_START_PC`, following jumps, calls and both sides of the skip
+//! instructions. Computed jumps (`BNNN`) can't be followed statically and are
+//! reported as unresolved.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+use serde_json::json;
+
+use crate::chip8::{FONT_SET, PROGRAM_START_PC};
+use crate::disasm::Instr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Skip,
+    Call,
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// Address right after the last instruction of the block.
+    pub end: usize,
+    pub instrs: Vec<(usize, Instr)>,
+    pub successors: Vec<(usize, EdgeKind)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Subroutine {
+    pub entry: usize,
+    pub blocks: BTreeSet<usize>,
+    pub callees: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspiciousKind {
+    OddAddress,
+    FontArea,
+    InterpreterArea,
+    OutsideProgram,
+    InvalidInstruction,
+    MachineCodeRoutine,
+    RunsOffProgram,
+}
+
+/// Something looking wrong found at `addr` while exploring the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suspicious {
+    pub addr: usize,
+    pub target: Option<usize>,
+    pub kind: SuspiciousKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub subroutines: BTreeMap<usize, Subroutine>,
+    /// Addresses of the `BNNN` computed jumps.
+    pub unresolved_jumps: Vec<usize>,
+    pub suspicious: Vec<Suspicious>,
+    /// Ranges of the program never reached by the traversal, usually data.
+    pub unreachable: Vec<Range<usize>>,
+}
+
+impl Analysis {
+    pub fn new(program: &[u8]) -> Self {
+        let program_end = PROGRAM_START_PC + program.len();
+
+        let mut instrs = BTreeMap::new();
+        let mut visited = BTreeSet::new();
+        let mut call_targets = BTreeSet::new();
+        let mut unresolved_jumps = vec![];
+        let mut suspicious = vec![];
+
+        let mut todo = vec![PROGRAM_START_PC];
+        while let Some(addr) = todo.pop() {
+            if !visited.insert(addr) {
+                continue;
+            }
+
+            let mut report = |target, kind| {
+                suspicious.push(Suspicious { addr, target, kind });
+            };
+
+            if addr + 1 >= program_end {
+                report(None, SuspiciousKind::RunsOffProgram);
+                continue;
+            }
+
+            let instr = match Instr::fetch(program, addr - PROGRAM_START_PC) {
+                Some(instr) => instr,
+                None => {
+                    report(None, SuspiciousKind::InvalidInstruction);
+                    continue;
+                }
+            };
+
+            let mut follow = |target: usize| {
+                if target & 1 == 1 {
+                    report(Some(target), SuspiciousKind::OddAddress);
+                }
+
+                match target_kind(target, program_end) {
+                    Some(kind) => {
+                        report(Some(target), kind);
+                        false
+                    }
+                    None => true,
+                }
+            };
+
+            let mut successors = vec![];
+            match instr {
+                Instr::Ret => {}
+                Instr::Sys(_) => report(None, SuspiciousKind::MachineCodeRoutine),
+                Instr::JumpOff(_) => unresolved_jumps.push(addr),
+                Instr::Jump(nnn) => {
+                    let target = usize::from(nnn);
+                    if follow(target) {
+                        successors.push((target, EdgeKind::Jump));
+                    }
+                }
+                Instr::Call(nnn) => {
+                    let target = usize::from(nnn);
+                    if follow(target) {
+                        call_targets.insert(target);
+                        successors.push((target, EdgeKind::Call));
+                    }
+                    successors.push((addr + 2, EdgeKind::Fallthrough));
+                }
+                i if i.is_skip() => {
+                    successors.push((addr + 2, EdgeKind::Fallthrough));
+                    successors.push((addr + 4, EdgeKind::Skip));
+                }
+                _ => successors.push((addr + 2, EdgeKind::Fallthrough)),
+            }
+
+            todo.extend(successors.iter().rev().map(|(a, _)| *a));
+            instrs.insert(addr, (instr, successors));
+        }
+
+        unresolved_jumps.sort();
+        suspicious.sort_by_key(|s| s.addr);
+
+        let blocks = build_blocks(&instrs);
+        let subroutines = build_subroutines(&blocks, &call_targets);
+        let unreachable = unreachable_ranges(&instrs, program_end);
+
+        Analysis {
+            blocks,
+            subroutines,
+            unresolved_jumps,
+            suspicious,
+            unreachable,
+        }
+    }
+
+    /// Maximum number of nested calls starting from the program entry, `None`
+    /// if some subroutines are recursive.
+    pub fn max_call_depth(&self) -> Option<usize> {
+        fn depth(
+            analysis: &Analysis,
+            entry: usize,
+            visiting: &mut BTreeSet<usize>,
+            memo: &mut BTreeMap<usize, usize>,
+        ) -> Option<usize> {
+            if let Some(d) = memo.get(&entry) {
+                return Some(*d);
+            }
+            if !visiting.insert(entry) {
+                return None;
+            }
+
+            let mut max = 0;
+            if let Some(sub) = analysis.subroutines.get(&entry) {
+                for callee in &sub.callees {
+                    max = max.max(1 + depth(analysis, *callee, visiting, memo)?);
+                }
+            }
+
+            visiting.remove(&entry);
+            memo.insert(entry, max);
+            Some(max)
+        }
+
+        depth(
+            self,
+            PROGRAM_START_PC,
+            &mut BTreeSet::new(),
+            &mut BTreeMap::new(),
+        )
+    }
+
+    /// Graphviz representation of the control flow graph.
+    pub fn cfg_dot(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box fontname=monospace];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (addr, instr) in &block.instrs {
+                write!(label, "{:#05X}: {}\\l", addr, instr).unwrap();
+            }
+
+            writeln!(out, "    \"{:#05X}\" [label=\"{}\"];", block.start, label).unwrap();
+        }
+
+        for block in self.blocks.values() {
+            for (to, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Skip => " [label=\"skip\" style=dashed]",
+                    EdgeKind::Call => " [label=\"call\" color=blue]",
+                };
+
+                writeln!(
+                    out,
+                    "    \"{:#05X}\" -> \"{:#05X}\"{};",
+                    block.start, to, style
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    /// Graphviz representation of the subroutine call graph.
+    pub fn call_graph_dot(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "digraph calls {{").unwrap();
+        writeln!(out, "    node [shape=ellipse fontname=monospace];").unwrap();
+
+        for sub in self.subroutines.values() {
+            writeln!(out, "    \"{:#05X}\";", sub.entry).unwrap();
+
+            for callee in &sub.callees {
+                writeln!(out, "    \"{:#05X}\" -> \"{:#05X}\";", sub.entry, callee).unwrap();
+            }
+        }
+
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let blocks = self
+            .blocks
+            .values()
+            .map(|b| {
+                json!({
+                    "start": b.start,
+                    "end": b.end,
+                    "instructions": b.instrs.iter().map(|(addr, i)| json!({
+                        "addr": addr,
+                        "text": i.to_string(),
+                    })).collect::<Vec<_>>(),
+                    "successors": b.successors.iter().map(|(to, kind)| json!({
+                        "to": to,
+                        "kind": kind.to_string(),
+                    })).collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let subroutines = self
+            .subroutines
+            .values()
+            .map(|s| {
+                json!({
+                    "entry": s.entry,
+                    "blocks": s.blocks,
+                    "callees": s.callees,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let suspicious = self
+            .suspicious
+            .iter()
+            .map(|s| {
+                json!({
+                    "addr": s.addr,
+                    "target": s.target,
+                    "kind": s.kind.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "blocks": blocks,
+            "subroutines": subroutines,
+            "unresolved_jumps": self.unresolved_jumps,
+            "suspicious": suspicious,
+            "unreachable": self.unreachable.iter().map(|r| json!({
+                "start": r.start,
+                "end": r.end,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Call => "call",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for SuspiciousKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SuspiciousKind::OddAddress => "odd address",
+            SuspiciousKind::FontArea => "target in the font area",
+            SuspiciousKind::InterpreterArea => "target in the interpreter area",
+            SuspiciousKind::OutsideProgram => "target outside of the program",
+            SuspiciousKind::InvalidInstruction => "invalid instruction",
+            SuspiciousKind::MachineCodeRoutine => "machine code routine",
+            SuspiciousKind::RunsOffProgram => "execution runs off the program",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+fn target_kind(target: usize, program_end: usize) -> Option<SuspiciousKind> {
+    if target < FONT_SET.len() {
+        Some(SuspiciousKind::FontArea)
+    } else if target < PROGRAM_START_PC {
+        Some(SuspiciousKind::InterpreterArea)
+    } else if target >= program_end {
+        Some(SuspiciousKind::OutsideProgram)
+    } else {
+        None
+    }
+}
+
+type Instrs = BTreeMap<usize, (Instr, Vec<(usize, EdgeKind)>)>;
+
+fn build_blocks(instrs: &Instrs) -> BTreeMap<usize, BasicBlock> {
+    let mut preds = BTreeMap::<usize, Vec<(usize, EdgeKind)>>::new();
+    for (addr, (_, successors)) in instrs {
+        for (to, kind) in successors {
+            preds.entry(*to).or_default().push((*addr, *kind));
+        }
+    }
+
+    // an instruction starts a new block unless its only way in is falling
+    // through from an instruction that can only fall through
+    let is_leader = |addr: usize| match preds.get(&addr).map(|p| p.as_slice()) {
+        Some([(from, EdgeKind::Fallthrough)]) => instrs[from].1.len() != 1,
+        _ => true,
+    };
+
+    let mut blocks = BTreeMap::new();
+    for &start in instrs.keys().filter(|a| is_leader(**a)) {
+        let mut block = BasicBlock {
+            start,
+            end: start,
+            instrs: vec![],
+            successors: vec![],
+        };
+
+        let mut addr = start;
+        loop {
+            let (instr, successors) = &instrs[&addr];
+            block.instrs.push((addr, *instr));
+            block.end = addr + 2;
+
+            match successors.as_slice() {
+                [(next, EdgeKind::Fallthrough)]
+                    if instrs.contains_key(next) && !is_leader(*next) =>
+                {
+                    addr = *next;
+                }
+                _ => {
+                    block.successors = successors
+                        .iter()
+                        .filter(|(to, _)| instrs.contains_key(to))
+                        .cloned()
+                        .collect();
+                    break;
+                }
+            }
+        }
+
+        blocks.insert(start, block);
+    }
+
+    blocks
+}
+
+fn build_subroutines(
+    blocks: &BTreeMap<usize, BasicBlock>,
+    call_targets: &BTreeSet<usize>,
+) -> BTreeMap<usize, Subroutine> {
+    let entries = Some(PROGRAM_START_PC)
+        .into_iter()
+        .chain(call_targets.iter().cloned())
+        .filter(|e| blocks.contains_key(e));
+
+    entries
+        .map(|entry| {
+            let mut sub = Subroutine {
+                entry,
+                blocks: BTreeSet::new(),
+                callees: BTreeSet::new(),
+            };
+
+            let mut todo = vec![entry];
+            while let Some(start) = todo.pop() {
+                if !sub.blocks.insert(start) {
+                    continue;
+                }
+
+                for (to, kind) in &blocks[&start].successors {
+                    if *kind == EdgeKind::Call {
+                        sub.callees.insert(*to);
+                    } else {
+                        todo.push(*to);
+                    }
+                }
+            }
+
+            (entry, sub)
+        })
+        .collect()
+}
+
+fn unreachable_ranges(instrs: &Instrs, program_end: usize) -> Vec<Range<usize>> {
+    let mut covered = vec![false; program_end - PROGRAM_START_PC];
+    for addr in instrs.keys() {
+        let off = addr - PROGRAM_START_PC;
+        covered[off] = true;
+        covered[off + 1] = true;
+    }
+
+    let mut ranges = vec![];
+    let mut start = None;
+    for (off, c) in covered.iter().enumerate() {
+        match (start, c) {
+            (None, false) => start = Some(off),
+            (Some(s), true) => {
+                ranges.push(PROGRAM_START_PC + s..PROGRAM_START_PC + off);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(s) = start {
+        ranges.push(PROGRAM_START_PC + s..program_end);
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subroutine_and_return() {
+        // CALL 0x204 / JP 0x202 / RET
+        let analysis = Analysis::new(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]);
+
+        assert_eq!(
+            analysis.blocks[&0x200].successors,
+            vec![(0x204, EdgeKind::Call), (0x202, EdgeKind::Fallthrough)]
+        );
+        assert!(analysis.blocks[&0x204].successors.is_empty());
+
+        assert_eq!(
+            analysis.subroutines.keys().cloned().collect::<Vec<_>>(),
+            vec![0x200, 0x204]
+        );
+        let main = &analysis.subroutines[&0x200];
+        assert_eq!(main.blocks, BTreeSet::from([0x200, 0x202]));
+        assert_eq!(main.callees, BTreeSet::from([0x204]));
+        assert_eq!(analysis.max_call_depth(), Some(1));
+
+        assert!(analysis.suspicious.is_empty());
+        assert!(analysis.unreachable.is_empty());
+    }
+
+    #[test]
+    fn computed_jump_is_unresolved() {
+        // JP V0, 0x210
+        let analysis = Analysis::new(&[0xB2, 0x10]);
+
+        assert_eq!(analysis.unresolved_jumps, vec![0x200]);
+        assert!(analysis.blocks[&0x200].successors.is_empty());
+        assert!(analysis.suspicious.is_empty());
+    }
+
+    #[test]
+    fn suspicious_targets() {
+        // JP 0x010
+        let analysis = Analysis::new(&[0x10, 0x10]);
+        assert_eq!(
+            analysis.suspicious,
+            vec![Suspicious {
+                addr: 0x200,
+                target: Some(0x010),
+                kind: SuspiciousKind::FontArea,
+            }]
+        );
+
+        // JP 0x203 lands in the middle of the next instruction, which reads
+        // as JP 0x203 again
+        let analysis = Analysis::new(&[0x12, 0x03, 0x12, 0x12, 0x03]);
+        assert_eq!(
+            analysis.suspicious,
+            vec![
+                Suspicious {
+                    addr: 0x200,
+                    target: Some(0x203),
+                    kind: SuspiciousKind::OddAddress,
+                },
+                Suspicious {
+                    addr: 0x203,
+                    target: Some(0x203),
+                    kind: SuspiciousKind::OddAddress,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_data_is_unreachable() {
+        // JP 0x200 followed by a sprite
+        let analysis = Analysis::new(&[0x12, 0x00, 0xF0, 0x90, 0xF0]);
+
+        assert_eq!(analysis.unreachable, vec![0x202..0x205]);
+        assert!(analysis.suspicious.is_empty());
+    }
+}
@@ -0,0 +1,436 @@
+//! A small assembler for the mnemonics printed by the disassembler, those of
+//! Cowgod's Chip-8 technical reference.
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! start:
+//!     LD   I, sprite
+//!     DRW  V0, V1, 5
+//!     JP   start
+//! sprite:
+//!     DB   0xF0, 0x90, 0x90, 0x90, 0xF0
+//! ```
+//!
+//! Numbers are decimal, hexadecimal with a `0x` or `#` prefix or binary with
+//! a `0b` prefix, labels can be used wherever an address or a value is
+//! expected. `DB` and `DW` emit bytes and big endian words. The program is
+//! assembled to be loaded at `PROGRAM_START_PC`.
+//!
+//! The listings of the disassembler are accepted as they are: the address and
+//! the hex dump that start their lines are skipped, so a disassembled rom can
+//! be edited and assembled back. The word of the dump is kept as long as it
+//! decodes to the same instruction, since some roms have garbage in the
+//! nibbles not used by the instruction.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::chip8::PROGRAM_START_PC;
+use crate::disasm::Instr;
+
+/// An error in the source, `line` starts from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    V(usize),
+    I,
+    /// `[I]`, the memory pointed by I.
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    /// The RPL user flags.
+    R,
+    Value(u16),
+}
+
+/// A statement of the source along with its line.
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+    /// The instruction word of a disassembler listing.
+    word: Option<u16>,
+}
+
+const RESERVED: [&str; 7] = ["I", "DT", "ST", "K", "F", "B", "R"];
+
+/// Assemble `src` into a program.
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    let mut addr = PROGRAM_START_PC;
+
+    for (i, line) in src.lines().enumerate() {
+        let error = |message: String| AsmError {
+            line: i + 1,
+            message,
+        };
+
+        let mut line = line.split(';').next().unwrap().trim();
+
+        while let Some((label, rest)) = split_label(line) {
+            if !is_label(label) {
+                return Err(error(format!("invalid label {}", label)));
+            }
+            if labels.insert(label.to_string(), addr as u16).is_some() {
+                return Err(error(format!("label {} defined twice", label)));
+            }
+            line = rest.trim_start();
+        }
+
+        let (mut mnemonic, mut rest) = split_word(line);
+        let mut word = None;
+
+        // the address and the hex dump of a disassembler listing
+        if parse_number(mnemonic).is_some() {
+            let (dump, instr) = split_word(rest);
+            if dump.is_empty() || !dump.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(error(format!("unknown instruction {}", mnemonic)));
+            }
+            if dump.len() == 4 {
+                word = u16::from_str_radix(dump, 16).ok();
+            }
+            let (m, r) = split_word(instr);
+            mnemonic = m;
+            rest = r;
+        }
+
+        if mnemonic.is_empty() {
+            continue;
+        }
+
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operands = if rest.is_empty() {
+            vec![]
+        } else {
+            rest.split(',').map(str::trim).collect::<Vec<_>>()
+        };
+
+        addr += match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            _ => 2,
+        };
+
+        statements.push(Statement {
+            line: i + 1,
+            mnemonic,
+            operands,
+            word,
+        });
+    }
+
+    let mut program = vec![];
+    for statement in &statements {
+        let error = |message: String| AsmError {
+            line: statement.line,
+            message,
+        };
+
+        let operands = statement
+            .operands
+            .iter()
+            .map(|o| parse_operand(o, &labels))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error)?;
+
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for o in &operands {
+                    program.push(byte(value(*o).map_err(error)?).map_err(error)?);
+                }
+            }
+            "DW" => {
+                for o in &operands {
+                    program.extend_from_slice(&value(*o).map_err(error)?.to_be_bytes());
+                }
+            }
+            mnemonic => {
+                let instr = instr(mnemonic, &operands).map_err(error)?;
+                let word = match statement.word {
+                    Some(word) if Instr::decode(word) == Some(instr) => word,
+                    _ => instr.encode(),
+                };
+                program.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+fn instr(mnemonic: &str, operands: &[Operand]) -> Result<Instr, String> {
+    use Operand::*;
+
+    let instr = match (mnemonic, operands) {
+        ("CLS", []) => Instr::Cls,
+        ("RET", []) => Instr::Ret,
+        ("SYS", [Value(a)]) => Instr::Sys(addr(*a)?),
+
+        ("JP", [Value(a)]) => Instr::Jump(addr(*a)?),
+        ("JP", [V(0), Value(a)]) => Instr::JumpOff(addr(*a)?),
+        ("CALL", [Value(a)]) => Instr::Call(addr(*a)?),
+        ("SE", [V(x), Value(nn)]) => Instr::SkipEqNn(*x, byte(*nn)?),
+        ("SE", [V(x), V(y)]) => Instr::SkipEqRr(*x, *y),
+        ("SNE", [V(x), Value(nn)]) => Instr::SkipNeNn(*x, byte(*nn)?),
+        ("SNE", [V(x), V(y)]) => Instr::SkipNeRr(*x, *y),
+
+        ("LD", [V(x), Value(nn)]) => Instr::Load(*x, byte(*nn)?),
+        ("LD", [V(x), V(y)]) => Instr::Assign(*x, *y),
+        ("LD", [I, Value(a)]) => Instr::SetI(addr(*a)?),
+        ("LD", [V(x), Dt]) => Instr::StoreDelay(*x),
+        ("LD", [V(x), K]) => Instr::WaitKeypress(*x),
+        ("LD", [Dt, V(x)]) => Instr::SetDelayTimer(*x),
+        ("LD", [St, V(x)]) => Instr::SetSoundTimer(*x),
+        ("LD", [F, V(x)]) => Instr::FontSpriteAddr(*x),
+        ("LD", [B, V(x)]) => Instr::Bcd(*x),
+        ("LD", [IndirectI, V(x)]) => Instr::DumpRegs(*x),
+        ("LD", [V(x), IndirectI]) => Instr::LoadRegs(*x),
+        ("LD", [R, V(x)]) => Instr::StoreFlags(*x),
+        ("LD", [V(x), R]) => Instr::LoadFlags(*x),
+
+        ("ADD", [V(x), Value(nn)]) => Instr::AddNn(*x, byte(*nn)?),
+        ("ADD", [V(x), V(y)]) => Instr::AddRr(*x, *y),
+        ("ADD", [I, V(x)]) => Instr::AddI(*x),
+        ("OR", [V(x), V(y)]) => Instr::Or(*x, *y),
+        ("AND", [V(x), V(y)]) => Instr::And(*x, *y),
+        ("XOR", [V(x), V(y)]) => Instr::Xor(*x, *y),
+        ("SUB", [V(x), V(y)]) => Instr::SubRr(*x, *y),
+        ("SUBN", [V(x), V(y)]) => Instr::SubRrInv(*x, *y),
+        ("SHR", [V(x)]) => Instr::ShiftR(*x, *x),
+        ("SHR", [V(x), V(y)]) => Instr::ShiftR(*x, *y),
+        ("SHL", [V(x)]) => Instr::ShiftL(*x, *x),
+        ("SHL", [V(x), V(y)]) => Instr::ShiftL(*x, *y),
+
+        ("RND", [V(x), Value(nn)]) => Instr::Rand(*x, byte(*nn)?),
+        ("DRW", [V(x), V(y), Value(n)]) if *n <= 0xF => Instr::Draw(*x, *y, *n as u8),
+        ("DRW", [V(_), V(_), Value(n)]) => return Err(format!("sprite height {} over 15", n)),
+
+        ("SKP", [V(x)]) => Instr::SkipPressed(*x),
+        ("SKNP", [V(x)]) => Instr::SkipNotPressed(*x),
+
+        (
+            "CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+            | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP",
+            _,
+        ) => return Err(format!("invalid operands for {}", mnemonic)),
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+
+    Ok(instr)
+}
+
+fn parse_operand(s: &str, labels: &HashMap<String, u16>) -> Result<Operand, String> {
+    let upper = s.to_ascii_uppercase();
+
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => {
+            if let Some(x) = register(&upper) {
+                Operand::V(x)
+            } else if let Some(n) = parse_number(s) {
+                Operand::Value(n)
+            } else if let Some(addr) = labels.get(s) {
+                Operand::Value(*addr)
+            } else if is_label(s) {
+                return Err(format!("unknown label {}", s));
+            } else {
+                return Err(format!("invalid operand {}", s));
+            }
+        }
+    };
+
+    Ok(operand)
+}
+
+/// Parse `V0` to `VF`.
+fn register(s: &str) -> Option<usize> {
+    let x = s.strip_prefix('V')?;
+    if x.len() != 1 {
+        return None;
+    }
+
+    usize::from_str_radix(x, 16).ok()
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    let lower = s.to_ascii_lowercase();
+
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_prefix('#') {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+
+    // from_str_radix accepts a sign
+    if digits.starts_with('+') {
+        return None;
+    }
+
+    u16::from_str_radix(digits, radix).ok()
+}
+
+/// Split the first word of `s` from the rest of it.
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    }
+}
+
+/// Split `label:` at the start of a line from the rest of it.
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    let label = &line[..colon];
+
+    if label.contains(char::is_whitespace) {
+        None
+    } else {
+        Some((label, &line[colon + 1..]))
+    }
+}
+
+fn is_label(s: &str) -> bool {
+    let upper = s.to_ascii_uppercase();
+
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && register(&upper).is_none()
+        && !RESERVED.contains(&upper.as_str())
+}
+
+fn value(operand: Operand) -> Result<u16, String> {
+    match operand {
+        Operand::Value(n) => Ok(n),
+        _ => Err("expected a value".to_string()),
+    }
+}
+
+fn byte(n: u16) -> Result<u8, String> {
+    if n > 0xFF {
+        Err(format!("{:#X} doesn't fit in a byte", n))
+    } else {
+        Ok(n as u8)
+    }
+}
+
+fn addr(n: u16) -> Result<u16, String> {
+    if n > 0xFFF {
+        Err(format!("address {:#X} out of range", n))
+    } else {
+        Ok(n)
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::disasm::disassemble;
+
+    fn error(src: &str) -> AsmError {
+        assemble(src).unwrap_err()
+    }
+
+    #[test]
+    fn games_round_trip() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../games");
+        let mut games = 0;
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let program = fs::read(&path).unwrap();
+
+            let listing = disassemble(&program, None)
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            let assembled = assemble(&listing)
+                .unwrap_or_else(|e| panic!("cannot assemble {}: {}", path.display(), e));
+            assert!(assembled == program, "{} differs", path.display());
+            games += 1;
+        }
+
+        assert!(games > 0);
+    }
+
+    #[test]
+    fn labels() {
+        let src = "
+            start:  JP   end      ; forward reference
+            loop:   LD   I, data
+                    JP   loop
+            end:    CALL start
+            data:   DB   1
+        ";
+
+        assert_eq!(
+            assemble(src).unwrap(),
+            [0x12, 0x06, 0xA2, 0x08, 0x12, 0x02, 0x22, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn data() {
+        let src = "DB 0xF0, #90, 0b1001, 144\nDW 0x1234, 5";
+
+        assert_eq!(
+            assemble(src).unwrap(),
+            [0xF0, 0x90, 0x09, 0x90, 0x12, 0x34, 0x00, 0x05]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("CLS\nJP nowhere"),
+            AsmError {
+                line: 2,
+                message: "unknown label nowhere".to_string(),
+            }
+        );
+        assert_eq!(error("LD V0, 256").message, "0x100 doesn't fit in a byte");
+        assert_eq!(error("DB 0x1FF").message, "0x1FF doesn't fit in a byte");
+        assert_eq!(error("DRW V0, V1, 16").message, "sprite height 16 over 15");
+        assert_eq!(
+            error("a: CLS\na: RET"),
+            AsmError {
+                line: 2,
+                message: "label a defined twice".to_string(),
+            }
+        );
+    }
+}
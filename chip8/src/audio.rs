@@ -0,0 +1,248 @@
+//! Sound of the buzzer, synthesized a frame at a time so that the same
+//! timers always produce the same samples.
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Frequency of the buzzer tone in Hz.
+pub const TONE_FREQUENCY: f32 = 240.0;
+
+pub const VOLUME: f32 = 0.25;
+
+/// Frames per second the timers are ticked at.
+pub const FRAME_RATE: u32 = 60;
+
+pub const WAVEFORMS: [&str; 4] = ["square", "triangle", "sawtooth", "sine"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Waveform {
+    /// The tone of the original buzzer.
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+/// Generator of the buzzer tone.
+#[derive(Debug, Clone)]
+pub struct Synth {
+    sample_rate: u32,
+    waveform: Waveform,
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWaveformError(String);
+
+/// Recorder of the buzzer to WAV, one frame at a time.
+///
+/// The samples only depend on whether the buzzer sounded at every frame, that
+/// is on when the sound timer goes from zero to non zero and back, so the
+/// same run always gives the same file.
+pub struct WavRecorder<W: Write + Seek> {
+    wav: WavWriter<W>,
+    synth: Synth,
+}
+
+/// Writer of 16 bit mono WAV files, the sizes in the header are filled in
+/// by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    w: W,
+    samples: u32,
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32, waveform: Waveform, frequency: f32, volume: f32) -> Self {
+        Synth {
+            sample_rate,
+            waveform,
+            phase_inc: frequency / sample_rate as f32,
+            phase: 0.0,
+            volume,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The samples of a frame, silent unless `beeping`.
+    pub fn frame(&mut self, beeping: bool) -> Vec<f32> {
+        let n = self.sample_rate / FRAME_RATE;
+        (0..n).map(|_| self.sample(beeping)).collect()
+    }
+
+    /// The next sample.
+    pub fn sample(&mut self, beeping: bool) -> f32 {
+        if !beeping {
+            // start every beep from the same phase
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let p = self.phase;
+        let s = match self.waveform {
+            Waveform::Square => {
+                if p < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * p - 1.0,
+            Waveform::Sine => (2.0 * PI * p).sin(),
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+
+        s * self.volume
+    }
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Synth::new(SAMPLE_RATE, Waveform::Square, TONE_FREQUENCY, VOLUME)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut w: W, sample_rate: u32) -> io::Result<Self> {
+        let bytes_per_sample = 2;
+
+        w.write_all(b"RIFF")?;
+        w.write_all(&0_u32.to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16_u32.to_le_bytes())?;
+        w.write_all(&1_u16.to_le_bytes())?; // PCM
+        w.write_all(&1_u16.to_le_bytes())?; // mono
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * bytes_per_sample).to_le_bytes())?;
+        w.write_all(&(bytes_per_sample as u16).to_le_bytes())?;
+        w.write_all(&16_u16.to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&0_u32.to_le_bytes())?;
+
+        Ok(WavWriter { w, samples: 0 })
+    }
+
+    /// Append samples between -1 and 1.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for s in samples {
+            let s = (s.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            self.w.write_all(&s.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    /// Fill in the sizes in the header and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.samples * 2;
+
+        self.w.seek(SeekFrom::Start(4))?;
+        self.w.write_all(&(36 + data_size).to_le_bytes())?;
+        self.w.seek(SeekFrom::Start(40))?;
+        self.w.write_all(&data_size.to_le_bytes())?;
+        self.w.flush()?;
+
+        Ok(self.w)
+    }
+}
+
+impl<W: Write + Seek> WavRecorder<W> {
+    pub fn new(w: W, synth: Synth) -> io::Result<Self> {
+        Ok(WavRecorder {
+            wav: WavWriter::new(w, synth.sample_rate())?,
+            synth,
+        })
+    }
+
+    /// Append the sixtieth of second of sound of a frame.
+    pub fn add_frame(&mut self, beeping: bool) -> io::Result<()> {
+        self.wav.write_samples(&self.synth.frame(beeping))
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.wav.finish()
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = ParseWaveformError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(ParseWaveformError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ParseWaveformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid waveform {}, expected one of {}",
+            self.0,
+            WAVEFORMS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseWaveformError {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::headless::{InputScript, Runner, DEFAULT_CYCLES_PER_FRAME};
+
+    /// Record UFO for 5 seconds, the missile fired at frame 60 sounds the
+    /// buzzer when it hits.
+    fn record(seed: u64, script: &InputScript) -> Vec<u8> {
+        let program = include_bytes!("../../games/UFO");
+        let mut runner = Runner::new(program, seed, DEFAULT_CYCLES_PER_FRAME).unwrap();
+        let mut recorder = WavRecorder::new(Cursor::new(vec![]), Synth::default()).unwrap();
+
+        for _ in 0..300 {
+            script.apply(runner.frame(), runner.chip8_mut());
+            runner.run_frame();
+            recorder.add_frame(runner.beeped()).unwrap();
+        }
+
+        recorder.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn same_run_same_wav() {
+        let script = InputScript::parse("60 5 down\n62 5 up\n").unwrap();
+
+        let wav = record(42, &script);
+        assert_eq!(wav, record(42, &script));
+
+        // 44 bytes of header
+        assert_eq!(
+            wav.len(),
+            44 + 300 * (SAMPLE_RATE / FRAME_RATE) as usize * 2
+        );
+        assert!(wav[44..].iter().any(|b| *b != 0));
+        assert!(record(42, &InputScript::default())[44..]
+            .iter()
+            .all(|b| *b == 0));
+    }
+}
@@ -0,0 +1,279 @@
+//! Recording of the gameplay: animated GIFs and PNGs along with raw Y4M video
+//! and WAV audio to feed to ffmpeg.
+//!
+//! Everything works on rendered frames and on the buzzer state of every
+//! frame, so the same code records from the frontends and from headless runs.
+//! The size of a recording is the one of its first frame, later frames of a
+//! different size, e.g. after switching to hires, are resized to fit.
+
+use std::fmt;
+use std::io::{self, Seek, Write};
+use std::str::FromStr;
+
+use crate::audio::{Synth, WavRecorder, FRAME_RATE};
+use crate::display::Display;
+use crate::filters::Pipeline;
+use crate::image::{self, GifEncoder, Image};
+use crate::palette::{self, Palette};
+
+pub const ANIMATION_FORMATS: [&str; 2] = ["gif", "apng"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+/// Recorder of an animation at 60 frames per second where consecutive
+/// identical frames are merged into a longer one.
+pub struct AnimationRecorder<W: Write> {
+    encoder: AnimationEncoder<W>,
+    /// The last frame and for how many frames it stayed on screen, it's
+    /// written once a different one comes in.
+    pending: Option<(Image, u16)>,
+    /// Number of sixtieths of second written so far.
+    elapsed: usize,
+    frames: usize,
+}
+
+enum AnimationEncoder<W: Write> {
+    /// The GIF encoder is created with the size of the first frame.
+    Gif(Option<W>, Option<GifEncoder<W>>),
+    /// The number of frames goes in the header, so they're kept until the
+    /// end.
+    Apng(W, Vec<(Image, u16)>),
+}
+
+/// Writer of uncompressed YUV4MPEG2 video at 60 frames per second.
+pub struct Y4mWriter<W: Write> {
+    w: W,
+    size: Option<(usize, usize)>,
+}
+
+/// A Y4M video along with the WAV of the buzzer, frame by frame.
+pub struct AvDump<V: Write, A: Write + Seek> {
+    video: Y4mWriter<V>,
+    audio: WavRecorder<A>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAnimationFormatError(String);
+
+/// Render the screen like the frontends do, the background turns into the
+/// buzzer color of the palette while beeping.
+pub fn render(pipeline: &Pipeline, display: &Display, palette: &Palette, beeping: bool) -> Image {
+    pipeline.render_display(
+        display,
+        palette::rgba(palette.background_while(beeping)),
+        palette::rgba(palette.foreground()),
+    )
+}
+
+impl<W: Write> AnimationRecorder<W> {
+    pub fn new(w: W, format: AnimationFormat) -> Self {
+        let encoder = match format {
+            AnimationFormat::Gif => AnimationEncoder::Gif(Some(w), None),
+            AnimationFormat::Apng => AnimationEncoder::Apng(w, vec![]),
+        };
+
+        AnimationRecorder {
+            encoder,
+            pending: None,
+            elapsed: 0,
+            frames: 0,
+        }
+    }
+
+    /// Number of distinct frames recorded so far.
+    pub fn frames(&self) -> usize {
+        self.frames + usize::from(self.pending.is_some())
+    }
+
+    /// Append the frame shown for the next sixtieth of second.
+    pub fn add_frame(&mut self, img: &Image) -> io::Result<()> {
+        let img = match self.size() {
+            Some((width, height)) => img.resized(width, height),
+            None => img.clone(),
+        };
+
+        if let Some((last, duration)) = &mut self.pending {
+            if *last == img && *duration < u16::MAX {
+                *duration += 1;
+                return Ok(());
+            }
+        }
+
+        self.flush()?;
+        self.pending = Some((img, 1));
+
+        Ok(())
+    }
+
+    /// Write the last frame and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+
+        match self.encoder {
+            AnimationEncoder::Gif(_, Some(gif)) => gif.finish(),
+            AnimationEncoder::Apng(mut w, frames) => {
+                image::write_apng(&mut w, &frames)?;
+                Ok(w)
+            }
+            AnimationEncoder::Gif(_, None) => {
+                Err(io::Error::other("an animation needs at least a frame"))
+            }
+        }
+    }
+
+    fn size(&self) -> Option<(usize, usize)> {
+        let first = match &self.encoder {
+            AnimationEncoder::Gif(_, Some(gif)) => return Some(gif.size()),
+            AnimationEncoder::Gif(_, None) => None,
+            AnimationEncoder::Apng(_, frames) => frames.first().map(|(img, _)| img),
+        };
+
+        first
+            .or_else(|| self.pending.as_ref().map(|(img, _)| img))
+            .map(|img| (img.width, img.height))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let (img, duration) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let start = self.elapsed;
+        self.elapsed += usize::from(duration);
+        self.frames += 1;
+
+        match &mut self.encoder {
+            AnimationEncoder::Gif(w, gif) => {
+                // GIF delays are in hundredths of second, rounding when every
+                // frame ends rather than how long it lasts keeps the timing
+                // from drifting
+                let fps = FRAME_RATE as usize;
+                let cs = |frames: usize| (frames * 100 + fps / 2) / fps;
+                let delay = (cs(self.elapsed) - cs(start)) as u16;
+
+                if let Some(w) = w.take() {
+                    *gif = Some(GifEncoder::new(w, img.width, img.height)?);
+                }
+                gif.as_mut().unwrap().add_frame(&img, delay)
+            }
+            AnimationEncoder::Apng(_, frames) => {
+                frames.push((img, duration));
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// The header is written along with the first frame, since it has the
+    /// size of the video.
+    pub fn new(w: W) -> Self {
+        Y4mWriter { w, size: None }
+    }
+
+    pub fn add_frame(&mut self, img: &Image) -> io::Result<()> {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => {
+                // 4:4:4 since the pixels of the screen are usually smaller
+                // than the 2x2 blocks of 4:2:0
+                writeln!(
+                    self.w,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    img.width, img.height, FRAME_RATE
+                )?;
+                self.size = Some((img.width, img.height));
+                (img.width, img.height)
+            }
+        };
+        let img = img.resized(width, height);
+
+        let n = width * height;
+        let mut planes = vec![0; n * 3];
+        for (i, px) in img.pixels.chunks_exact(4).enumerate() {
+            let [y, u, v] = rgb_to_yuv(px[0], px[1], px[2]);
+            planes[i] = y;
+            planes[n + i] = u;
+            planes[2 * n + i] = v;
+        }
+
+        self.w.write_all(b"FRAME\n")?;
+        self.w.write_all(&planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<V: Write, A: Write + Seek> AvDump<V, A> {
+    pub fn new(video: V, audio: A) -> io::Result<Self> {
+        Ok(AvDump {
+            video: Y4mWriter::new(video),
+            audio: WavRecorder::new(audio, Synth::default())?,
+        })
+    }
+
+    /// Append a frame of video and the sixtieth of second of audio played
+    /// along with it.
+    pub fn add_frame(&mut self, img: &Image, beeping: bool) -> io::Result<()> {
+        self.video.add_frame(img)?;
+        self.audio.add_frame(beeping)
+    }
+
+    pub fn finish(self) -> io::Result<(V, A)> {
+        Ok((self.video.finish()?, self.audio.finish()?))
+    }
+}
+
+/// BT.601 limited range conversion, the one ffmpeg assumes by default.
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (f32::from(r), f32::from(g), f32::from(b));
+
+    let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+    let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+    let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
+
+impl AnimationFormat {
+    /// The extension of the files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+        }
+    }
+}
+
+impl FromStr for AnimationFormat {
+    type Err = ParseAnimationFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(AnimationFormat::Gif),
+            "apng" => Ok(AnimationFormat::Apng),
+            _ => Err(ParseAnimationFormatError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ParseAnimationFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid animation format {}, expected one of {}",
+            self.0,
+            ANIMATION_FORMATS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseAnimationFormatError {}
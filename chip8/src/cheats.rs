@@ -0,0 +1,279 @@
+//! Cheats: searching the RAM for interesting values, freezing addresses and
+//! patching the program.
+//!
+//! Cheats are stored in a plain text file grouped by the SHA-1 of the rom they
+//! apply to:
+//!
+//! ```text
+//! # comments start with a hash
+//! [3f0ab1c2...]
+//! freeze 0x3A4=03 Infinite lives
+//! patch 0x2C0=12,0x2C1=F6 Skip the intro
+//! ```
+//!
+//! A `freeze` cheat writes its values every frame while a `patch` cheat
+//! writes them once when it's enabled.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use rand::Rng;
+
+use crate::chip8::{Chip8, RAM_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchFilter {
+    /// Keep the addresses holding the given value.
+    Equal(u8),
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+}
+
+/// Iterative search of the RAM addresses satisfying a sequence of filters,
+/// each filter compares the RAM against the snapshot taken by the previous
+/// one.
+#[derive(Debug, Clone)]
+pub struct MemorySearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<usize>,
+}
+
+impl MemorySearch {
+    /// Start a new search where every address is a candidate.
+    pub fn new(ram: &[u8]) -> Self {
+        MemorySearch {
+            snapshot: ram.to_vec(),
+            candidates: (0..ram.len()).collect(),
+        }
+    }
+
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+
+    pub fn filter(&mut self, ram: &[u8], filter: SearchFilter) {
+        let snapshot = &self.snapshot;
+
+        self.candidates.retain(|&addr| {
+            let (old, new) = (snapshot[addr], ram[addr]);
+
+            match filter {
+                SearchFilter::Equal(v) => new == v,
+                SearchFilter::Unchanged => new == old,
+                SearchFilter::Changed => new != old,
+                SearchFilter::Increased => new > old,
+                SearchFilter::Decreased => new < old,
+            }
+        });
+
+        self.snapshot.copy_from_slice(ram);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// Write the values at every frame.
+    Freeze,
+    /// Write the values only once.
+    Patch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub kind: CheatKind,
+    pub writes: Vec<(usize, u8)>,
+    enabled: bool,
+    applied: bool,
+}
+
+impl Cheat {
+    pub fn new(name: String, kind: CheatKind, writes: Vec<(usize, u8)>) -> Self {
+        Cheat {
+            name,
+            kind,
+            writes,
+            enabled: false,
+            applied: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.applied = false;
+    }
+
+    /// Apply the cheat if enabled, it's meant to be called once per frame.
+    pub fn apply<R: Rng>(&mut self, chip8: &mut Chip8<R>) {
+        if !self.enabled || (self.kind == CheatKind::Patch && self.applied) {
+            return;
+        }
+
+        for &(addr, value) in &self.writes {
+            chip8.poke(addr, value);
+        }
+
+        self.applied = true;
+    }
+}
+
+/// Collection of cheats keyed by the SHA-1 of the rom they apply to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheatFile {
+    roms: BTreeMap<String, Vec<Cheat>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl CheatFile {
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let mut file = CheatFile::default();
+        let mut rom = None;
+
+        for (i, line) in s.lines().enumerate() {
+            let err = |msg: &str| ParseError {
+                line: i + 1,
+                msg: msg.to_string(),
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let hash = line
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                    .ok_or_else(|| err("unterminated rom hash"))?;
+
+                rom = Some(hash.trim().to_lowercase());
+                continue;
+            }
+
+            let rom = rom
+                .as_ref()
+                .ok_or_else(|| err("cheat outside of a rom section"))?;
+
+            let mut parts = line.split_whitespace();
+            let kind = match parts.next() {
+                Some("freeze") => CheatKind::Freeze,
+                Some("patch") => CheatKind::Patch,
+                _ => return Err(err("cheat kind must be either freeze or patch")),
+            };
+
+            let writes = parts
+                .next()
+                .ok_or_else(|| err("missing cheat values"))?
+                .split(',')
+                .map(|w| parse_write(w).ok_or_else(|| err("values must be like 0x3A4=03")))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let name = parts.collect::<Vec<_>>().join(" ");
+
+            file.add(rom, Cheat::new(name, kind, writes));
+        }
+
+        Ok(file)
+    }
+
+    /// Cheats of the rom with the given SHA-1.
+    pub fn cheats(&self, rom_sha1: &str) -> &[Cheat] {
+        self.roms.get(rom_sha1).map_or(&[], |c| c.as_slice())
+    }
+
+    pub fn cheats_mut(&mut self, rom_sha1: &str) -> &mut Vec<Cheat> {
+        self.roms.entry(rom_sha1.to_string()).or_default()
+    }
+
+    pub fn add(&mut self, rom_sha1: &str, cheat: Cheat) {
+        self.cheats_mut(rom_sha1).push(cheat);
+    }
+}
+
+fn parse_write(w: &str) -> Option<(usize, u8)> {
+    let mut parts = w.trim().splitn(2, '=');
+
+    let addr = parts.next()?;
+    let addr = addr.strip_prefix("0x").unwrap_or(addr);
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+
+    let value = parts.next()?;
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    let value = u8::from_str_radix(value, 16).ok()?;
+
+    if addr >= RAM_SIZE {
+        return None;
+    }
+
+    Some((addr, value))
+}
+
+impl fmt::Display for CheatFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (rom, cheats) in self.roms.iter().filter(|(_, c)| !c.is_empty()) {
+            writeln!(f, "[{}]", rom)?;
+
+            for cheat in cheats {
+                let kind = match cheat.kind {
+                    CheatKind::Freeze => "freeze",
+                    CheatKind::Patch => "patch",
+                };
+
+                let writes = cheat
+                    .writes
+                    .iter()
+                    .map(|(addr, value)| format!("{:#05X}={:02X}", addr, value))
+                    .collect::<Vec<_>>();
+
+                writeln!(f, "{} {} {}", kind, writes.join(","), cheat.name)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_any_whitespace() {
+        let file = CheatFile::parse("[ABCD]\nfreeze  0x3A4=03\tInfinite   lives\npatch 0x2C0=12\n")
+            .unwrap();
+
+        assert_eq!(
+            file.cheats("abcd"),
+            [
+                Cheat::new(
+                    "Infinite lives".to_string(),
+                    CheatKind::Freeze,
+                    vec![(0x3A4, 0x03)]
+                ),
+                Cheat::new(String::new(), CheatKind::Patch, vec![(0x2C0, 0x12)]),
+            ]
+        );
+    }
+}
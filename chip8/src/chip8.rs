@@ -1,12 +1,21 @@
+use std::fmt;
+
 use rand::Rng;
 
+use crate::access::{Access, AccessMap};
+use crate::framebuffer::{Dirty, Framebuffer};
+use crate::quirks::Quirks;
+use crate::state::SaveState;
+
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const RAM_SIZE: usize = 4096;
 pub const PROGRAM_START_PC: usize = 0x200;
+/// Maximum number of nested subroutine calls.
+pub const STACK_SIZE: usize = 16;
 
 // each hex digit has a 4x5 sprite
-const FONT_SET: [u8; 80] = [
+pub(crate) const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -25,17 +34,35 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// Why a program cannot be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramError {
+    /// The program doesn't fit in RAM from its load address.
+    TooLarge { size: usize, max: usize },
+    /// The load address is out of RAM or in the font.
+    InvalidAddress(usize),
+}
+
+/// Notable things happened while running the program that frontends might want
+/// to report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The instruction at `pc` wrote to `addr` which had already been executed.
+    SelfModification { pc: usize, addr: usize },
+}
+
 #[derive(Clone)]
 pub struct Chip8<R: Rng> {
     registers: [u8; 16],
     i_reg: u16,
 
     ram: [u8; RAM_SIZE],
-    vram: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    vram: Framebuffer,
+    dirty: Dirty,
 
     pc: usize,
 
-    stack: [usize; 16],
+    stack: [usize; STACK_SIZE],
     sp: usize,
 
     delay_timer: u8,
@@ -44,33 +71,37 @@ pub struct Chip8<R: Rng> {
     waiting_keypress_reg: Option<usize>,
     keyboard: [bool; 16],
 
-    rng: R,
-}
+    /// The RPL user flags of `FX75` and `FX85`.
+    flags: [u8; 16],
 
-impl<R: Rng> Chip8<R> {
-    pub fn with_program(rng: R, program: &[u8]) -> Option<Self> {
-        if program.len() > RAM_SIZE - PROGRAM_START_PC {
-            return None;
-        }
+    rng: R,
 
-        let mut ram = [0; RAM_SIZE];
+    quirks: Quirks,
+    /// Whether the flags are kept by `reset`, like the HP48 kept them
+    /// between programs.
+    persistent_flags: bool,
 
-        (&mut ram[..FONT_SET.len()]).copy_from_slice(&FONT_SET);
+    /// The program loaded and its address, to start it over on reset.
+    program: Vec<u8>,
+    load_addr: usize,
 
-        for (dst, src) in (&mut ram[PROGRAM_START_PC..]).iter_mut().zip(program) {
-            *dst = *src;
-        }
+    accesses: AccessMap,
+    events: Vec<Event>,
+}
 
-        Some(Chip8 {
+impl<R: Rng> Chip8<R> {
+    pub fn with_program(rng: R, program: &[u8]) -> Option<Self> {
+        let mut chip8 = Chip8 {
             registers: [0; 16],
             i_reg: 0,
 
-            ram,
-            vram: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            ram: [0; RAM_SIZE],
+            vram: Framebuffer::new(),
+            dirty: Dirty::all(),
 
             pc: PROGRAM_START_PC,
 
-            stack: [0; 16],
+            stack: [0; STACK_SIZE],
             sp: 0,
 
             delay_timer: 0,
@@ -79,8 +110,98 @@ impl<R: Rng> Chip8<R> {
             waiting_keypress_reg: None,
             keyboard: [false; 16],
 
+            flags: [0; 16],
+
             rng,
-        })
+
+            quirks: Quirks::default(),
+            persistent_flags: false,
+
+            program: vec![],
+            load_addr: PROGRAM_START_PC,
+
+            accesses: AccessMap::new(),
+            events: vec![],
+        };
+
+        chip8.load_program(program).ok()?;
+        Some(chip8)
+    }
+
+    /// Replace the program with `program` loaded at `PROGRAM_START_PC` and
+    /// start it, see `reset`.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), ProgramError> {
+        self.load_at(PROGRAM_START_PC, program)
+    }
+
+    /// Replace the program with `program` loaded at `addr`, where it starts
+    /// from, e.g. 0x600 for the ETI-660 programs.
+    pub fn load_at(&mut self, addr: usize, program: &[u8]) -> Result<(), ProgramError> {
+        if addr < FONT_SET.len() || addr >= RAM_SIZE {
+            return Err(ProgramError::InvalidAddress(addr));
+        }
+        if program.len() > RAM_SIZE - addr {
+            return Err(ProgramError::TooLarge {
+                size: program.len(),
+                max: RAM_SIZE - addr,
+            });
+        }
+
+        self.program = program.to_vec();
+        self.load_addr = addr;
+        self.reset();
+
+        Ok(())
+    }
+
+    /// Start the program over: the RAM is restored to the font and the
+    /// program while the registers, the screen, the timers, the stack and the
+    /// keys are cleared. The quirks and the random number generator are kept
+    /// and so are the flags if they're persistent.
+    pub fn reset(&mut self) {
+        self.registers = [0; 16];
+        self.i_reg = 0;
+
+        self.ram = [0; RAM_SIZE];
+        self.ram[..FONT_SET.len()].copy_from_slice(&FONT_SET);
+        self.ram[self.load_addr..self.load_addr + self.program.len()]
+            .copy_from_slice(&self.program);
+
+        self.vram = Framebuffer::new();
+        self.dirty = Dirty::all();
+        self.pc = self.load_addr;
+        self.stack = [0; STACK_SIZE];
+        self.sp = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.waiting_keypress_reg = None;
+        self.keyboard = [false; 16];
+
+        if !self.persistent_flags {
+            self.flags = [0; 16];
+        }
+
+        self.accesses = AccessMap::new();
+        self.events.clear();
+    }
+
+    /// The program loaded and the address it's loaded at.
+    pub fn program(&self) -> (usize, &[u8]) {
+        (self.load_addr, &self.program)
+    }
+
+    /// Keep the RPL flags written by `FX75` across `reset` and
+    /// `load_program`.
+    pub fn set_persistent_flags(&mut self, persistent: bool) {
+        self.persistent_flags = persistent;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
 
     pub fn beep(&self) -> bool {
@@ -92,11 +213,129 @@ impl<R: Rng> Chip8<R> {
         self.sound_timer = self.sound_timer.saturating_sub(1);
     }
 
-    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, &u8)> {
-        self.vram
-            .iter()
-            .enumerate()
-            .flat_map(|(y, r)| r.iter().enumerate().map(move |(x, c)| (y, x, c)))
+    /// Iterate over all the pixels as `(y, x, lit)`, prefer `framebuffer()`
+    /// to read the screen in bulk.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+        self.vram.pixels()
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.vram
+    }
+
+    /// The rows changed since the last call, the first call returns the
+    /// whole screen.
+    pub fn take_dirty(&mut self) -> Dirty {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The registers `V0` to `VF`.
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn i_reg(&self) -> u16 {
+        self.i_reg
+    }
+
+    /// The return addresses of the calls in progress, the innermost last.
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    /// The delay and the sound timers.
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    /// Whether every hex key is pressed.
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keyboard
+    }
+
+    /// Overwrite `V0` to `VF` and I, e.g. to carry them over a reload.
+    pub fn set_registers(&mut self, registers: [u8; 16], i_reg: u16) {
+        self.registers = registers;
+        self.i_reg = i_reg;
+    }
+
+    /// Whether the program is blocked on `FX0A` waiting for a key.
+    pub fn waiting_keypress(&self) -> bool {
+        self.waiting_keypress_reg.is_some()
+    }
+
+    /// Whether the registers, timers, memory and screen of the two machines
+    /// are the same, the random number generators aren't compared.
+    pub fn same_state<S: Rng>(&self, other: &Chip8<S>) -> bool {
+        self.pc == other.pc
+            && self.registers == other.registers
+            && self.flags == other.flags
+            && self.i_reg == other.i_reg
+            && self.sp == other.sp
+            && self.stack == other.stack
+            && self.delay_timer == other.delay_timer
+            && self.sound_timer == other.sound_timer
+            && self.waiting_keypress_reg == other.waiting_keypress_reg
+            && self.vram == other.vram
+            && self.ram[..] == other.ram[..]
+    }
+
+    /// Snapshot of the machine, see `SaveState`.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            registers: self.registers,
+            i_reg: self.i_reg,
+            flags: self.flags,
+            ram: self.ram.to_vec(),
+            vram: self.vram.clone(),
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            waiting_keypress_reg: self.waiting_keypress_reg,
+            quirks: self.quirks,
+        }
+    }
+
+    /// Restore a snapshot taken by `save_state`, the keys pressed and the
+    /// random number generator are left alone.
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.registers = state.registers;
+        self.i_reg = state.i_reg;
+        self.flags = state.flags;
+        self.ram.copy_from_slice(&state.ram);
+        self.vram = state.vram.clone();
+        self.dirty = Dirty::all();
+        self.pc = state.pc;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.waiting_keypress_reg = state.waiting_keypress_reg;
+        self.quirks = state.quirks;
+    }
+
+    /// Write a byte of RAM from outside of the program, e.g. to apply cheats.
+    /// The write isn't tracked in the access map.
+    pub fn poke(&mut self, addr: usize, value: u8) {
+        self.ram[addr] = value;
+    }
+
+    pub fn accesses(&self) -> &AccessMap {
+        &self.accesses
+    }
+
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
     }
 
     pub fn keypress(&mut self, hex_key: u8) {
@@ -129,6 +368,7 @@ impl<R: Rng> Chip8<R> {
         }
 
         let instr = (u16::from(self.ram[self.pc]) << 8) | u16::from(self.ram[self.pc + 1]);
+        self.accesses.execute(self.pc);
         self.pc += 2;
 
         let opcode = instr >> 12;
@@ -158,9 +398,9 @@ impl<R: Rng> Chip8<R> {
             0x8 if n == 0x3 => self.xor(x, y),
             0x8 if n == 0x4 => self.add_rr(x, y),
             0x8 if n == 0x5 => self.sub_rr(x, y),
-            0x8 if n == 0x6 => self.shiftr(x),
+            0x8 if n == 0x6 => self.shiftr(x, y),
             0x8 if n == 0x7 => self.sub_rr_inv(x, y),
-            0x8 if n == 0xE => self.shiftl(x),
+            0x8 if n == 0xE => self.shiftl(x, y),
 
             0x9 => self.skip_if_ne_rr(x, y),
 
@@ -185,6 +425,8 @@ impl<R: Rng> Chip8<R> {
             0xF if nn == 0x33 => self.bcd(x),
             0xF if nn == 0x55 => self.dump_regs(x),
             0xF if nn == 0x65 => self.load_regs(x),
+            0xF if nn == 0x75 => self.store_flags(x),
+            0xF if nn == 0x85 => self.load_flags(x),
 
             _ => unreachable!("opcode {:X} not recognized at pc: {:X}", instr, self.pc - 2),
         };
@@ -211,7 +453,13 @@ impl<R: Rng> Chip8<R> {
     }
 
     fn goto_off(&mut self, pc: u16) {
-        self.pc = usize::from(pc) + usize::from(self.registers[0]);
+        let x = if self.quirks.jump {
+            usize::from(pc >> 8)
+        } else {
+            0
+        };
+
+        self.pc = usize::from(pc) + usize::from(self.registers[x]);
     }
 
     // ------------------------------------------------------------------------
@@ -291,24 +539,45 @@ impl<R: Rng> Chip8<R> {
     // ------------------------------------------------------------------------
     fn or(&mut self, x: usize, y: usize) {
         self.registers[x] |= self.registers[y];
+        self.vf_reset();
     }
 
     fn and(&mut self, x: usize, y: usize) {
         self.registers[x] &= self.registers[y];
+        self.vf_reset();
     }
 
     fn xor(&mut self, x: usize, y: usize) {
         self.registers[x] ^= self.registers[y];
+        self.vf_reset();
     }
 
-    fn shiftr(&mut self, x: usize) {
-        self.registers[0xF] = self.registers[x] & 0x1;
-        self.registers[x] >>= 1;
+    fn vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
-    fn shiftl(&mut self, x: usize) {
-        self.registers[0xF] = self.registers[x] >> 7;
-        self.registers[x] <<= 1;
+    fn shiftr(&mut self, x: usize, y: usize) {
+        let r = if self.quirks.shift {
+            self.registers[x]
+        } else {
+            self.registers[y]
+        };
+
+        self.registers[0xF] = r & 0x1;
+        self.registers[x] = r >> 1;
+    }
+
+    fn shiftl(&mut self, x: usize, y: usize) {
+        let r = if self.quirks.shift {
+            self.registers[x]
+        } else {
+            self.registers[y]
+        };
+
+        self.registers[0xF] = r >> 7;
+        self.registers[x] = r << 1;
     }
 
     // ------------------------------------------------------------------------
@@ -332,12 +601,39 @@ impl<R: Rng> Chip8<R> {
         let i = usize::from(self.i_reg);
 
         self.ram[i..=x + i].copy_from_slice(&self.registers[..=x]);
+        self.track_write(i..i + x + 1, Access::REGS_WRITTEN);
+
+        if self.quirks.load_store {
+            self.i_reg += x as u16 + 1;
+        }
+    }
+
+    fn store_flags(&mut self, x: usize) {
+        self.flags[..=x].copy_from_slice(&self.registers[..=x]);
+    }
+
+    fn load_flags(&mut self, x: usize) {
+        self.registers[..=x].copy_from_slice(&self.flags[..=x]);
     }
 
     fn load_regs(&mut self, x: usize) {
         let i = usize::from(self.i_reg);
 
         self.registers[..=x].copy_from_slice(&self.ram[i..=i + x]);
+        self.accesses.read(i..i + x + 1, Access::REGS_READ);
+
+        if self.quirks.load_store {
+            self.i_reg += x as u16 + 1;
+        }
+    }
+
+    fn track_write(&mut self, range: std::ops::Range<usize>, access: Access) {
+        // note: here self.pc is already after the current instruction
+        let pc = self.pc - 2;
+
+        for addr in self.accesses.write(range, access) {
+            self.events.push(Event::SelfModification { pc, addr });
+        }
     }
 
     // ------------------------------------------------------------------------
@@ -386,6 +682,7 @@ impl<R: Rng> Chip8<R> {
         self.ram[i] = r / 100;
         self.ram[i + 1] = (r / 10) % 10;
         self.ram[i + 2] = r % 10;
+        self.track_write(i..i + 3, Access::BCD_WRITTEN);
     }
 
     fn rand(&mut self, x: usize, nn: u8) {
@@ -396,11 +693,12 @@ impl<R: Rng> Chip8<R> {
     // Graphics
     // ------------------------------------------------------------------------
     fn clear_vram(&mut self) {
-        for row in self.vram.iter_mut() {
-            for b in row.iter_mut() {
-                *b = 0;
-            }
+        let vram = &self.vram;
+        for y in (0..vram.height()).filter(|y| vram.row(*y) != 0) {
+            self.dirty.mark_row(y);
         }
+
+        self.vram.clear();
     }
 
     fn draw(&mut self, x: usize, y: usize, n: u8) {
@@ -410,18 +708,55 @@ impl<R: Rng> Chip8<R> {
         let x = usize::from(self.registers[x]);
 
         let sprite_start = usize::from(self.i_reg);
+        self.accesses.read(
+            sprite_start..sprite_start + usize::from(n),
+            Access::SPRITE_READ,
+        );
+
+        // the starting position always wraps around, what goes past the
+        // edges wraps around as well unless clipping
+        let (x, y) = (x % self.vram.width(), y % self.vram.height());
 
-        for i in 0..usize::from(n) {
-            let row = &mut self.vram[(y + i) % self.vram.len()];
-            let sprite_row = self.ram[sprite_start + i];
+        let sprite = &self.ram[sprite_start..sprite_start + usize::from(n)];
+        let collision = self.vram.draw_sprite(x, y, sprite, self.quirks.clip);
 
-            for b in 0..8 {
-                let cur_row_pix = &mut row[(x + b) % row.len()];
-                let sprite_pix = (sprite_row >> (7 - b)) & 0x1;
+        self.dirty.mark_sprite();
 
-                self.registers[0xF] |= *cur_row_pix & sprite_pix;
-                *cur_row_pix ^= sprite_pix;
+        let h = self.vram.height();
+        for (i, _) in sprite.iter().enumerate().filter(|(_, b)| **b != 0) {
+            if self.quirks.clip && y + i >= h {
+                break;
             }
+            self.dirty.mark_row((y + i) % h);
+        }
+
+        self.registers[0xF] = u8::from(collision);
+    }
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::TooLarge { size, max } => write!(
+                f,
+                "program too large: {} bytes while at most {} fit",
+                size, max
+            ),
+            ProgramError::InvalidAddress(addr) => write!(f, "invalid load address {:#05X}", addr),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::SelfModification { pc, addr } => write!(
+                f,
+                "self modifying code: instruction at {:#05X} wrote to {:#05X}",
+                pc, addr
+            ),
         }
     }
 }
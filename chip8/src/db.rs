@@ -0,0 +1,266 @@
+//! Database of known roms with the settings they play best with.
+//!
+//! Roms are identified by the SHA-1 or SHA-256 of their bytes and the
+//! database can be loaded from JSON or TOML files shaped like the builtin one:
+//!
+//! ```json
+//! {
+//!   "roms": [
+//!     {
+//!       "title": "Pong",
+//!       "author": "Paul Vervalin",
+//!       "year": 1990,
+//!       "platform": "chip8",
+//!       "quirks": "default",
+//!       "instructions_per_frame": 8,
+//!       "keys": { "1": "left paddle up", "4": "left paddle down" },
+//!       "gamepads": [{ "dpup": "1", "dpdown": "4" }, { "dpup": "C", "dpdown": "D" }],
+//!       "palette": ["#000000", "#FFFFFF"],
+//!       "sha1": "a60611339661e3ab2d8af024ad1da5880a6f8665"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `quirks` is either the name of a preset (`default`, `vip`, `schip` or
+//! `xochip`) or a table with the individual quirks. `palette` is either a
+//! list of colors or a single builtin palette name, like `["amber"]`.
+//! `gamepads` has the hex keys of the gamepad of every player, see
+//! `input::Padmap`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::hash;
+use crate::input::{self, Padmap};
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+
+const BUILTIN: &str = include_str!("../roms.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomInfo {
+    pub title: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub year: Option<u16>,
+    #[serde(default = "default_platform")]
+    pub platform: Platform,
+    #[serde(default, deserialize_with = "deserialize_quirks")]
+    pub quirks: Quirks,
+    #[serde(default)]
+    pub instructions_per_frame: Option<usize>,
+    /// Description of what each key does keyed by the hex digit of the key.
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+    /// Either the name of a builtin palette or from 2 to 4 colors as
+    /// `#RRGGBB`, see the `palette` module.
+    #[serde(default)]
+    pub palette: Vec<String>,
+    /// Hex keys of the gamepad inputs of every player, the left stick goes
+    /// along with the D-pad unless bound.
+    #[serde(default)]
+    pub gamepads: Vec<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub sha1: Option<String>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomDatabase {
+    #[serde(default)]
+    roms: Vec<RomInfo>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+}
+
+impl Platform {
+    /// The quirks programs written for the platform usually expect.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::Schip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+}
+
+impl RomDatabase {
+    /// The database shipped with the crate, it contains all the games in the
+    /// `games` directory.
+    pub fn builtin() -> Self {
+        RomDatabase::from_json(BUILTIN).expect("the builtin rom database is invalid")
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, LoadError> {
+        serde_json::from_str(s).map_err(LoadError::Json)
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, LoadError> {
+        toml::from_str(s).map_err(LoadError::Toml)
+    }
+
+    pub fn roms(&self) -> &[RomInfo] {
+        &self.roms
+    }
+
+    /// Add all the roms of `other`, its entries take precedence over the
+    /// existing ones.
+    pub fn merge(&mut self, other: RomDatabase) {
+        let mut roms = other.roms;
+        roms.append(&mut self.roms);
+        self.roms = roms;
+    }
+
+    /// Find the entry of the given program, if any.
+    pub fn lookup(&self, program: &[u8]) -> Option<&RomInfo> {
+        let sha1 = hash::sha1(program);
+        let sha256 = hash::sha256(program);
+
+        self.roms.iter().find(|r| {
+            r.sha1
+                .as_ref()
+                .is_some_and(|h| h.eq_ignore_ascii_case(&sha1))
+                || r.sha256
+                    .as_ref()
+                    .is_some_and(|h| h.eq_ignore_ascii_case(&sha256))
+        })
+    }
+}
+
+impl RomInfo {
+    /// The palette of the rom, if it has a valid one.
+    pub fn palette(&self) -> Option<Palette> {
+        match self.palette.as_slice() {
+            [name] => Palette::find_builtin(name),
+            colors => Palette::from_hex(&self.title, colors, None).ok(),
+        }
+    }
+
+    /// The gamepads of every player, the bindings with unknown inputs or
+    /// invalid hex keys are skipped.
+    pub fn padmaps(&self) -> Vec<Padmap> {
+        self.gamepads
+            .iter()
+            .map(|bindings| {
+                let mut padmap = Padmap::empty();
+                for (pad_input, hex_key) in bindings {
+                    if let (true, Some(hex_key)) = (
+                        input::is_pad_input(pad_input),
+                        input::parse_hex_key(hex_key),
+                    ) {
+                        padmap.bind(pad_input, hex_key);
+                    }
+                }
+                padmap.stick_follows_dpad();
+                padmap
+            })
+            .collect()
+    }
+}
+
+/// Parse a color in the `#RRGGBB` format.
+pub fn parse_hex_color(s: &str) -> Option<[u8; 3]> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if s.len() != 6 {
+        return None;
+    }
+
+    let c = u32::from_str_radix(s, 16).ok()?;
+    Some([(c >> 16) as u8, (c >> 8) as u8, c as u8])
+}
+
+fn default_platform() -> Platform {
+    Platform::Chip8
+}
+
+fn deserialize_quirks<'de, D: Deserializer<'de>>(d: D) -> Result<Quirks, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum QuirksDef {
+        Preset(String),
+        Custom(Quirks),
+    }
+
+    match QuirksDef::deserialize(d)? {
+        QuirksDef::Preset(name) => Quirks::preset(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown quirks preset {}", name))),
+        QuirksDef::Custom(quirks) => Ok(quirks),
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::Schip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Json(e) => write!(f, "invalid json rom database: {}", e),
+            LoadError::Toml(e) => write!(f, "invalid toml rom database: {}", e),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn builtin_has_all_games() {
+        let db = RomDatabase::builtin();
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../games");
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let info = db.lookup(&fs::read(&path).unwrap());
+            assert!(info.is_some(), "{} is missing", path.display());
+        }
+
+        let blitz = db.roms().iter().find(|r| r.title == "Blitz").unwrap();
+        assert_eq!(
+            blitz.quirks,
+            Quirks {
+                clip: true,
+                ..Quirks::default()
+            }
+        );
+
+        let kaleid = db
+            .roms()
+            .iter()
+            .find(|r| r.title == "Kaleidoscope")
+            .unwrap();
+        assert_eq!(kaleid.quirks, Quirks::vip());
+        assert_eq!(kaleid.palette(), Palette::find_builtin("vip"));
+    }
+}
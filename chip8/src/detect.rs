@@ -0,0 +1,366 @@
+//! Heuristic detection of the platform and quirks a program has been written
+//! for, used when a rom is not in any database.
+//!
+//! Only the code reached by the static analysis is scanned so that sprites
+//! and other data don't count as evidence. Every finding votes for or against
+//! a quirk and the confidence of each decision grows with the number of votes
+//! agreeing with it.
+
+use std::fmt;
+
+use crate::analysis::{Analysis, SuspiciousKind};
+use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH, PROGRAM_START_PC};
+use crate::db::Platform;
+use crate::disasm::Instr;
+use crate::quirks::Quirks;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evidence {
+    /// An instruction only available on SUPER-CHIP and later.
+    SchipOpcode(u16),
+    /// An instruction only available on XO-CHIP.
+    XoChipOpcode(u16),
+    /// `8XY6` or `8XYE` with X different from Y right after setting VY, as if
+    /// VY was the register being shifted.
+    ShiftFromVy,
+    /// `8XY6` or `8XYE` with X different from Y right after setting VX, as if
+    /// VX was shifted in place.
+    ShiftInPlace,
+    /// `FX55` or `FX65` followed by another one without setting I, as if I
+    /// had moved past the registers.
+    LoadStoreChained,
+    /// `FX55` or `FX65` followed by I being advanced manually.
+    LoadStoreManualAdvance,
+    /// `BXNN` right after setting VX but not V0.
+    JumpFromVx,
+    /// `BNNN` right after setting V0.
+    JumpFromV0,
+    /// A sprite drawn at a constant position crossing the screen edges.
+    SpriteOverEdge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    pub addr: usize,
+    pub evidence: Evidence,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// How much the findings back the recommendation, between 0 and 1. It's
+    /// 0.5 when nothing points in any direction.
+    pub confidence: f32,
+    pub findings: Vec<Finding>,
+}
+
+/// Votes for and against enabling something.
+#[derive(Debug, Clone, Copy, Default)]
+struct Votes {
+    pro: u32,
+    con: u32,
+}
+
+impl Votes {
+    fn decide(self, default: bool) -> (bool, f32) {
+        let decision = match self.pro.cmp(&self.con) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Equal => default,
+        };
+
+        // Laplace smoothing so that no evidence at all is a coin toss
+        let agreeing = if decision { self.pro } else { self.con };
+        let confidence = (agreeing + 1) as f32 / (self.pro + self.con + 2) as f32;
+
+        (decision, confidence)
+    }
+}
+
+/// Scan the program and recommend the platform and quirks to run it with.
+pub fn detect(program: &[u8]) -> Detection {
+    let analysis = Analysis::new(program);
+    let mut findings = vec![];
+
+    let word_at = |addr: usize| {
+        let off = addr - PROGRAM_START_PC;
+        (u16::from(program[off]) << 8) | u16::from(program[off + 1])
+    };
+
+    // extended instructions are either invalid or machine code routines for
+    // the analysis, which stops exploring there
+    for s in &analysis.suspicious {
+        let evidence = match s.kind {
+            SuspiciousKind::InvalidInstruction | SuspiciousKind::MachineCodeRoutine => {
+                extended_opcode(word_at(s.addr))
+            }
+            _ => None,
+        };
+
+        if let Some(evidence) = evidence {
+            findings.push(Finding {
+                addr: s.addr,
+                evidence,
+            });
+        }
+    }
+
+    for block in analysis.blocks.values() {
+        for (i, &(addr, instr)) in block.instrs.iter().enumerate() {
+            let before = &block.instrs[..i];
+            let after = &block.instrs[i + 1..];
+
+            let evidence = match instr {
+                // 16x16 sprites on SUPER-CHIP, nothing at all on CHIP-8
+                Instr::Draw(x, y, 0) => Some(Evidence::SchipOpcode(
+                    0xD000 | (x as u16) << 8 | (y as u16) << 4,
+                )),
+                // the RPL flags are valid instructions for the interpreter
+                Instr::StoreFlags(_) | Instr::LoadFlags(_) => {
+                    Some(Evidence::SchipOpcode(instr.encode()))
+                }
+                // the decoder takes any 5XYN for 5XY0, XO-CHIP has 5XY2 and
+                // 5XY3 among them
+                Instr::SkipEqRr(..) if word_at(addr) & 0xF != 0 => extended_opcode(word_at(addr)),
+                Instr::ShiftR(x, y) | Instr::ShiftL(x, y) if x != y => shift_evidence(x, y, before),
+                Instr::DumpRegs(_) | Instr::LoadRegs(_) => load_store_evidence(after),
+                Instr::JumpOff(nnn) => jump_evidence(usize::from(nnn >> 8), before),
+                Instr::Draw(x, y, n) => sprite_evidence(x, y, n, before),
+                _ => None,
+            };
+
+            if let Some(evidence) = evidence {
+                findings.push(Finding { addr, evidence });
+            }
+        }
+    }
+
+    findings.sort_by_key(|f| f.addr);
+
+    let has = |f: fn(&Evidence) -> bool| findings.iter().any(|e| f(&e.evidence));
+    let platform = if has(|e| matches!(e, Evidence::XoChipOpcode(_))) {
+        Platform::XoChip
+    } else if has(|e| matches!(e, Evidence::SchipOpcode(_))) {
+        Platform::Schip
+    } else {
+        Platform::Chip8
+    };
+
+    let mut shift = Votes::default();
+    let mut load_store = Votes::default();
+    let mut jump = Votes::default();
+    let mut clip = Votes::default();
+    let mut platform_votes = Votes::default();
+
+    for f in &findings {
+        match f.evidence {
+            Evidence::SchipOpcode(_) | Evidence::XoChipOpcode(_) => platform_votes.pro += 1,
+            Evidence::ShiftFromVy => shift.con += 1,
+            Evidence::ShiftInPlace => shift.pro += 1,
+            Evidence::LoadStoreChained => load_store.pro += 1,
+            Evidence::LoadStoreManualAdvance => load_store.con += 1,
+            Evidence::JumpFromVx => jump.pro += 1,
+            Evidence::JumpFromV0 => jump.con += 1,
+            Evidence::SpriteOverEdge => clip.pro += 1,
+        }
+    }
+
+    let defaults = platform.quirks();
+    let (shift, shift_confidence) = shift.decide(defaults.shift);
+    let (load_store, load_store_confidence) = load_store.decide(defaults.load_store);
+    let (jump, jump_confidence) = jump.decide(defaults.jump);
+    let (clip, clip_confidence) = clip.decide(defaults.clip);
+    let (_, platform_confidence) = platform_votes.decide(platform != Platform::Chip8);
+
+    let quirks = Quirks {
+        shift,
+        load_store,
+        jump,
+        clip,
+        ..defaults
+    };
+
+    let confidence = (platform_confidence
+        + shift_confidence
+        + load_store_confidence
+        + jump_confidence
+        + clip_confidence)
+        / 5.0;
+
+    Detection {
+        platform,
+        quirks,
+        confidence,
+        findings,
+    }
+}
+
+fn extended_opcode(word: u16) -> Option<Evidence> {
+    let x = (word >> 8) & 0xF;
+    let nn = word & 0xFF;
+
+    match word >> 12 {
+        0x0 if x == 0 && (nn & 0xF0 == 0xC0 || (0xFB..=0xFF).contains(&nn)) => {
+            Some(Evidence::SchipOpcode(word))
+        }
+        0x0 if x == 0 && nn & 0xF0 == 0xD0 => Some(Evidence::XoChipOpcode(word)),
+        0x5 if word & 0xF == 0x2 || word & 0xF == 0x3 => Some(Evidence::XoChipOpcode(word)),
+        0xF if nn == 0x30 => Some(Evidence::SchipOpcode(word)),
+        0xF if word == 0xF000 || word == 0xF002 || nn == 0x01 || nn == 0x3A => {
+            Some(Evidence::XoChipOpcode(word))
+        }
+        _ => None,
+    }
+}
+
+fn shift_evidence(x: usize, y: usize, before: &[(usize, Instr)]) -> Option<Evidence> {
+    for (_, instr) in before.iter().rev() {
+        if instr.writes_register(y) {
+            return Some(Evidence::ShiftFromVy);
+        }
+        if instr.writes_register(x) {
+            return Some(Evidence::ShiftInPlace);
+        }
+    }
+
+    None
+}
+
+fn load_store_evidence(after: &[(usize, Instr)]) -> Option<Evidence> {
+    for (_, instr) in after {
+        match instr {
+            Instr::SetI(_) => return None,
+            Instr::AddI(_) => return Some(Evidence::LoadStoreManualAdvance),
+            Instr::DumpRegs(_) | Instr::LoadRegs(_) => return Some(Evidence::LoadStoreChained),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn jump_evidence(x: usize, before: &[(usize, Instr)]) -> Option<Evidence> {
+    if x == 0 {
+        return None;
+    }
+
+    for (_, instr) in before.iter().rev() {
+        if instr.writes_register(0) {
+            return Some(Evidence::JumpFromV0);
+        }
+        if instr.writes_register(x) {
+            return Some(Evidence::JumpFromVx);
+        }
+    }
+
+    None
+}
+
+fn sprite_evidence(x: usize, y: usize, n: u8, before: &[(usize, Instr)]) -> Option<Evidence> {
+    let constant = |r: usize| {
+        for (_, instr) in before.iter().rev() {
+            match *instr {
+                Instr::Load(rr, nn) if rr == r => return Some(usize::from(nn)),
+                i if i.writes_register(r) => return None,
+                _ => {}
+            }
+        }
+
+        None
+    };
+
+    let over_x = constant(x).is_some_and(|vx| vx % DISPLAY_WIDTH + 8 > DISPLAY_WIDTH);
+    let over_y =
+        constant(y).is_some_and(|vy| vy % DISPLAY_HEIGHT + usize::from(n) > DISPLAY_HEIGHT);
+
+    if over_x || over_y {
+        Some(Evidence::SpriteOverEdge)
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Evidence::SchipOpcode(w) => write!(f, "SUPER-CHIP instruction {:04X}", w),
+            Evidence::XoChipOpcode(w) => write!(f, "XO-CHIP instruction {:04X}", w),
+            Evidence::ShiftFromVy => {
+                write!(f, "VY shifted right after setting it (shift quirk off)")
+            }
+            Evidence::ShiftInPlace => {
+                write!(f, "VX shifted right after setting it (shift quirk on)")
+            }
+            Evidence::LoadStoreChained => write!(
+                f,
+                "registers stored or loaded again without setting I (load/store quirk on)"
+            ),
+            Evidence::LoadStoreManualAdvance => write!(
+                f,
+                "I advanced after storing or loading registers (load/store quirk off)"
+            ),
+            Evidence::JumpFromVx => {
+                write!(f, "computed jump right after setting VX (jump quirk on)")
+            }
+            Evidence::JumpFromV0 => {
+                write!(f, "computed jump right after setting V0 (jump quirk off)")
+            }
+            Evidence::SpriteOverEdge => {
+                write!(f, "sprite drawn across the screen edge (clip quirk on)")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#05X}: {}", self.addr, self.evidence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpl_flags_are_schip() {
+        // CLS, LD R, V3, JP 0x202
+        let detection = detect(&[0x00, 0xE0, 0xF3, 0x75, 0x12, 0x02]);
+
+        assert_eq!(detection.platform, Platform::Schip);
+        assert_eq!(
+            detection.findings,
+            vec![Finding {
+                addr: 0x202,
+                evidence: Evidence::SchipOpcode(0xF375),
+            }]
+        );
+    }
+
+    #[test]
+    fn draw_sets_vf() {
+        // LD V1, 1 / DRW V0, V0, 1 / SHR VF, V1 / JP 0x206
+        let detection = detect(&[0x61, 0x01, 0xD0, 0x01, 0x8F, 0x16, 0x12, 0x06]);
+
+        // VF was written by the collision flag after V1 was set
+        assert_eq!(
+            detection.findings,
+            vec![Finding {
+                addr: 0x204,
+                evidence: Evidence::ShiftInPlace,
+            }]
+        );
+    }
+
+    #[test]
+    fn save_load_range_is_xochip() {
+        // CLS, SAVE V1 - V2, JP 0x202
+        let detection = detect(&[0x00, 0xE0, 0x51, 0x22, 0x12, 0x02]);
+        assert_eq!(detection.platform, Platform::XoChip);
+
+        // CLS, SE V1, V2, JP 0x202
+        let detection = detect(&[0x00, 0xE0, 0x51, 0x20, 0x12, 0x02]);
+        assert_eq!(detection.platform, Platform::Chip8);
+    }
+}
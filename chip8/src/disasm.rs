@@ -0,0 +1,453 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::access::{Access, AccessMap};
+use crate::analysis::Analysis;
+use crate::chip8::PROGRAM_START_PC;
+
+/// A decoded CHIP-8 instruction.
+///
+/// The variants mirror the operations implemented by `Chip8::emulate_cycle`
+/// and keep the same operand types: registers are indexes, addresses are the
+/// raw 12 bit `nnn` operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Sys(u16),
+    Cls,
+    Ret,
+
+    Jump(u16),
+    Call(u16),
+    SkipEqNn(usize, u8),
+    SkipNeNn(usize, u8),
+    SkipEqRr(usize, usize),
+
+    Load(usize, u8),
+    AddNn(usize, u8),
+
+    Assign(usize, usize),
+    Or(usize, usize),
+    And(usize, usize),
+    Xor(usize, usize),
+    AddRr(usize, usize),
+    SubRr(usize, usize),
+    ShiftR(usize, usize),
+    SubRrInv(usize, usize),
+    ShiftL(usize, usize),
+
+    SkipNeRr(usize, usize),
+
+    SetI(u16),
+    JumpOff(u16),
+    Rand(usize, u8),
+    Draw(usize, usize, u8),
+
+    SkipPressed(usize),
+    SkipNotPressed(usize),
+
+    StoreDelay(usize),
+    WaitKeypress(usize),
+    SetDelayTimer(usize),
+    SetSoundTimer(usize),
+    AddI(usize),
+    FontSpriteAddr(usize),
+    Bcd(usize),
+    DumpRegs(usize),
+    LoadRegs(usize),
+    StoreFlags(usize),
+    LoadFlags(usize),
+}
+
+/// A line of a disassembled program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    Code {
+        addr: usize,
+        word: u16,
+        instr: Instr,
+        self_modified: bool,
+    },
+    Data {
+        addr: usize,
+        byte: u8,
+        access: Access,
+    },
+}
+
+/// Disassemble a program loaded at `PROGRAM_START_PC`.
+///
+/// Bytes reached by the static analysis are printed as code, everything else
+/// as data. If `accesses` is given, what has been observed at runtime takes
+/// precedence so that executed bytes are always code and bytes only ever used
+/// as data are printed as such.
+pub fn disassemble(program: &[u8], accesses: Option<&AccessMap>) -> Vec<Line> {
+    let analysis = Analysis::new(program);
+    let static_code = analysis
+        .blocks
+        .values()
+        .flat_map(|b| b.instrs.iter().map(|(addr, _)| *addr))
+        .collect::<BTreeSet<_>>();
+
+    let access = |addr: usize| accesses.map_or(Access::NONE, |a| a.get(addr));
+
+    let is_code = |addr: usize| {
+        let a = access(addr);
+        if a.contains(Access::OPCODE) {
+            return true;
+        }
+
+        static_code.contains(&addr) && !a.is_data() && !access(addr + 1).is_data()
+    };
+
+    let mut lines = vec![];
+    let mut off = 0;
+    while off < program.len() {
+        let addr = PROGRAM_START_PC + off;
+
+        let instr = if is_code(addr) {
+            Instr::fetch(program, off)
+        } else {
+            None
+        };
+
+        match instr {
+            Some(instr) => {
+                let word = (u16::from(program[off]) << 8) | u16::from(program[off + 1]);
+                let self_modified = access(addr).contains(Access::SELF_MODIFIED)
+                    || access(addr + 1).contains(Access::SELF_MODIFIED);
+
+                lines.push(Line::Code {
+                    addr,
+                    word,
+                    instr,
+                    self_modified,
+                });
+                off += 2;
+            }
+            None => {
+                lines.push(Line::Data {
+                    addr,
+                    byte: program[off],
+                    access: access(addr),
+                });
+                off += 1;
+            }
+        }
+    }
+
+    lines
+}
+
+impl Instr {
+    /// Decode a big endian instruction word, returns `None` if the word is not
+    /// a valid CHIP-8 instruction.
+    pub fn decode(instr: u16) -> Option<Self> {
+        let opcode = instr >> 12;
+        let x = usize::from((instr >> 8) & 0xF);
+        let y = usize::from((instr >> 4) & 0xF);
+        let nnn = instr & 0xFFF;
+        let nn = (instr & 0xFF) as u8;
+        let n = (instr & 0xF) as u8;
+
+        let i = match opcode {
+            0x0 if nnn == 0xE0 => Instr::Cls,
+            0x0 if nnn == 0xEE => Instr::Ret,
+            0x0 => Instr::Sys(nnn),
+
+            0x1 => Instr::Jump(nnn),
+            0x2 => Instr::Call(nnn),
+            0x3 => Instr::SkipEqNn(x, nn),
+            0x4 => Instr::SkipNeNn(x, nn),
+            0x5 => Instr::SkipEqRr(x, y),
+
+            0x6 => Instr::Load(x, nn),
+            0x7 => Instr::AddNn(x, nn),
+
+            0x8 if n == 0x0 => Instr::Assign(x, y),
+            0x8 if n == 0x1 => Instr::Or(x, y),
+            0x8 if n == 0x2 => Instr::And(x, y),
+            0x8 if n == 0x3 => Instr::Xor(x, y),
+            0x8 if n == 0x4 => Instr::AddRr(x, y),
+            0x8 if n == 0x5 => Instr::SubRr(x, y),
+            0x8 if n == 0x6 => Instr::ShiftR(x, y),
+            0x8 if n == 0x7 => Instr::SubRrInv(x, y),
+            0x8 if n == 0xE => Instr::ShiftL(x, y),
+
+            0x9 => Instr::SkipNeRr(x, y),
+
+            0xA => Instr::SetI(nnn),
+            0xB => Instr::JumpOff(nnn),
+            0xC => Instr::Rand(x, nn),
+            0xD => Instr::Draw(x, y, n),
+
+            0xE if nn == 0x9E => Instr::SkipPressed(x),
+            0xE if nn == 0xA1 => Instr::SkipNotPressed(x),
+
+            0xF if nn == 0x07 => Instr::StoreDelay(x),
+            0xF if nn == 0x0A => Instr::WaitKeypress(x),
+            0xF if nn == 0x15 => Instr::SetDelayTimer(x),
+            0xF if nn == 0x18 => Instr::SetSoundTimer(x),
+            0xF if nn == 0x1E => Instr::AddI(x),
+            0xF if nn == 0x29 => Instr::FontSpriteAddr(x),
+            0xF if nn == 0x33 => Instr::Bcd(x),
+            0xF if nn == 0x55 => Instr::DumpRegs(x),
+            0xF if nn == 0x65 => Instr::LoadRegs(x),
+            0xF if nn == 0x75 => Instr::StoreFlags(x),
+            0xF if nn == 0x85 => Instr::LoadFlags(x),
+
+            _ => return None,
+        };
+
+        Some(i)
+    }
+
+    /// Decode the instruction at `addr` in `mem`, returns `None` if the
+    /// instruction doesn't fit in `mem` or it's not valid.
+    pub fn fetch(mem: &[u8], addr: usize) -> Option<Self> {
+        let hi = *mem.get(addr)?;
+        let lo = *mem.get(addr + 1)?;
+
+        Instr::decode((u16::from(hi) << 8) | u16::from(lo))
+    }
+
+    /// Encode the instruction as a big endian instruction word, the opposite
+    /// of `decode`. Operands out of range are truncated.
+    pub fn encode(self) -> u16 {
+        let xnn = |op: u16, x: usize, nn: u8| op << 12 | (x as u16 & 0xF) << 8 | u16::from(nn);
+        let xyn = |op: u16, x: usize, y: usize, n: u8| {
+            op << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | u16::from(n & 0xF)
+        };
+        let nnn = |op: u16, nnn: u16| op << 12 | (nnn & 0xFFF);
+
+        match self {
+            Instr::Sys(a) => nnn(0x0, a),
+            Instr::Cls => 0x00E0,
+            Instr::Ret => 0x00EE,
+
+            Instr::Jump(a) => nnn(0x1, a),
+            Instr::Call(a) => nnn(0x2, a),
+            Instr::SkipEqNn(x, nn) => xnn(0x3, x, nn),
+            Instr::SkipNeNn(x, nn) => xnn(0x4, x, nn),
+            Instr::SkipEqRr(x, y) => xyn(0x5, x, y, 0x0),
+
+            Instr::Load(x, nn) => xnn(0x6, x, nn),
+            Instr::AddNn(x, nn) => xnn(0x7, x, nn),
+
+            Instr::Assign(x, y) => xyn(0x8, x, y, 0x0),
+            Instr::Or(x, y) => xyn(0x8, x, y, 0x1),
+            Instr::And(x, y) => xyn(0x8, x, y, 0x2),
+            Instr::Xor(x, y) => xyn(0x8, x, y, 0x3),
+            Instr::AddRr(x, y) => xyn(0x8, x, y, 0x4),
+            Instr::SubRr(x, y) => xyn(0x8, x, y, 0x5),
+            Instr::ShiftR(x, y) => xyn(0x8, x, y, 0x6),
+            Instr::SubRrInv(x, y) => xyn(0x8, x, y, 0x7),
+            Instr::ShiftL(x, y) => xyn(0x8, x, y, 0xE),
+
+            Instr::SkipNeRr(x, y) => xyn(0x9, x, y, 0x0),
+
+            Instr::SetI(a) => nnn(0xA, a),
+            Instr::JumpOff(a) => nnn(0xB, a),
+            Instr::Rand(x, nn) => xnn(0xC, x, nn),
+            Instr::Draw(x, y, n) => xyn(0xD, x, y, n),
+
+            Instr::SkipPressed(x) => xnn(0xE, x, 0x9E),
+            Instr::SkipNotPressed(x) => xnn(0xE, x, 0xA1),
+
+            Instr::StoreDelay(x) => xnn(0xF, x, 0x07),
+            Instr::WaitKeypress(x) => xnn(0xF, x, 0x0A),
+            Instr::SetDelayTimer(x) => xnn(0xF, x, 0x15),
+            Instr::SetSoundTimer(x) => xnn(0xF, x, 0x18),
+            Instr::AddI(x) => xnn(0xF, x, 0x1E),
+            Instr::FontSpriteAddr(x) => xnn(0xF, x, 0x29),
+            Instr::Bcd(x) => xnn(0xF, x, 0x33),
+            Instr::DumpRegs(x) => xnn(0xF, x, 0x55),
+            Instr::LoadRegs(x) => xnn(0xF, x, 0x65),
+            Instr::StoreFlags(x) => xnn(0xF, x, 0x75),
+            Instr::LoadFlags(x) => xnn(0xF, x, 0x85),
+        }
+    }
+
+    /// The opcode pattern of the instruction, like `8XY4`.
+    pub fn pattern(self) -> &'static str {
+        match self {
+            Instr::Sys(_) => "0NNN",
+            Instr::Cls => "00E0",
+            Instr::Ret => "00EE",
+
+            Instr::Jump(_) => "1NNN",
+            Instr::Call(_) => "2NNN",
+            Instr::SkipEqNn(..) => "3XNN",
+            Instr::SkipNeNn(..) => "4XNN",
+            Instr::SkipEqRr(..) => "5XY0",
+
+            Instr::Load(..) => "6XNN",
+            Instr::AddNn(..) => "7XNN",
+
+            Instr::Assign(..) => "8XY0",
+            Instr::Or(..) => "8XY1",
+            Instr::And(..) => "8XY2",
+            Instr::Xor(..) => "8XY3",
+            Instr::AddRr(..) => "8XY4",
+            Instr::SubRr(..) => "8XY5",
+            Instr::ShiftR(..) => "8XY6",
+            Instr::SubRrInv(..) => "8XY7",
+            Instr::ShiftL(..) => "8XYE",
+
+            Instr::SkipNeRr(..) => "9XY0",
+
+            Instr::SetI(_) => "ANNN",
+            Instr::JumpOff(_) => "BNNN",
+            Instr::Rand(..) => "CXNN",
+            Instr::Draw(..) => "DXYN",
+
+            Instr::SkipPressed(_) => "EX9E",
+            Instr::SkipNotPressed(_) => "EXA1",
+
+            Instr::StoreDelay(_) => "FX07",
+            Instr::WaitKeypress(_) => "FX0A",
+            Instr::SetDelayTimer(_) => "FX15",
+            Instr::SetSoundTimer(_) => "FX18",
+            Instr::AddI(_) => "FX1E",
+            Instr::FontSpriteAddr(_) => "FX29",
+            Instr::Bcd(_) => "FX33",
+            Instr::DumpRegs(_) => "FX55",
+            Instr::LoadRegs(_) => "FX65",
+            Instr::StoreFlags(_) => "FX75",
+            Instr::LoadFlags(_) => "FX85",
+        }
+    }
+
+    /// Whether executing the instruction changes the register `Vr`, the
+    /// collision flag of `DXYN` counts as a write of VF.
+    pub fn writes_register(self, r: usize) -> bool {
+        match self {
+            Instr::Load(x, _)
+            | Instr::AddNn(x, _)
+            | Instr::Assign(x, _)
+            | Instr::Or(x, _)
+            | Instr::And(x, _)
+            | Instr::Xor(x, _)
+            | Instr::Rand(x, _)
+            | Instr::StoreDelay(x)
+            | Instr::WaitKeypress(x) => x == r,
+            // these also set the carry in VF
+            Instr::AddRr(x, _)
+            | Instr::SubRr(x, _)
+            | Instr::ShiftR(x, _)
+            | Instr::SubRrInv(x, _)
+            | Instr::ShiftL(x, _) => x == r || r == 0xF,
+            Instr::Draw(..) => r == 0xF,
+            Instr::LoadRegs(x) | Instr::LoadFlags(x) => r <= x,
+            _ => false,
+        }
+    }
+
+    /// Whether the instruction conditionally skips the next one.
+    pub fn is_skip(self) -> bool {
+        matches!(
+            self,
+            Instr::SkipEqNn(..)
+                | Instr::SkipNeNn(..)
+                | Instr::SkipEqRr(..)
+                | Instr::SkipNeRr(..)
+                | Instr::SkipPressed(..)
+                | Instr::SkipNotPressed(..)
+        )
+    }
+}
+
+impl fmt::Display for Instr {
+    // mnemonics follow Cowgod's Chip-8 technical reference
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instr::Sys(nnn) => write!(f, "SYS  {:#05X}", nnn),
+            Instr::Cls => write!(f, "CLS"),
+            Instr::Ret => write!(f, "RET"),
+
+            Instr::Jump(nnn) => write!(f, "JP   {:#05X}", nnn),
+            Instr::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instr::SkipEqNn(x, nn) => write!(f, "SE   V{:X}, {:#04X}", x, nn),
+            Instr::SkipNeNn(x, nn) => write!(f, "SNE  V{:X}, {:#04X}", x, nn),
+            Instr::SkipEqRr(x, y) => write!(f, "SE   V{:X}, V{:X}", x, y),
+
+            Instr::Load(x, nn) => write!(f, "LD   V{:X}, {:#04X}", x, nn),
+            Instr::AddNn(x, nn) => write!(f, "ADD  V{:X}, {:#04X}", x, nn),
+
+            Instr::Assign(x, y) => write!(f, "LD   V{:X}, V{:X}", x, y),
+            Instr::Or(x, y) => write!(f, "OR   V{:X}, V{:X}", x, y),
+            Instr::And(x, y) => write!(f, "AND  V{:X}, V{:X}", x, y),
+            Instr::Xor(x, y) => write!(f, "XOR  V{:X}, V{:X}", x, y),
+            Instr::AddRr(x, y) => write!(f, "ADD  V{:X}, V{:X}", x, y),
+            Instr::SubRr(x, y) => write!(f, "SUB  V{:X}, V{:X}", x, y),
+            Instr::ShiftR(x, y) => write!(f, "SHR  V{:X}, V{:X}", x, y),
+            Instr::SubRrInv(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instr::ShiftL(x, y) => write!(f, "SHL  V{:X}, V{:X}", x, y),
+
+            Instr::SkipNeRr(x, y) => write!(f, "SNE  V{:X}, V{:X}", x, y),
+
+            Instr::SetI(nnn) => write!(f, "LD   I, {:#05X}", nnn),
+            Instr::JumpOff(nnn) => write!(f, "JP   V0, {:#05X}", nnn),
+            Instr::Rand(x, nn) => write!(f, "RND  V{:X}, {:#04X}", x, nn),
+            Instr::Draw(x, y, n) => write!(f, "DRW  V{:X}, V{:X}, {}", x, y, n),
+
+            Instr::SkipPressed(x) => write!(f, "SKP  V{:X}", x),
+            Instr::SkipNotPressed(x) => write!(f, "SKNP V{:X}", x),
+
+            Instr::StoreDelay(x) => write!(f, "LD   V{:X}, DT", x),
+            Instr::WaitKeypress(x) => write!(f, "LD   V{:X}, K", x),
+            Instr::SetDelayTimer(x) => write!(f, "LD   DT, V{:X}", x),
+            Instr::SetSoundTimer(x) => write!(f, "LD   ST, V{:X}", x),
+            Instr::AddI(x) => write!(f, "ADD  I, V{:X}", x),
+            Instr::FontSpriteAddr(x) => write!(f, "LD   F, V{:X}", x),
+            Instr::Bcd(x) => write!(f, "LD   B, V{:X}", x),
+            Instr::DumpRegs(x) => write!(f, "LD   [I], V{:X}", x),
+            Instr::LoadRegs(x) => write!(f, "LD   V{:X}, [I]", x),
+            Instr::StoreFlags(x) => write!(f, "LD   R, V{:X}", x),
+            Instr::LoadFlags(x) => write!(f, "LD   V{:X}, R", x),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Line::Code {
+                addr,
+                word,
+                instr,
+                self_modified,
+            } => {
+                write!(f, "{:#05X}  {:04X}  {}", addr, word, instr)?;
+                if self_modified {
+                    write!(f, "  ; self modified")?;
+                }
+
+                Ok(())
+            }
+            Line::Data { addr, byte, access } => {
+                // show data as a sprite row since that's what it usually is
+                let mut row = String::with_capacity(8);
+                for b in (0..8).rev() {
+                    row.push(if (byte >> b) & 1 == 1 { '#' } else { '.' });
+                }
+
+                write!(
+                    f,
+                    "{:#05X}  {:02X}    DB   {:#04X}  ; {}",
+                    addr, byte, byte, row
+                )?;
+                if access.contains(Access::SPRITE_READ) {
+                    write!(f, " sprite")?;
+                }
+                if access.intersects(Access::REGS_READ | Access::REGS_WRITTEN) {
+                    write!(f, " registers")?;
+                }
+                if access.contains(Access::BCD_WRITTEN) {
+                    write!(f, " bcd")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Anti-flicker processing of the screen before it's presented.
+//!
+//! Games erase sprites by drawing them again, so a moving sprite is often
+//! missing from the screen at the end of a frame and it flickers. The
+//! `Display` smooths that out by turning the framebuffer into per pixel
+//! intensities, the emulation itself is never touched.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+use crate::framebuffer::{Dirty, Framebuffer};
+
+pub const ANTI_FLICKER_MODES: [&str; 4] = ["off", "phosphor", "blend", "wait-draw"];
+
+/// Default fraction of the intensity a pixel keeps every frame after being
+/// turned off.
+pub const DEFAULT_PHOSPHOR_PERSISTENCE: f32 = 0.6;
+
+/// Default number of frames blended together.
+pub const DEFAULT_BLEND_FRAMES: usize = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AntiFlicker {
+    /// Present the framebuffer as is.
+    #[default]
+    Off,
+    /// Pixels turned off fade out keeping the given fraction of their
+    /// intensity every frame, like the phosphor of a CRT.
+    Phosphor(f32),
+    /// A pixel is lit if it has been lit in any of the last given frames.
+    Blend(usize),
+    /// Present the screen only after frames in which a sprite was drawn, so
+    /// that frames ending right after an erase are never shown.
+    WaitForDraw,
+}
+
+pub struct Display {
+    mode: AntiFlicker,
+    width: usize,
+    height: usize,
+    /// Intensity of every pixel in row major order.
+    levels: Vec<u8>,
+    /// The last frames, newest last, when blending.
+    history: VecDeque<Framebuffer>,
+    /// Rows changed since the last time they have been presented.
+    pending: Dirty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAntiFlickerError(String);
+
+impl Display {
+    pub fn new(mode: AntiFlicker) -> Self {
+        let vram = Framebuffer::new();
+
+        Display {
+            mode,
+            width: vram.width(),
+            height: vram.height(),
+            levels: vec![0; vram.width() * vram.height()],
+            history: VecDeque::new(),
+            pending: Dirty::all(),
+        }
+    }
+
+    pub fn mode(&self) -> AntiFlicker {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: AntiFlicker) {
+        self.mode = mode;
+        self.history.clear();
+        self.pending = Dirty::all();
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The intensity of every pixel in row major order, from 0 for the
+    /// background to 255 for the foreground.
+    pub fn levels(&self) -> &[u8] {
+        &self.levels
+    }
+
+    pub fn level(&self, x: usize, y: usize) -> u8 {
+        self.levels[y * self.width + x]
+    }
+
+    /// Process the framebuffer at the end of a frame, `dirty` is what
+    /// `Chip8::take_dirty` returned. Returns the rows whose intensities
+    /// changed and have to be presented again.
+    pub fn update(&mut self, vram: &Framebuffer, dirty: Dirty) -> Dirty {
+        if (vram.width(), vram.height()) != (self.width, self.height) {
+            self.width = vram.width();
+            self.height = vram.height();
+            self.levels = vec![0; self.width * self.height];
+            self.history.clear();
+            self.pending = Dirty::all();
+        }
+
+        self.pending.merge(dirty);
+
+        match self.mode {
+            AntiFlicker::Off => {
+                let pending = mem::take(&mut self.pending);
+                for rows in pending.spans(self.height) {
+                    for y in rows {
+                        self.set_row(y, vram.row(y));
+                    }
+                }
+                pending
+            }
+            AntiFlicker::WaitForDraw => {
+                if !self.pending.has_sprites() {
+                    return Dirty::default();
+                }
+
+                let pending = mem::take(&mut self.pending);
+                for rows in pending.spans(self.height) {
+                    for y in rows {
+                        self.set_row(y, vram.row(y));
+                    }
+                }
+                pending
+            }
+            AntiFlicker::Blend(frames) => {
+                self.history.push_back(vram.clone());
+                while self.history.len() > frames.max(1) {
+                    self.history.pop_front();
+                }
+
+                self.pending = Dirty::default();
+
+                let mut changed = Dirty::default();
+                for y in 0..self.height {
+                    let row = self.history.iter().fold(0, |acc, fb| acc | fb.row(y));
+                    if self.set_row(y, row) {
+                        changed.mark_row(y);
+                    }
+                }
+                changed
+            }
+            AntiFlicker::Phosphor(persistence) => {
+                self.pending = Dirty::default();
+
+                let mut changed = Dirty::default();
+                for y in 0..self.height {
+                    if self.decay_row(y, vram.row(y), persistence) {
+                        changed.mark_row(y);
+                    }
+                }
+                changed
+            }
+        }
+    }
+
+    /// Write the RGBA pixels into `buf`, `width() * height() * 4` bytes long,
+    /// blending `off` and `on` according to the intensity.
+    pub fn blit_rgba(&self, buf: &mut [u8], off: [u8; 4], on: [u8; 4]) {
+        for (px, level) in buf.chunks_exact_mut(4).zip(&self.levels) {
+            px.copy_from_slice(&mix(off, on, *level));
+        }
+    }
+
+    /// Set the pixels of row `y` to either 0 or 255 according to the packed
+    /// `row`, returns whether any changed.
+    fn set_row(&mut self, y: usize, row: u128) -> bool {
+        let w = self.width;
+        let mut changed = false;
+
+        for (x, level) in self.levels[y * w..(y + 1) * w].iter_mut().enumerate() {
+            let new = if (row >> (w - 1 - x)) & 1 == 1 {
+                255
+            } else {
+                0
+            };
+
+            changed |= new != *level;
+            *level = new;
+        }
+
+        changed
+    }
+
+    /// Light the pixels of row `y` lit in `row` and fade out the others,
+    /// returns whether any changed.
+    fn decay_row(&mut self, y: usize, row: u128, persistence: f32) -> bool {
+        let w = self.width;
+        let mut changed = false;
+
+        for (x, level) in self.levels[y * w..(y + 1) * w].iter_mut().enumerate() {
+            let new = if (row >> (w - 1 - x)) & 1 == 1 {
+                255
+            } else {
+                (f32::from(*level) * persistence) as u8
+            };
+
+            changed |= new != *level;
+            *level = new;
+        }
+
+        changed
+    }
+}
+
+impl AntiFlicker {
+    /// The name of the mode in `ANTI_FLICKER_MODES`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AntiFlicker::Off => "off",
+            AntiFlicker::Phosphor(_) => "phosphor",
+            AntiFlicker::Blend(_) => "blend",
+            AntiFlicker::WaitForDraw => "wait-draw",
+        }
+    }
+}
+
+/// Linear interpolation between `off` and `on`, `level` 255 being `on`.
+pub fn mix(off: [u8; 4], on: [u8; 4], level: u8) -> [u8; 4] {
+    let mut c = [0; 4];
+    for i in 0..4 {
+        let (a, b) = (u32::from(off[i]), u32::from(on[i]));
+        c[i] = ((a * (255 - u32::from(level)) + b * u32::from(level)) / 255) as u8;
+    }
+    c
+}
+
+impl FromStr for AntiFlicker {
+    type Err = ParseAntiFlickerError;
+
+    /// Parse one of `ANTI_FLICKER_MODES`, `phosphor` and `blend` optionally
+    /// followed by the persistence and the number of frames respectively,
+    /// e.g. `phosphor:0.8` or `blend:3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseAntiFlickerError(s.to_string());
+
+        let (mode, arg) = match s.split_once(':') {
+            Some((mode, arg)) => (mode, Some(arg)),
+            None => (s, None),
+        };
+
+        match (mode, arg) {
+            ("off", None) => Ok(AntiFlicker::Off),
+            ("wait-draw", None) => Ok(AntiFlicker::WaitForDraw),
+            ("phosphor", None) => Ok(AntiFlicker::Phosphor(DEFAULT_PHOSPHOR_PERSISTENCE)),
+            ("phosphor", Some(p)) => p
+                .parse()
+                .ok()
+                .filter(|p| (0.0..1.0).contains(p))
+                .map(AntiFlicker::Phosphor)
+                .ok_or_else(err),
+            ("blend", None) => Ok(AntiFlicker::Blend(DEFAULT_BLEND_FRAMES)),
+            ("blend", Some(n)) => n
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(AntiFlicker::Blend)
+                .ok_or_else(err),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl fmt::Display for AntiFlicker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AntiFlicker::Off => write!(f, "off"),
+            AntiFlicker::Phosphor(p) => write!(f, "phosphor:{}", p),
+            AntiFlicker::Blend(n) => write!(f, "blend:{}", n),
+            AntiFlicker::WaitForDraw => write!(f, "wait-draw"),
+        }
+    }
+}
+
+impl fmt::Display for ParseAntiFlickerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid anti-flicker mode {}, expected one of {}",
+            self.0,
+            ANTI_FLICKER_MODES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseAntiFlickerError {}
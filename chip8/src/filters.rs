@@ -0,0 +1,365 @@
+//! Post-processing of the screen on the CPU: upscaling followed by filters
+//! giving it the look of a CRT or of an LCD.
+//!
+//! The pipeline works on pixel intensities, like the ones of a `Display`,
+//! and produces an RGBA image `scale` times larger than the screen.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::display::{self, Display};
+use crate::framebuffer::Framebuffer;
+use crate::image::Image;
+
+pub const UPSCALER_NAMES: [&str; 2] = ["nearest", "scale2x"];
+pub const FILTER_NAMES: [&str; 4] = ["scanlines", "grid", "bloom", "barrel"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upscaler {
+    /// Every pixel becomes a square.
+    Nearest,
+    /// The Scale2x (EPX) algorithm which rounds diagonal edges, applied as
+    /// many times as the scale is divisible by 2, nearest neighbour takes
+    /// care of what's left.
+    Scale2x,
+}
+
+/// A filter applied to the upscaled image, the parameter is how strong the
+/// effect is between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Darken the bottom of every row of pixels.
+    Scanlines(f32),
+    /// Darken the border of every pixel like the gaps of an LCD.
+    Grid(f32),
+    /// Make the lit pixels glow onto their neighbours.
+    Bloom(f32),
+    /// Bend the image like the glass of a CRT, the corners become black.
+    Barrel(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub upscaler: Upscaler,
+    /// How many times larger than the screen the image is.
+    pub scale: usize,
+    /// Applied in order after upscaling.
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFilterError(String);
+
+impl Pipeline {
+    /// Whether the pipeline doesn't do anything other than copying the
+    /// screen.
+    pub fn is_identity(&self) -> bool {
+        self.upscaler == Upscaler::Nearest && self.scale <= 1 && self.filters.is_empty()
+    }
+
+    /// Render `width` by `height` pixel intensities in row major order with
+    /// `off` and `on` as the colors of intensities 0 and 255.
+    pub fn render(
+        &self,
+        levels: &[u8],
+        width: usize,
+        height: usize,
+        off: [u8; 4],
+        on: [u8; 4],
+    ) -> Image {
+        let scale = self.scale.max(1);
+
+        let (mut levels, mut w, mut h) = (levels.to_vec(), width, height);
+        let mut left = scale;
+        if self.upscaler == Upscaler::Scale2x {
+            while left.is_multiple_of(2) {
+                levels = scale2x(&levels, w, h);
+                w *= 2;
+                h *= 2;
+                left /= 2;
+            }
+        }
+
+        let mut image = Image::new(w * left, h * left, off);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let level = levels[(y / left) * w + x / left];
+                if level != 0 {
+                    image.set(x, y, display::mix(off, on, level));
+                }
+            }
+        }
+
+        for filter in &self.filters {
+            filter.apply(&mut image, scale);
+        }
+
+        image
+    }
+
+    pub fn render_display(&self, display: &Display, off: [u8; 4], on: [u8; 4]) -> Image {
+        self.render(display.levels(), display.width(), display.height(), off, on)
+    }
+
+    pub fn render_framebuffer(&self, vram: &Framebuffer, off: [u8; 4], on: [u8; 4]) -> Image {
+        let mut levels = vec![0; vram.width() * vram.height()];
+        vram.blit_luma(&mut levels, 0, 255);
+
+        self.render(&levels, vram.width(), vram.height(), off, on)
+    }
+}
+
+impl Filter {
+    /// Apply the filter to an image made of `cell` by `cell` squares per
+    /// screen pixel.
+    pub fn apply(&self, image: &mut Image, cell: usize) {
+        match *self {
+            Filter::Scanlines(strength) => {
+                // the bottom third of every row of pixels, or every other
+                // line when pixels are too small for that
+                let dark = |y: usize| {
+                    if cell < 2 {
+                        y % 2 == 1
+                    } else {
+                        y % cell >= cell - (cell / 3).max(1)
+                    }
+                };
+
+                for y in (0..image.height).filter(|y| dark(*y)) {
+                    for x in 0..image.width {
+                        image.set(x, y, darken(image.get(x, y), strength));
+                    }
+                }
+            }
+            Filter::Grid(strength) => {
+                if cell < 2 {
+                    return;
+                }
+
+                for y in 0..image.height {
+                    for x in 0..image.width {
+                        if x % cell == cell - 1 || y % cell == cell - 1 {
+                            image.set(x, y, darken(image.get(x, y), strength));
+                        }
+                    }
+                }
+            }
+            Filter::Bloom(strength) => {
+                let radius = (cell / 2).max(1);
+                let glow = box_blur(&box_blur(image, radius), radius);
+
+                for (px, g) in image
+                    .pixels
+                    .chunks_exact_mut(4)
+                    .zip(glow.pixels.chunks_exact(4))
+                {
+                    for i in 0..3 {
+                        let c = f32::from(px[i]) + f32::from(g[i]) * strength;
+                        px[i] = c.min(255.0) as u8;
+                    }
+                }
+            }
+            Filter::Barrel(strength) => {
+                let src = image.clone();
+                let (w, h) = (image.width as f32, image.height as f32);
+
+                for y in 0..image.height {
+                    for x in 0..image.width {
+                        let nx = (x as f32 + 0.5) / w * 2.0 - 1.0;
+                        let ny = (y as f32 + 0.5) / h * 2.0 - 1.0;
+                        let k = 1.0 + strength * (nx * nx + ny * ny);
+
+                        let sx = ((nx * k + 1.0) / 2.0 * w).floor();
+                        let sy = ((ny * k + 1.0) / 2.0 * h).floor();
+
+                        let rgba = if sx >= 0.0 && sy >= 0.0 && sx < w && sy < h {
+                            src.get(sx as usize, sy as usize)
+                        } else {
+                            [0, 0, 0, 0xFF]
+                        };
+                        image.set(x, y, rgba);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The name of the filter in `FILTER_NAMES`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Scanlines(_) => "scanlines",
+            Filter::Grid(_) => "grid",
+            Filter::Bloom(_) => "bloom",
+            Filter::Barrel(_) => "barrel",
+        }
+    }
+}
+
+fn darken(rgba: [u8; 4], strength: f32) -> [u8; 4] {
+    let k = 1.0 - strength.clamp(0.0, 1.0);
+    [
+        (f32::from(rgba[0]) * k) as u8,
+        (f32::from(rgba[1]) * k) as u8,
+        (f32::from(rgba[2]) * k) as u8,
+        rgba[3],
+    ]
+}
+
+/// Scale2x of a `w` by `h` grid, the pixels past the edges repeat the ones on
+/// the edges.
+fn scale2x(src: &[u8], w: usize, h: usize) -> Vec<u8> {
+    let mut dst = vec![0; w * h * 4];
+    let at = |x: usize, y: usize| src[y * w + x];
+
+    for y in 0..h {
+        for x in 0..w {
+            let e = at(x, y);
+            let above = at(x, y.saturating_sub(1));
+            let below = at(x, (y + 1).min(h - 1));
+            let left = at(x.saturating_sub(1), y);
+            let right = at((x + 1).min(w - 1), y);
+
+            let (e0, e1, e2, e3) = if above != below && left != right {
+                (
+                    if left == above { left } else { e },
+                    if above == right { right } else { e },
+                    if left == below { left } else { e },
+                    if below == right { right } else { e },
+                )
+            } else {
+                (e, e, e, e)
+            };
+
+            let (dx, dy) = (x * 2, y * 2);
+            dst[dy * w * 2 + dx] = e0;
+            dst[dy * w * 2 + dx + 1] = e1;
+            dst[(dy + 1) * w * 2 + dx] = e2;
+            dst[(dy + 1) * w * 2 + dx + 1] = e3;
+        }
+    }
+
+    dst
+}
+
+/// Separable box blur of the given radius.
+fn box_blur(image: &Image, radius: usize) -> Image {
+    let (w, h) = (image.width, image.height);
+
+    // blur the lines of `len` pixels `stride` bytes apart starting at every
+    // offset in `starts`, with a running sum over the window
+    let pass = |src: &[u8], dst: &mut [u8], starts: Vec<usize>, len: usize, stride: usize| {
+        for start in starts {
+            let px = |i: usize| start + i * stride;
+
+            let mut sum = [0_u32; 3];
+            for i in 0..radius.min(len) {
+                for c in 0..3 {
+                    sum[c] += u32::from(src[px(i) + c]);
+                }
+            }
+
+            for i in 0..len {
+                if i + radius < len {
+                    for c in 0..3 {
+                        sum[c] += u32::from(src[px(i + radius) + c]);
+                    }
+                }
+                if i > radius {
+                    for c in 0..3 {
+                        sum[c] -= u32::from(src[px(i - radius - 1) + c]);
+                    }
+                }
+
+                let n = ((i + radius + 1).min(len) - i.saturating_sub(radius)) as u32;
+                for c in 0..3 {
+                    dst[px(i) + c] = (sum[c] / n) as u8;
+                }
+            }
+        }
+    };
+
+    let mut tmp = image.clone();
+    pass(
+        &image.pixels,
+        &mut tmp.pixels,
+        (0..h).map(|y| y * w * 4).collect(),
+        w,
+        4,
+    );
+
+    let mut out = tmp.clone();
+    pass(
+        &tmp.pixels,
+        &mut out.pixels,
+        (0..w).map(|x| x * 4).collect(),
+        h,
+        w * 4,
+    );
+
+    out
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            upscaler: Upscaler::Nearest,
+            scale: 1,
+            filters: vec![],
+        }
+    }
+}
+
+impl FromStr for Upscaler {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Upscaler::Nearest),
+            "scale2x" => Ok(Upscaler::Scale2x),
+            _ => Err(ParseFilterError(s.to_string())),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+
+    /// Parse one of `FILTER_NAMES` optionally followed by the strength, e.g.
+    /// `scanlines` or `bloom:0.8`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseFilterError(s.to_string());
+
+        let (name, strength) = match s.split_once(':') {
+            Some((name, strength)) => {
+                let strength = strength.parse::<f32>().map_err(|_| err())?;
+                if !(0.0..=1.0).contains(&strength) {
+                    return Err(err());
+                }
+                (name, Some(strength))
+            }
+            None => (s, None),
+        };
+
+        match name {
+            "scanlines" => Ok(Filter::Scanlines(strength.unwrap_or(0.5))),
+            "grid" => Ok(Filter::Grid(strength.unwrap_or(0.4))),
+            "bloom" => Ok(Filter::Bloom(strength.unwrap_or(0.6))),
+            "barrel" => Ok(Filter::Barrel(strength.unwrap_or(0.15))),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid filter {}, expected one of {} or {} with an optional :strength",
+            self.0,
+            UPSCALER_NAMES.join(", "),
+            FILTER_NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseFilterError {}
@@ -0,0 +1,364 @@
+//! Bit-packed monochrome screen.
+//!
+//! Every row is a single integer whose most significant bit is the leftmost
+//! pixel, `u64` rows for the 64x32 CHIP-8 screen and `u128` rows for the
+//! 128x64 SUPER-CHIP high resolution one. Drawing a sprite row is then a
+//! shift, an AND for the collision and a XOR.
+
+use std::ops::Range;
+
+use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// the rows are kept inline, the screen is accessed far too often to go
+// through a pointer
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, PartialEq, Eq)]
+pub enum Framebuffer {
+    Lores([u64; DISPLAY_HEIGHT]),
+    Hires([u128; HIRES_HEIGHT]),
+}
+
+/// The rows of the screen that changed since the last time the frontend
+/// redrew it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dirty {
+    /// Bit `y` is set when row `y` changed.
+    rows: u64,
+    /// Whether any sprite has been drawn, as opposed to just clearing.
+    sprites: bool,
+}
+
+impl Framebuffer {
+    /// A blank low resolution screen.
+    pub fn new() -> Self {
+        Framebuffer::Lores([0; DISPLAY_HEIGHT])
+    }
+
+    /// A blank high resolution screen.
+    pub fn hires() -> Self {
+        Framebuffer::Hires([0; HIRES_HEIGHT])
+    }
+
+    pub fn is_hires(&self) -> bool {
+        matches!(self, Framebuffer::Hires(_))
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            Framebuffer::Lores(_) => DISPLAY_WIDTH,
+            Framebuffer::Hires(_) => HIRES_WIDTH,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Framebuffer::Lores(_) => DISPLAY_HEIGHT,
+            Framebuffer::Hires(_) => HIRES_HEIGHT,
+        }
+    }
+
+    /// The packed row `y`, the pixel at `x` is the bit `width() - 1 - x`.
+    pub fn row(&self, y: usize) -> u128 {
+        match self {
+            Framebuffer::Lores(rows) => u128::from(rows[y]),
+            Framebuffer::Hires(rows) => rows[y],
+        }
+    }
+
+    /// Replace the packed row `y`, the bits past the width are ignored.
+    pub fn set_row(&mut self, y: usize, row: u128) {
+        match self {
+            Framebuffer::Lores(rows) => rows[y] = row as u64,
+            Framebuffer::Hires(rows) => rows[y] = row,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        (self.row(y) >> (self.width() - 1 - x)) & 1 == 1
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Framebuffer::Lores(rows) => *rows = [0; DISPLAY_HEIGHT],
+            Framebuffer::Hires(rows) => *rows = [0; HIRES_HEIGHT],
+        }
+    }
+
+    pub fn is_blank(&self) -> bool {
+        match self {
+            Framebuffer::Lores(rows) => rows.iter().all(|r| *r == 0),
+            Framebuffer::Hires(rows) => rows.iter().all(|r| *r == 0),
+        }
+    }
+
+    /// Number of pixels turned on.
+    pub fn count_lit(&self) -> usize {
+        match self {
+            Framebuffer::Lores(rows) => rows.iter().map(|r| r.count_ones() as usize).sum(),
+            Framebuffer::Hires(rows) => rows.iter().map(|r| r.count_ones() as usize).sum(),
+        }
+    }
+
+    /// XOR the 8 pixels wide `sprite` at the given position and return
+    /// whether any pixel has been turned off.
+    ///
+    /// The position must already be on screen. Sprites going past the right
+    /// and bottom edges either wrap around or get clipped.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let mut collision = false;
+
+        match self {
+            Framebuffer::Lores(rows) => {
+                for (i, byte) in sprite.iter().enumerate() {
+                    if clip && y + i >= DISPLAY_HEIGHT {
+                        break;
+                    }
+
+                    let line = u64::from(*byte) << (DISPLAY_WIDTH - 8);
+                    let line = if clip {
+                        line >> x
+                    } else {
+                        line.rotate_right(x as u32)
+                    };
+
+                    let row = &mut rows[(y + i) % DISPLAY_HEIGHT];
+                    collision |= *row & line != 0;
+                    *row ^= line;
+                }
+            }
+            Framebuffer::Hires(rows) => {
+                for (i, byte) in sprite.iter().enumerate() {
+                    if clip && y + i >= HIRES_HEIGHT {
+                        break;
+                    }
+
+                    let line = u128::from(*byte) << (HIRES_WIDTH - 8);
+                    let line = if clip {
+                        line >> x
+                    } else {
+                        line.rotate_right(x as u32)
+                    };
+
+                    let row = &mut rows[(y + i) % HIRES_HEIGHT];
+                    collision |= *row & line != 0;
+                    *row ^= line;
+                }
+            }
+        }
+
+        collision
+    }
+
+    /// The horizontal runs of lit pixels of row `y` as ranges of columns.
+    pub fn lit_runs(&self, y: usize) -> impl Iterator<Item = Range<usize>> {
+        let w = self.width();
+        // left align the row so that the leftmost pixel is the top bit
+        let mut row = self.row(y) << (HIRES_WIDTH - w);
+        let mut x = 0;
+
+        std::iter::from_fn(move || {
+            if row == 0 {
+                return None;
+            }
+
+            let skip = row.leading_zeros() as usize;
+            row <<= skip;
+            let len = row.leading_ones() as usize;
+            row = row.checked_shl(len as u32).unwrap_or(0);
+
+            let start = x + skip;
+            x = start + len;
+            Some(start..x)
+        })
+    }
+
+    /// Iterate over all the pixels as `(y, x, lit)` in row major order.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+        let (w, h) = (self.width(), self.height());
+
+        (0..h).flat_map(move |y| {
+            let row = self.row(y);
+            (0..w).map(move |x| (y, x, (row >> (w - 1 - x)) & 1 == 1))
+        })
+    }
+
+    /// Write a byte per pixel into `buf`, `width() * height()` bytes long,
+    /// using `off` and `on` as values.
+    pub fn blit_luma(&self, buf: &mut [u8], off: u8, on: u8) {
+        let w = self.width();
+
+        for (y, line) in buf.chunks_exact_mut(w).take(self.height()).enumerate() {
+            let row = self.row(y);
+            for (x, px) in line.iter_mut().enumerate() {
+                *px = if (row >> (w - 1 - x)) & 1 == 1 {
+                    on
+                } else {
+                    off
+                };
+            }
+        }
+    }
+
+    /// Write the RGBA pixels into `buf`, `width() * height() * 4` bytes long,
+    /// using `off` and `on` as colors.
+    pub fn blit_rgba(&self, buf: &mut [u8], off: [u8; 4], on: [u8; 4]) {
+        let w = self.width();
+
+        for (y, line) in buf.chunks_exact_mut(w * 4).take(self.height()).enumerate() {
+            let row = self.row(y);
+            for (x, px) in line.chunks_exact_mut(4).enumerate() {
+                let lit = (row >> (w - 1 - x)) & 1 == 1;
+                px.copy_from_slice(if lit { &on } else { &off });
+            }
+        }
+    }
+}
+
+impl Dirty {
+    /// Every row of the screen.
+    pub fn all() -> Self {
+        Dirty {
+            rows: !0,
+            sprites: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    pub fn is_row_dirty(&self, y: usize) -> bool {
+        self.rows & (1 << y) != 0
+    }
+
+    pub fn has_sprites(&self) -> bool {
+        self.sprites
+    }
+
+    pub fn mark_row(&mut self, y: usize) {
+        self.rows |= 1 << y;
+    }
+
+    pub fn mark_sprite(&mut self) {
+        self.sprites = true;
+    }
+
+    /// Merge the rows of `other` into these ones.
+    pub fn merge(&mut self, other: Dirty) {
+        self.rows |= other.rows;
+        self.sprites |= other.sprites;
+    }
+
+    /// The dirty rows grouped in consecutive ranges, `height` is the height
+    /// of the screen.
+    pub fn spans(&self, height: usize) -> impl Iterator<Item = Range<usize>> {
+        let mut rows = if height >= 64 {
+            self.rows
+        } else {
+            self.rows & ((1 << height) - 1)
+        };
+        let mut y = 0;
+
+        std::iter::from_fn(move || {
+            if rows == 0 {
+                return None;
+            }
+
+            let skip = rows.trailing_zeros() as usize;
+            rows >>= skip;
+            let len = rows.trailing_ones() as usize;
+            rows = rows.checked_shr(len as u32).unwrap_or(0);
+
+            let start = y + skip;
+            y = start + len;
+            Some(start..y)
+        })
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{Chip8, Quirks};
+
+    // LD V0, 30 / LD I, 0x000 / DRW V0, V0, 5 / CLS / JP 0x208
+    const PROGRAM: [u8; 10] = [0x60, 0x1E, 0xA0, 0x00, 0xD0, 0x05, 0x00, 0xE0, 0x12, 0x08];
+
+    fn run(quirks: Quirks, cycles: usize) -> Chip8<StdRng> {
+        let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(0), &PROGRAM).unwrap();
+        chip8.set_quirks(quirks);
+        assert_eq!(chip8.take_dirty(), Dirty::all());
+
+        for _ in 0..cycles {
+            chip8.emulate_cycle();
+        }
+        chip8
+    }
+
+    #[test]
+    fn sprite_marks_its_rows() {
+        // the 0 of the font is 5 rows tall, drawn from row 30 it wraps around
+        let mut chip8 = run(Quirks::default(), 3);
+        let dirty = chip8.take_dirty();
+        assert_eq!(
+            dirty.spans(DISPLAY_HEIGHT).collect::<Vec<_>>(),
+            vec![0..3, 30..32]
+        );
+        assert!(dirty.has_sprites());
+
+        // taking the rows clears them
+        assert!(chip8.take_dirty().is_empty());
+
+        let mut chip8 = run(Quirks::vip(), 3);
+        let dirty = chip8.take_dirty();
+        assert_eq!(
+            dirty.spans(DISPLAY_HEIGHT).collect::<Vec<_>>(),
+            vec![30..32]
+        );
+    }
+
+    #[test]
+    fn clear_marks_the_lit_rows() {
+        let mut chip8 = run(Quirks::default(), 3);
+        chip8.take_dirty();
+        chip8.emulate_cycle();
+
+        // the rows that were blank don't change
+        let dirty = chip8.take_dirty();
+        assert_eq!(
+            dirty.spans(DISPLAY_HEIGHT).collect::<Vec<_>>(),
+            vec![0..3, 30..32]
+        );
+        assert!(!dirty.has_sprites());
+        assert!(chip8.framebuffer().is_blank());
+    }
+
+    #[test]
+    fn all_covers_the_screen() {
+        assert_eq!(
+            Dirty::all().spans(DISPLAY_HEIGHT).collect::<Vec<_>>(),
+            vec![0..DISPLAY_HEIGHT]
+        );
+        assert_eq!(
+            Dirty::all().spans(HIRES_HEIGHT).collect::<Vec<_>>(),
+            vec![0..HIRES_HEIGHT]
+        );
+
+        let mut dirty = Dirty::default();
+        dirty.mark_row(5);
+        dirty.merge(Dirty::all());
+        assert_eq!(dirty, Dirty::all());
+    }
+}
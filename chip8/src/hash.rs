@@ -0,0 +1,14 @@
+//! Hashes used to identify roms.
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+/// Lowercase hex SHA-1 of `data`.
+pub fn sha1(data: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(data))
+}
+
+/// Lowercase hex SHA-256 of `data`.
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
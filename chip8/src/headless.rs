@@ -0,0 +1,382 @@
+//! Run programs without any frontend, deterministically given the same seed
+//! and input.
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::chip8::Chip8;
+use crate::filters::Pipeline;
+use crate::image::Image;
+
+/// Number of instructions executed every frame by default, that is the
+/// frontends' 500Hz at 60 frames per second.
+pub const DEFAULT_CYCLES_PER_FRAME: usize = 500 / 60;
+
+/// Number of frames the program counter must stay still for a run to be
+/// considered stalled.
+pub const STALL_FRAMES: usize = 60;
+
+pub struct Runner {
+    chip8: Chip8<StdRng>,
+    cycles_per_frame: usize,
+    frame: usize,
+    cycle: usize,
+    /// Whether the buzzer sounded during the last frame.
+    beeped: bool,
+}
+
+/// How a supervised run went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The program ran until the end and there's something on screen.
+    Ok,
+    /// The interpreter panicked executing the instruction at `pc`.
+    Crashed { pc: usize, msg: String },
+    /// The program has been stuck at `pc` for `STALL_FRAMES` frames without
+    /// waiting for a key.
+    Stalled { pc: usize },
+    /// The screen is blank at the end of the run.
+    Blank,
+}
+
+/// A `Runner` that catches the interpreter panics and notices when the
+/// program gets stuck, it stops running as soon as either happens.
+pub struct Supervisor {
+    runner: Runner,
+    outcome: Option<Outcome>,
+    stalled_frames: usize,
+    /// Where the program has been stuck since the start of the frame, if it
+    /// has been.
+    stuck_pc: Option<usize>,
+}
+
+/// Key presses and releases to replay at given frames.
+///
+/// Scripts are plain text with one event per line made of the frame, the hex
+/// key and whether it goes `down` or `up`:
+///
+/// ```text
+/// # start the game and move right for a second
+/// 60 5 down
+/// 62 5 up
+/// 120 6 down
+/// 180 6 up
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    /// Sorted by frame.
+    events: Vec<(usize, u8, bool)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl Runner {
+    pub fn new(program: &[u8], seed: u64, cycles_per_frame: usize) -> Option<Self> {
+        let chip8 = Chip8::with_program(StdRng::seed_from_u64(seed), program)?;
+
+        Some(Runner {
+            chip8,
+            cycles_per_frame,
+            frame: 0,
+            cycle: 0,
+            beeped: false,
+        })
+    }
+
+    pub fn chip8(&self) -> &Chip8<StdRng> {
+        &self.chip8
+    }
+
+    pub fn chip8_mut(&mut self) -> &mut Chip8<StdRng> {
+        &mut self.chip8
+    }
+
+    /// Number of frames run so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn cycles_per_frame(&self) -> usize {
+        self.cycles_per_frame
+    }
+
+    /// Whether the buzzer sounded during the last frame, like the frontends
+    /// check it right before ticking the timers.
+    pub fn beeped(&self) -> bool {
+        self.beeped
+    }
+
+    /// Run a single instruction, the timers are ticked after the last
+    /// instruction of every frame.
+    pub fn run_cycle(&mut self) {
+        self.chip8.emulate_cycle();
+        self.cycle += 1;
+
+        if self.cycle >= self.cycles_per_frame {
+            self.beeped = self.chip8.beep();
+            self.chip8.decrease_timers();
+            self.frame += 1;
+            self.cycle = 0;
+        }
+    }
+
+    /// The screen as it is now rendered through `pipeline`.
+    pub fn screenshot(&self, pipeline: &Pipeline, off: [u8; 4], on: [u8; 4]) -> Image {
+        pipeline.render_framebuffer(self.chip8.framebuffer(), off, on)
+    }
+
+    /// Run the instructions of a frame and tick the timers, like the
+    /// frontends do at every refresh.
+    pub fn run_frame(&mut self) {
+        let frame = self.frame;
+        while self.frame == frame {
+            self.run_cycle();
+        }
+    }
+}
+
+impl Supervisor {
+    pub fn new(runner: Runner) -> Self {
+        Supervisor {
+            runner,
+            outcome: None,
+            stalled_frames: 0,
+            stuck_pc: None,
+        }
+    }
+
+    pub fn runner(&self) -> &Runner {
+        &self.runner
+    }
+
+    /// Whether the program hasn't crashed or stalled yet.
+    pub fn is_running(&self) -> bool {
+        self.outcome.is_none()
+    }
+
+    /// Feed the input of the upcoming frame.
+    pub fn start_frame(&mut self, script: &InputScript) {
+        if !self.is_running() {
+            return;
+        }
+
+        script.apply(self.runner.frame(), self.runner.chip8_mut());
+        self.stuck_pc = Some(self.runner.chip8().pc());
+    }
+
+    pub fn run_cycle(&mut self) {
+        if !self.is_running() {
+            return;
+        }
+
+        let pc = self.runner.chip8().pc();
+        let waiting = self.runner.chip8().waiting_keypress();
+        let runner = &mut self.runner;
+
+        if let Err(e) = panic::catch_unwind(AssertUnwindSafe(|| runner.run_cycle())) {
+            self.outcome = Some(Outcome::Crashed {
+                pc,
+                msg: panic_message(&*e),
+            });
+            return;
+        }
+
+        if waiting || self.stuck_pc != Some(self.runner.chip8().pc()) {
+            self.stuck_pc = None;
+        }
+    }
+
+    /// Check whether the program got stuck during the frame just run.
+    pub fn end_frame(&mut self) {
+        if !self.is_running() {
+            return;
+        }
+
+        if self.stuck_pc.is_some() {
+            self.stalled_frames += 1;
+        } else {
+            self.stalled_frames = 0;
+        }
+
+        if self.stalled_frames >= STALL_FRAMES {
+            self.outcome = Some(Outcome::Stalled {
+                pc: self.runner.chip8().pc(),
+            });
+        }
+    }
+
+    pub fn run_frame(&mut self, script: &InputScript) {
+        self.start_frame(script);
+        for _ in 0..self.runner.cycles_per_frame().max(1) {
+            self.run_cycle();
+        }
+        self.end_frame();
+    }
+
+    /// How the run went so far, a program still running is fine unless the
+    /// screen is blank.
+    pub fn outcome(&self) -> Outcome {
+        if let Some(outcome) = &self.outcome {
+            return outcome.clone();
+        }
+
+        if self.runner.chip8().framebuffer().is_blank() {
+            Outcome::Blank
+        } else {
+            Outcome::Ok
+        }
+    }
+}
+
+impl InputScript {
+    pub fn parse(s: &str) -> Result<Self, ScriptError> {
+        let mut events = vec![];
+
+        for (i, line) in s.lines().enumerate() {
+            let err = |msg: &str| ScriptError {
+                line: i + 1,
+                msg: msg.to_string(),
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+
+            let frame = parts
+                .next()
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| err("frame must be a number"))?;
+
+            let key = parts
+                .next()
+                .and_then(|k| u8::from_str_radix(k, 16).ok())
+                .filter(|k| *k < 16)
+                .ok_or_else(|| err("key must be an hex digit"))?;
+
+            let pressed = match parts.next() {
+                Some("down") => true,
+                Some("up") => false,
+                _ => return Err(err("key must go either down or up")),
+            };
+
+            events.push((frame, key, pressed));
+        }
+
+        events.sort_by_key(|(frame, _, _)| *frame);
+
+        Ok(InputScript { events })
+    }
+
+    /// Press and release the keys scheduled for the given frame.
+    pub fn apply<R: Rng>(&self, frame: usize, chip8: &mut Chip8<R>) {
+        let start = self.events.partition_point(|(f, _, _)| *f < frame);
+
+        for &(_, key, pressed) in self.events[start..].iter().take_while(|e| e.0 == frame) {
+            if pressed {
+                chip8.keypress(key);
+            } else {
+                chip8.keyrelease(key);
+            }
+        }
+    }
+}
+
+fn panic_message(e: &(dyn Any + Send)) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown error".to_string()
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::Crashed { pc, msg } => write!(f, "crashed at {:#05X}: {}", pc, msg),
+            Outcome::Stalled { pc } => write!(f, "stalled at {:#05X}", pc),
+            Outcome::Blank => write!(f, "blank screen"),
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for ScriptError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supervise(program: &[u8], frames: usize) -> Supervisor {
+        let runner = Runner::new(program, 0, DEFAULT_CYCLES_PER_FRAME).unwrap();
+        let mut supervisor = Supervisor::new(runner);
+        for _ in 0..frames {
+            supervisor.run_frame(&InputScript::default());
+        }
+
+        supervisor
+    }
+
+    #[test]
+    fn jump_to_itself_stalls() {
+        // JP 0x200
+        let supervisor = supervise(&[0x12, 0x00], STALL_FRAMES - 1);
+        assert!(supervisor.is_running());
+
+        let supervisor = supervise(&[0x12, 0x00], STALL_FRAMES);
+        assert_eq!(supervisor.outcome(), Outcome::Stalled { pc: 0x200 });
+    }
+
+    #[test]
+    fn waiting_for_a_key_is_no_stall() {
+        // LD V0, K
+        let supervisor = supervise(&[0xF0, 0x0A], 2 * STALL_FRAMES);
+
+        assert!(supervisor.is_running());
+        assert_eq!(supervisor.outcome(), Outcome::Blank);
+    }
+
+    #[test]
+    fn invalid_opcode_crashes() {
+        // LD V0, 1 / 8008
+        let supervisor = supervise(&[0x60, 0x01, 0x80, 0x08], 1);
+
+        match supervisor.outcome() {
+            Outcome::Crashed { pc, msg } => {
+                assert_eq!(pc, 0x202);
+                assert!(msg.contains("8008"), "{}", msg);
+            }
+            outcome => panic!("unexpected outcome {}", outcome),
+        }
+    }
+
+    #[test]
+    fn no_draws_is_blank() {
+        // ADD V0, 1 / JP 0x200
+        let supervisor = supervise(&[0x70, 0x01, 0x12, 0x00], 2 * STALL_FRAMES);
+        assert_eq!(supervisor.outcome(), Outcome::Blank);
+
+        // DRW V0, V0, 5 / ADD V0, 1 / JP 0x202
+        let supervisor = supervise(&[0xD0, 0x05, 0x70, 0x01, 0x12, 0x02], 2 * STALL_FRAMES);
+        assert_eq!(supervisor.outcome(), Outcome::Ok);
+    }
+}
@@ -0,0 +1,133 @@
+//! Visualisations of how a program uses the memory over time.
+
+use rand::Rng;
+
+use crate::access::{AccessMap, Counts};
+use crate::chip8::{Chip8, RAM_SIZE};
+use crate::framebuffer::Framebuffer;
+use crate::image::Image;
+
+/// The RAM is laid out as a square of 64x64 bytes.
+pub const HEATMAP_SIDE: usize = 64;
+
+const BACKGROUND: [u8; 4] = [0x10, 0x10, 0x10, 0xFF];
+
+/// Heatmap of the RAM accesses where reads, writes and executes light up the
+/// green, red and blue channels respectively and fade out over time.
+pub struct Heatmap {
+    decay: f32,
+    last_counts: Vec<Counts>,
+    heat: Vec<[f32; 3]>,
+}
+
+impl Heatmap {
+    /// `decay` is the fraction of heat kept at every update.
+    pub fn new(decay: f32) -> Self {
+        Heatmap {
+            decay,
+            last_counts: vec![Counts::default(); RAM_SIZE],
+            heat: vec![[0.0; 3]; RAM_SIZE],
+        }
+    }
+
+    /// Account the accesses happened since the previous update, it's meant to
+    /// be called once per frame.
+    pub fn update(&mut self, accesses: &AccessMap) {
+        for (addr, (last, heat)) in self.last_counts.iter_mut().zip(&mut self.heat).enumerate() {
+            let counts = accesses.counts(addr);
+
+            let deltas = [
+                counts.writes.wrapping_sub(last.writes),
+                counts.reads.wrapping_sub(last.reads),
+                counts.executes.wrapping_sub(last.executes),
+            ];
+
+            for (h, d) in heat.iter_mut().zip(&deltas) {
+                *h = if *d > 0 { 1.0 } else { *h * self.decay };
+            }
+
+            *last = counts;
+        }
+    }
+
+    /// Render the heatmap drawing every byte as a `scale`x`scale` square.
+    pub fn render(&self, scale: usize) -> Image {
+        let mut img = Image::new(HEATMAP_SIDE * scale, HEATMAP_SIDE * scale, BACKGROUND);
+
+        for (addr, heat) in self.heat.iter().enumerate() {
+            let mut rgba = BACKGROUND;
+            for (c, h) in rgba.iter_mut().zip(heat) {
+                *c = c.saturating_add((h * 255.0) as u8);
+            }
+
+            let x = addr % HEATMAP_SIDE;
+            let y = addr / HEATMAP_SIDE;
+            img.fill_rect(x * scale, y * scale, scale, scale, rgba);
+        }
+
+        img
+    }
+}
+
+/// Number of VRAM pixels changed at every frame.
+pub struct VramTimeline {
+    last_vram: Framebuffer,
+    changes: Vec<usize>,
+}
+
+impl VramTimeline {
+    pub fn new() -> Self {
+        VramTimeline {
+            last_vram: Framebuffer::new(),
+            changes: vec![],
+        }
+    }
+
+    /// Record the changes since the previous update, it's meant to be called
+    /// once per frame.
+    pub fn update<R: Rng>(&mut self, chip8: &Chip8<R>) {
+        let vram = chip8.framebuffer();
+
+        let changes = if vram.height() == self.last_vram.height() {
+            (0..vram.height())
+                .map(|y| (vram.row(y) ^ self.last_vram.row(y)).count_ones() as usize)
+                .sum()
+        } else {
+            // switching resolution changes everything
+            vram.width() * vram.height()
+        };
+
+        self.last_vram = vram.clone();
+        self.changes.push(changes);
+    }
+
+    pub fn changes(&self) -> &[usize] {
+        &self.changes
+    }
+
+    /// Render the timeline as a bar chart with a `bar_width` pixels wide bar
+    /// per frame scaled so that the busiest frame is `height` pixels tall.
+    pub fn render(&self, bar_width: usize, height: usize) -> Image {
+        let mut img = Image::new(self.changes.len().max(1) * bar_width, height, BACKGROUND);
+
+        let max = self.changes.iter().cloned().max().unwrap_or(0).max(1);
+        for (i, c) in self.changes.iter().enumerate() {
+            let h = c * height / max;
+            img.fill_rect(
+                i * bar_width,
+                height - h,
+                bar_width,
+                h,
+                [0xFF, 0xFF, 0xFF, 0xFF],
+            );
+        }
+
+        img
+    }
+}
+
+impl Default for VramTimeline {
+    fn default() -> Self {
+        VramTimeline::new()
+    }
+}
@@ -0,0 +1,162 @@
+//! Minimal RGBA image type used by the exporters along with PNG and animated
+//! GIF and PNG encoding.
+
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA pixels in row major order.
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Create a new image filled with the given color.
+    pub fn new(width: usize, height: usize, rgba: [u8; 4]) -> Self {
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&rgba);
+        }
+
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+
+        let mut rgba = [0; 4];
+        rgba.copy_from_slice(&self.pixels[i..i + 4]);
+        rgba
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let i = (y * self.width + x) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, rgba: [u8; 4]) {
+        for yy in y..(y + h).min(self.height) {
+            for xx in x..(x + w).min(self.width) {
+                self.set(xx, yy, rgba);
+            }
+        }
+    }
+
+    /// A copy of the image stretched to the given size with nearest
+    /// neighbour sampling.
+    pub fn resized(&self, width: usize, height: usize) -> Image {
+        if width == self.width && height == self.height {
+            return self.clone();
+        }
+
+        let mut img = Image::new(width, height, [0; 4]);
+        for y in 0..height {
+            for x in 0..width {
+                img.set(
+                    x,
+                    y,
+                    self.get(x * self.width / width, y * self.height / height),
+                );
+            }
+        }
+        img
+    }
+
+    pub fn write_png<W: Write>(&self, w: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(to_io_error)?;
+        writer.write_image_data(&self.pixels).map_err(to_io_error)?;
+
+        Ok(())
+    }
+}
+
+/// Write an animated PNG looping forever, every frame stays on screen for
+/// the given number of sixtieths of second. All the frames must have the
+/// same size.
+pub fn write_apng<W: Write>(w: W, frames: &[(Image, u16)]) -> io::Result<()> {
+    let (width, height) = match frames.first() {
+        Some((img, _)) => (img.width, img.height),
+        None => return Err(io::Error::other("an animated png needs at least a frame")),
+    };
+
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(to_io_error)?;
+
+    let mut writer = encoder.write_header().map_err(to_io_error)?;
+    for (img, duration) in frames {
+        assert!(
+            img.width == width && img.height == height,
+            "frame size doesn't match the png size"
+        );
+
+        writer.set_frame_delay(*duration, 60).map_err(to_io_error)?;
+        writer.write_image_data(&img.pixels).map_err(to_io_error)?;
+    }
+
+    writer.finish().map_err(to_io_error)
+}
+
+/// Encoder of animated GIFs, all the frames must have the same size.
+pub struct GifEncoder<W: Write> {
+    encoder: gif::Encoder<W>,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(w: W, width: usize, height: usize) -> io::Result<Self> {
+        let mut encoder =
+            gif::Encoder::new(w, width as u16, height as u16, &[]).map_err(to_io_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(to_io_error)?;
+
+        Ok(GifEncoder {
+            encoder,
+            width,
+            height,
+        })
+    }
+
+    /// Append a frame that stays on screen for `delay` hundredths of second.
+    pub fn add_frame(&mut self, image: &Image, delay: u16) -> io::Result<()> {
+        assert!(
+            image.width == self.width && image.height == self.height,
+            "frame size doesn't match the gif size"
+        );
+
+        let mut pixels = image.pixels.clone();
+        let mut frame =
+            gif::Frame::from_rgba_speed(self.width as u16, self.height as u16, &mut pixels, 10);
+        frame.delay = delay;
+
+        self.encoder.write_frame(&frame).map_err(to_io_error)
+    }
+
+    /// Width and height of the frames.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Write the trailer and return the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.encoder.into_inner()
+    }
+}
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::other(e)
+}
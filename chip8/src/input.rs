@@ -0,0 +1,625 @@
+//! Mapping of the host keyboard and gamepads to the hex keypad.
+//!
+//! Host keys are identified by their physical position with the names of the
+//! W3C `KeyboardEvent.code` values: `KeyQ` is the key right of Tab whatever
+//! is printed on it, so the same bindings work on every keyboard layout.
+//! `Layout` only tells how to label the keys for the user.
+//!
+//! Gamepad buttons have the names of the SDL game controller ones, like `a`
+//! or `dpup`, and the directions of the sticks are the name of the axis
+//! followed by the sign, like `lefty-` for the left stick pushed up.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+pub const PRESETS: [&str; 4] = ["qwerty", "numpad", "azerty", "dvorak"];
+
+/// The hex keys in the order they appear on the VIP keypad, row by row.
+pub const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// The buttons of a gamepad.
+pub const PAD_BUTTONS: [&str; 15] = [
+    "a",
+    "b",
+    "x",
+    "y",
+    "back",
+    "guide",
+    "start",
+    "leftstick",
+    "rightstick",
+    "leftshoulder",
+    "rightshoulder",
+    "dpup",
+    "dpdown",
+    "dpleft",
+    "dpright",
+];
+
+/// The axes of a gamepad, the triggers only go one way.
+pub const PAD_AXES: [&str; 6] = [
+    "leftx",
+    "lefty",
+    "rightx",
+    "righty",
+    "lefttrigger",
+    "righttrigger",
+];
+
+/// The D-pad directions along with the left stick ones they stand for.
+const DPAD_STICK: [(&str, &str); 4] = [
+    ("dpup", "lefty-"),
+    ("dpdown", "lefty+"),
+    ("dpleft", "leftx-"),
+    ("dpright", "leftx+"),
+];
+
+/// The 4x4 block of keys on the left of the keyboard, laid out like the VIP
+/// keypad: `1234`, `QWER`, `ASDF` and `ZXCV` on a QWERTY keyboard.
+const LEFT_BLOCK: [&str; 16] = [
+    "KeyX", "Digit1", "Digit2", "Digit3", "KeyQ", "KeyW", "KeyE", "KeyA", "KeyS", "KeyD", "KeyZ",
+    "KeyC", "Digit4", "KeyR", "KeyF", "KeyV",
+];
+
+/// The numeric keypad laid out like the VIP keypad: 1 2 3 on 7 8 9, 4 5 6
+/// on 4 5 6, 7 8 9 on 1 2 3 and 0 B on 0 and the decimal point. C D E go
+/// down the right column on - + and Enter, A and F don't fit and go on / and
+/// * instead.
+const NUMPAD: [&str; 16] = [
+    "Numpad0",
+    "Numpad7",
+    "Numpad8",
+    "Numpad9",
+    "Numpad4",
+    "Numpad5",
+    "Numpad6",
+    "Numpad1",
+    "Numpad2",
+    "Numpad3",
+    "NumpadDivide",
+    "NumpadDecimal",
+    "NumpadSubtract",
+    "NumpadAdd",
+    "NumpadEnter",
+    "NumpadMultiply",
+];
+
+/// Keys with a name other than a letter, a digit or a keypad digit.
+const NAMED_CODES: [&str; 43] = [
+    "ArrowUp",
+    "ArrowDown",
+    "ArrowLeft",
+    "ArrowRight",
+    "Space",
+    "Enter",
+    "Tab",
+    "Backspace",
+    "ShiftLeft",
+    "ShiftRight",
+    "ControlLeft",
+    "ControlRight",
+    "AltLeft",
+    "AltRight",
+    "Minus",
+    "Equal",
+    "BracketLeft",
+    "BracketRight",
+    "Backslash",
+    "Semicolon",
+    "Quote",
+    "Backquote",
+    "Comma",
+    "Period",
+    "Slash",
+    "IntlBackslash",
+    "NumpadDivide",
+    "NumpadMultiply",
+    "NumpadSubtract",
+    "NumpadAdd",
+    "NumpadEnter",
+    "NumpadDecimal",
+    "Insert",
+    "Delete",
+    "Home",
+    "End",
+    "PageUp",
+    "PageDown",
+    "CapsLock",
+    "ContextMenu",
+    "MetaLeft",
+    "MetaRight",
+    "Escape",
+];
+
+/// What's printed on the QWERTY keys whose name isn't enough.
+const QWERTY_LABELS: [(&str, &str); 16] = [
+    ("Minus", "-"),
+    ("Equal", "="),
+    ("BracketLeft", "["),
+    ("BracketRight", "]"),
+    ("Backslash", "\\"),
+    ("Semicolon", ";"),
+    ("Quote", "'"),
+    ("Backquote", "`"),
+    ("Comma", ","),
+    ("Period", "."),
+    ("Slash", "/"),
+    ("NumpadDivide", "Num/"),
+    ("NumpadMultiply", "Num*"),
+    ("NumpadSubtract", "Num-"),
+    ("NumpadAdd", "Num+"),
+    ("NumpadDecimal", "Num."),
+];
+
+/// What's printed on the keys of the AZERTY and Dvorak layouts that differ
+/// from QWERTY.
+const AZERTY_LABELS: [(&str, &str); 6] = [
+    ("KeyQ", "A"),
+    ("KeyW", "Z"),
+    ("KeyA", "Q"),
+    ("KeyZ", "W"),
+    ("Semicolon", "M"),
+    ("KeyM", ","),
+];
+
+const DVORAK_LABELS: [(&str, &str); 31] = [
+    ("KeyQ", "'"),
+    ("KeyW", ","),
+    ("KeyE", "."),
+    ("KeyR", "P"),
+    ("KeyT", "Y"),
+    ("KeyY", "F"),
+    ("KeyU", "G"),
+    ("KeyI", "C"),
+    ("KeyO", "R"),
+    ("KeyP", "L"),
+    ("KeyS", "O"),
+    ("KeyD", "E"),
+    ("KeyF", "U"),
+    ("KeyG", "I"),
+    ("KeyH", "D"),
+    ("KeyJ", "H"),
+    ("KeyK", "T"),
+    ("KeyL", "N"),
+    ("Semicolon", "S"),
+    ("Quote", "-"),
+    ("KeyZ", ";"),
+    ("KeyX", "Q"),
+    ("KeyC", "J"),
+    ("KeyV", "K"),
+    ("KeyB", "X"),
+    ("KeyN", "B"),
+    ("Comma", "W"),
+    ("Period", "V"),
+    ("Slash", "Z"),
+    ("Minus", "["),
+    ("Equal", "]"),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Dvorak,
+}
+
+/// The host keys bound to every hex key, a hex key can have any number of
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    /// How the keys are labelled, see `Keymap::label`.
+    pub layout: Layout,
+    keys: [Vec<String>; 16],
+}
+
+/// The hex keys bound to the buttons and stick directions of a gamepad,
+/// see the module documentation for their names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Padmap {
+    inputs: BTreeMap<String, u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseKeymapError(String);
+
+/// Why a keymap in the format of `Keymap::to_text` cannot be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapTextError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl Layout {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Qwerty => "qwerty",
+            Layout::Azerty => "azerty",
+            Layout::Dvorak => "dvorak",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Layout::Qwerty, Layout::Azerty, Layout::Dvorak]
+            .iter()
+            .copied()
+            .find(|l| l.name() == name)
+    }
+}
+
+impl Keymap {
+    /// A keymap without any binding.
+    pub fn empty(layout: Layout) -> Self {
+        Keymap {
+            layout,
+            keys: Default::default(),
+        }
+    }
+
+    /// One of the `PRESETS`. The QWERTY, AZERTY and Dvorak ones bind the
+    /// same physical keys and only label them differently.
+    pub fn preset(name: &str) -> Option<Self> {
+        let (layout, codes) = match name {
+            "qwerty" => (Layout::Qwerty, LEFT_BLOCK),
+            "numpad" => (Layout::Qwerty, NUMPAD),
+            "azerty" => (Layout::Azerty, LEFT_BLOCK),
+            "dvorak" => (Layout::Dvorak, LEFT_BLOCK),
+            _ => return None,
+        };
+
+        let mut keymap = Keymap::empty(layout);
+        for (hex_key, code) in codes.iter().enumerate() {
+            keymap.keys[hex_key].push(code.to_string());
+        }
+        Some(keymap)
+    }
+
+    /// The host keys bound to `hex_key`.
+    pub fn keys(&self, hex_key: u8) -> &[String] {
+        &self.keys[usize::from(hex_key)]
+    }
+
+    /// The hex keys bound to the host key `code`.
+    pub fn hex_keys<'a>(&'a self, code: &'a str) -> impl Iterator<Item = u8> + 'a {
+        (0..16).filter(move |k| self.keys(*k).iter().any(|c| c == code))
+    }
+
+    /// Bind `code` to `hex_key` too, the other hex keys it was bound to lose
+    /// it.
+    pub fn bind(&mut self, hex_key: u8, code: &str) {
+        for keys in &mut self.keys {
+            keys.retain(|c| c != code);
+        }
+        self.keys[usize::from(hex_key)].push(code.to_string());
+    }
+
+    /// Replace the host keys of `hex_key`.
+    pub fn set(&mut self, hex_key: u8, codes: Vec<String>) {
+        self.keys[usize::from(hex_key)] = codes;
+    }
+
+    pub fn clear(&mut self, hex_key: u8) {
+        self.keys[usize::from(hex_key)].clear();
+    }
+
+    /// Replace the host keys of the hex keys in `overrides`, e.g. the ones
+    /// for a given game.
+    pub fn apply(&mut self, overrides: &BTreeMap<u8, Vec<String>>) {
+        for (hex_key, codes) in overrides {
+            self.set(*hex_key, codes.clone());
+        }
+    }
+
+    /// Every binding, in the same form as taken by `apply`.
+    pub fn bindings(&self) -> BTreeMap<u8, Vec<String>> {
+        (0..16).map(|k| (k, self.keys(k).to_vec())).collect()
+    }
+
+    /// The keymap as plain text: the name of the layout on the first line
+    /// followed by the host keys of every hex key, from 0 to F, separated by
+    /// commas on a line each.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![self.layout.name().to_string()];
+        lines.extend(self.keys.iter().map(|codes| codes.join(",")));
+        lines.join("\n")
+    }
+
+    /// Parse the format of `to_text`, the layout line can be missing and
+    /// defaults to QWERTY.
+    pub fn from_text(s: &str) -> Result<Self, KeymapTextError> {
+        let mut lines = s.split('\n').map(str::trim).enumerate().peekable();
+
+        let layout = match lines.peek().and_then(|(_, line)| Layout::from_name(line)) {
+            Some(layout) => {
+                lines.next();
+                layout
+            }
+            None => Layout::default(),
+        };
+
+        let mut keymap = Keymap::empty(layout);
+        let mut hex_key = 0;
+        for (i, line) in lines {
+            let err = |msg: String| KeymapTextError { line: i + 1, msg };
+
+            if hex_key >= 16 {
+                return Err(err("more than 16 hex keys".to_string()));
+            }
+
+            let codes = line.split(',').filter(|c| !c.is_empty());
+            for code in codes {
+                if !is_code(code) {
+                    return Err(err(format!("unknown key {}", code)));
+                }
+                keymap.keys[hex_key].push(code.to_string());
+            }
+            hex_key += 1;
+        }
+
+        if hex_key < 16 {
+            return Err(KeymapTextError {
+                line: s.split('\n').count(),
+                msg: format!("only {} hex keys out of 16", hex_key),
+            });
+        }
+
+        Ok(keymap)
+    }
+
+    /// What's printed on the key `code` with the layout of the keymap.
+    pub fn label(&self, code: &str) -> String {
+        let table: &[(&str, &str)] = match self.layout {
+            Layout::Qwerty => &[],
+            Layout::Azerty => &AZERTY_LABELS,
+            Layout::Dvorak => &DVORAK_LABELS,
+        };
+
+        if let Some((_, label)) = table.iter().find(|(c, _)| *c == code) {
+            return label.to_string();
+        }
+
+        if let Some((_, label)) = QWERTY_LABELS.iter().find(|(c, _)| *c == code) {
+            return label.to_string();
+        }
+
+        for (prefix, replacement) in &[("Key", ""), ("Digit", ""), ("Numpad", "Num"), ("Arrow", "")]
+        {
+            if let Some(rest) = code.strip_prefix(prefix) {
+                return format!("{}{}", replacement, rest);
+            }
+        }
+
+        code.to_string()
+    }
+}
+
+/// Whether `code` is the name of a host key, see the module documentation.
+pub fn is_code(code: &str) -> bool {
+    let single = |prefix: &str, valid: fn(&char) -> bool| {
+        code.strip_prefix(prefix).is_some_and(|rest| {
+            let mut chars = rest.chars();
+            chars.next().as_ref().is_some_and(valid) && chars.next().is_none()
+        })
+    };
+
+    single("Key", char::is_ascii_uppercase)
+        || single("Digit", char::is_ascii_digit)
+        || single("Numpad", char::is_ascii_digit)
+        || NAMED_CODES.contains(&code)
+        || (code.starts_with('F') && code[1..].parse::<u8>().is_ok_and(|n| (1..=12).contains(&n)))
+}
+
+impl Padmap {
+    pub fn empty() -> Self {
+        Padmap {
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    /// The hex key bound to the gamepad input `input`.
+    pub fn hex_key(&self, input: &str) -> Option<u8> {
+        self.inputs.get(input).copied()
+    }
+
+    pub fn bind(&mut self, input: &str, hex_key: u8) {
+        self.inputs.insert(input.to_string(), hex_key);
+    }
+
+    /// Rebind the inputs in `overrides`, e.g. the ones for a given game.
+    pub fn apply(&mut self, overrides: &BTreeMap<String, u8>) {
+        self.inputs
+            .extend(overrides.iter().map(|(i, k)| (i.clone(), *k)));
+    }
+
+    pub fn bindings(&self) -> &BTreeMap<String, u8> {
+        &self.inputs
+    }
+
+    /// Bind the directions of the left stick to the same hex keys of the
+    /// D-pad, unless they're bound already.
+    pub fn stick_follows_dpad(&mut self) {
+        for (dpad, stick) in &DPAD_STICK {
+            if let Some(hex_key) = self.hex_key(dpad) {
+                self.inputs.entry(stick.to_string()).or_insert(hex_key);
+            }
+        }
+    }
+}
+
+/// Whether `input` is the name of a button or of a stick direction of a
+/// gamepad, see the module documentation.
+pub fn is_pad_input(input: &str) -> bool {
+    let axis = input
+        .strip_suffix('-')
+        .or_else(|| input.strip_suffix('+'))
+        .filter(|axis| !axis.ends_with("trigger"));
+
+    PAD_BUTTONS.contains(&input)
+        || PAD_AXES.contains(&input) && input.ends_with("trigger")
+        || axis.is_some_and(|axis| PAD_AXES.contains(&axis))
+}
+
+/// Parse a hex key written as a single hex digit.
+pub fn parse_hex_key(s: &str) -> Option<u8> {
+    u8::from_str_radix(s, 16).ok().filter(|k| *k < 16)
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset("qwerty").unwrap()
+    }
+}
+
+impl Default for Padmap {
+    /// The D-pad and the left stick on the arrows of the numeric keypad, `2`
+    /// `8` `4` and `6`, with A on `5` and B on `0`.
+    fn default() -> Self {
+        let mut padmap = Padmap::empty();
+        for (input, hex_key) in &[
+            ("dpup", 0x2),
+            ("dpdown", 0x8),
+            ("dpleft", 0x4),
+            ("dpright", 0x6),
+            ("a", 0x5),
+            ("b", 0x0),
+        ] {
+            padmap.bind(input, *hex_key);
+        }
+        padmap.stick_follows_dpad();
+        padmap
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = ParseKeymapError;
+
+    /// Parse the name of one of the `PRESETS`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Keymap::preset(s).ok_or_else(|| ParseKeymapError(s.to_string()))
+    }
+}
+
+impl fmt::Display for ParseKeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid keymap {}, expected one of {}",
+            self.0,
+            PRESETS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ParseKeymapError {}
+
+impl fmt::Display for KeymapTextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for KeymapTextError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn presets() {
+        let qwerty = Keymap::preset("qwerty").unwrap();
+        assert_eq!(qwerty.keys(0x5), ["KeyW"]);
+        assert_eq!(qwerty.hex_keys("KeyV").collect::<Vec<_>>(), vec![0xF]);
+        assert_eq!(qwerty.label("KeyQ"), "Q");
+
+        let numpad = Keymap::preset("numpad").unwrap();
+        assert_eq!(numpad.keys(0x5), ["Numpad5"]);
+        assert_eq!(numpad.keys(0x1), ["Numpad7"]);
+
+        // same keys, other labels
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!(azerty.bindings(), qwerty.bindings());
+        assert_eq!(azerty.label("KeyQ"), "A");
+
+        assert_eq!(Keymap::preset("colemak"), None);
+    }
+
+    #[test]
+    fn many_keys_per_hex_key() {
+        let mut keymap = Keymap::default();
+        keymap.bind(0x5, "ArrowUp");
+        assert_eq!(keymap.keys(0x5), ["KeyW", "ArrowUp"]);
+        assert_eq!(keymap.hex_keys("ArrowUp").collect::<Vec<_>>(), vec![0x5]);
+
+        // a host key goes to a single hex key
+        keymap.bind(0x8, "ArrowUp");
+        assert_eq!(keymap.keys(0x5), ["KeyW"]);
+        assert_eq!(keymap.hex_keys("ArrowUp").collect::<Vec<_>>(), vec![0x8]);
+
+        keymap.clear(0x8);
+        assert!(keymap.keys(0x8).is_empty());
+    }
+
+    #[test]
+    fn apply_overrides() {
+        let mut keymap = Keymap::default();
+        let mut overrides = BTreeMap::new();
+        overrides.insert(0x5, codes(&["ArrowUp", "Space"]));
+        overrides.insert(0x6, vec![]);
+        keymap.apply(&overrides);
+
+        assert_eq!(keymap.keys(0x5), ["ArrowUp", "Space"]);
+        assert!(keymap.keys(0x6).is_empty());
+        // the others are left alone
+        assert_eq!(keymap.keys(0x4), ["KeyQ"]);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        for preset in &PRESETS {
+            let keymap = Keymap::preset(preset).unwrap();
+            assert_eq!(Keymap::from_text(&keymap.to_text()), Ok(keymap));
+        }
+
+        let mut keymap = Keymap::preset("dvorak").unwrap();
+        keymap.bind(0x5, "ArrowUp");
+        keymap.clear(0x0);
+        assert_eq!(Keymap::from_text(&keymap.to_text()), Ok(keymap.clone()));
+
+        // the saves without a layout are QWERTY
+        let text = keymap.to_text();
+        let (_, without_layout) = text.split_once('\n').unwrap();
+        let keymap = Keymap::from_text(without_layout).unwrap();
+        assert_eq!(keymap.layout, Layout::Qwerty);
+        assert_eq!(keymap.keys(0x5), ["KeyW", "ArrowUp"]);
+    }
+
+    #[test]
+    fn text_errors() {
+        let err = |line: usize, msg: &str| {
+            Err(KeymapTextError {
+                line,
+                msg: msg.to_string(),
+            })
+        };
+
+        let mut lines = Keymap::default().to_text();
+        lines = lines.replace("KeyW", "KeyWW");
+        assert_eq!(Keymap::from_text(&lines), err(7, "unknown key KeyWW"));
+
+        assert_eq!(
+            Keymap::from_text("azerty\nKeyX"),
+            err(2, "only 1 hex keys out of 16")
+        );
+        assert_eq!(
+            Keymap::from_text(&(Keymap::default().to_text() + "\nKeyB")),
+            err(18, "more than 16 hex keys")
+        );
+    }
+}
@@ -1,3 +1,26 @@
+pub mod access;
+pub mod analysis;
+pub mod asm;
+pub mod audio;
+pub mod capture;
+pub mod cheats;
 pub mod chip8;
+pub mod db;
+pub mod detect;
+pub mod disasm;
+pub mod display;
+pub mod filters;
+pub mod framebuffer;
+pub mod hash;
+pub mod headless;
+pub mod heatmap;
+pub mod image;
+pub mod input;
+pub mod lockstep;
+pub mod palette;
+pub mod quirks;
+pub mod state;
+pub mod stats;
 
-pub use self::chip8::Chip8;
+pub use self::chip8::{Chip8, Event, ProgramError};
+pub use self::quirks::Quirks;
@@ -0,0 +1,204 @@
+//! Dynamic quirk detection: the same program is run under every combination
+//! of quirks in lockstep, with the same seed and input, to find out which
+//! quirks the program actually depends on.
+//!
+//! Every combination is compared against the default quirks after every
+//! instruction, the first instruction after which the states differ is the
+//! one sensitive to the quirks that have been flipped.
+
+use crate::detect;
+use crate::disasm::Instr;
+use crate::headless::{InputScript, Outcome, Runner, Supervisor};
+use crate::quirks::{Quirks, QUIRK_NAMES};
+
+/// The first instruction after which a run differs from the one with the
+/// default quirks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    pub frame: usize,
+    /// Number of instructions executed since the start.
+    pub cycle: usize,
+    pub pc: usize,
+    pub instr: Option<Instr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Combination {
+    pub quirks: Quirks,
+    pub outcome: Outcome,
+    pub divergence: Option<Divergence>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// One entry per combination, the first one has the default quirks and
+    /// the next `QUIRK_NAMES.len()` flip a single quirk each, in order.
+    pub combinations: Vec<Combination>,
+    /// The sane combination closest to what static detection recommends, if
+    /// any.
+    pub suggested: Option<Quirks>,
+}
+
+impl Report {
+    /// Where flipping the `i`-th quirk of `QUIRK_NAMES` alone makes the
+    /// program behave differently, `None` if the quirk doesn't matter.
+    pub fn quirk_divergence(&self, i: usize) -> Option<Divergence> {
+        self.combinations[1 + i].divergence
+    }
+}
+
+/// Run `program` for `frames` frames under every quirks combination.
+///
+/// Returns `None` if the program doesn't fit in memory.
+pub fn run(
+    program: &[u8],
+    seed: u64,
+    script: &InputScript,
+    frames: usize,
+    cycles_per_frame: usize,
+) -> Option<Report> {
+    let base = Quirks::default();
+
+    let mut runs = combinations(base)
+        .into_iter()
+        .map(|quirks| {
+            let mut runner = Runner::new(program, seed, cycles_per_frame)?;
+            runner.chip8_mut().set_quirks(quirks);
+
+            Some((Supervisor::new(runner), None))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut cycle = 0;
+    for frame in 0..frames {
+        for (run, _) in &mut runs {
+            run.start_frame(script);
+        }
+
+        for _ in 0..cycles_per_frame.max(1) {
+            let base_pc = runs[0].0.runner().chip8().pc();
+
+            for (run, _) in &mut runs {
+                run.run_cycle();
+            }
+
+            cycle += 1;
+
+            let ((base, _), others) = runs.split_first_mut().unwrap();
+            if !base.is_running() {
+                continue;
+            }
+
+            let base = base.runner().chip8();
+            for (run, divergence) in others.iter_mut().filter(|(_, d)| d.is_none()) {
+                if run.runner().chip8().same_state(base) {
+                    continue;
+                }
+
+                *divergence = Some(Divergence {
+                    frame,
+                    cycle,
+                    pc: base_pc,
+                    instr: Instr::fetch(base.ram(), base_pc),
+                });
+            }
+        }
+
+        for (run, _) in &mut runs {
+            run.end_frame();
+        }
+    }
+
+    let combinations = runs
+        .into_iter()
+        .map(|(run, divergence)| Combination {
+            quirks: run.runner().chip8().quirks(),
+            outcome: run.outcome(),
+            divergence,
+        })
+        .collect::<Vec<_>>();
+
+    let recommended = detect::detect(program).quirks;
+    let distance = |q: &Quirks| {
+        (0..QUIRK_NAMES.len())
+            .filter(|i| q.get(*i) != recommended.get(*i))
+            .count()
+    };
+
+    let suggested = combinations
+        .iter()
+        .filter(|c| c.outcome == Outcome::Ok)
+        .min_by_key(|c| distance(&c.quirks))
+        .map(|c| c.quirks);
+
+    Some(Report {
+        combinations,
+        suggested,
+    })
+}
+
+/// All the quirks combinations, starting from `base` followed by the ones
+/// flipping a single quirk.
+fn combinations(base: Quirks) -> Vec<Quirks> {
+    let n = QUIRK_NAMES.len();
+
+    let mut masks = (0..1_usize << n).collect::<Vec<_>>();
+    masks.sort_by_key(|m| m.count_ones());
+
+    masks
+        .into_iter()
+        .map(|mask| {
+            let mut quirks = base;
+            for i in (0..n).filter(|i| mask & (1 << i) != 0) {
+                quirks.set(i, !base.get(i));
+            }
+            quirks
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_alone_diverges() {
+        // LD V1, 4 / SHR V0, V1 / JP 0x204
+        let report = run(
+            &[0x61, 0x04, 0x80, 0x16, 0x12, 0x04],
+            0,
+            &InputScript::default(),
+            2,
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(
+            report.quirk_divergence(0),
+            Some(Divergence {
+                frame: 0,
+                cycle: 2,
+                pc: 0x202,
+                instr: Some(Instr::ShiftR(0, 1)),
+            })
+        );
+        for (i, name) in QUIRK_NAMES.iter().enumerate().skip(1) {
+            assert_eq!(report.quirk_divergence(i), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn neutral_program_never_diverges() {
+        // LD V1, 4 / ADD V0, V1 / JP 0x204
+        let report = run(
+            &[0x61, 0x04, 0x80, 0x14, 0x12, 0x04],
+            0,
+            &InputScript::default(),
+            2,
+            10,
+        )
+        .unwrap();
+
+        assert!(report.combinations.iter().all(|c| c.divergence.is_none()));
+    }
+}
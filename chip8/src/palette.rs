@@ -0,0 +1,193 @@
+//! Color palettes for the screen.
+//!
+//! A palette has from 2 to 4 colors: the background, the foreground and, for
+//! XO-CHIP programs drawing on two planes, the color of the second plane and
+//! the one of the pixels lit on both planes. It can also have an accent color
+//! replacing the background while the buzzer sounds.
+//!
+//! Palettes other than the builtin ones are loaded from TOML files like:
+//!
+//! ```toml
+//! [[palettes]]
+//! name = "sunset"
+//! colors = ["#2B1B3D", "#FFB38A"]
+//! buzzer = "#FF3D3D"
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::db::parse_hex_color;
+
+/// Name, colors and buzzer color of the builtin palettes.
+const BUILTIN: [(&str, &[&str], Option<&str>); 7] = [
+    ("vip", &["#000000", "#FFFFFF"], None),
+    ("amber", &["#1A0F00", "#FFB000"], Some("#4D2E00")),
+    ("green", &["#001A00", "#33FF33"], Some("#004D00")),
+    (
+        "gameboy",
+        &["#9BBC0F", "#0F380F", "#306230", "#8BAC0F"],
+        None,
+    ),
+    (
+        "octo",
+        &["#996600", "#FFCC00", "#FF6600", "#662200"],
+        Some("#FFAA00"),
+    ),
+    (
+        "high-contrast",
+        &["#000000", "#FFFF00", "#00FFFF", "#FFFFFF"],
+        Some("#FF0000"),
+    ),
+    // Okabe-Ito colors, distinguishable with any kind of color blindness
+    (
+        "colorblind",
+        &["#000000", "#E69F00", "#56B4E9", "#F0E442"],
+        Some("#D55E00"),
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    /// Between 2 and 4 colors, see the module documentation.
+    pub colors: Vec<[u8; 3]>,
+    pub buzzer: Option<[u8; 3]>,
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Toml(toml::de::Error),
+    /// A color not in the `#RRGGBB` format.
+    InvalidColor(String),
+    /// The palette with the given name has less than 2 or more than 4
+    /// colors.
+    ColorCount(String),
+}
+
+#[derive(Deserialize)]
+struct PaletteFile {
+    #[serde(default)]
+    palettes: Vec<RawPalette>,
+}
+
+#[derive(Deserialize)]
+struct RawPalette {
+    name: String,
+    colors: Vec<String>,
+    #[serde(default)]
+    buzzer: Option<String>,
+}
+
+impl Palette {
+    /// Create a palette out of colors in the `#RRGGBB` format.
+    pub fn from_hex<S: AsRef<str>>(
+        name: &str,
+        colors: &[S],
+        buzzer: Option<&str>,
+    ) -> Result<Self, PaletteError> {
+        let parse =
+            |c: &str| parse_hex_color(c).ok_or_else(|| PaletteError::InvalidColor(c.into()));
+
+        if !(2..=4).contains(&colors.len()) {
+            return Err(PaletteError::ColorCount(name.to_string()));
+        }
+
+        Ok(Palette {
+            name: name.to_string(),
+            colors: colors
+                .iter()
+                .map(|c| parse(c.as_ref()))
+                .collect::<Result<_, _>>()?,
+            buzzer: buzzer.map(parse).transpose()?,
+        })
+    }
+
+    pub fn builtin() -> Vec<Palette> {
+        BUILTIN
+            .iter()
+            .map(|(name, colors, buzzer)| {
+                Palette::from_hex(name, colors, *buzzer).expect("invalid builtin palette")
+            })
+            .collect()
+    }
+
+    pub fn find_builtin(name: &str) -> Option<Palette> {
+        Palette::builtin().into_iter().find(|p| p.name == name)
+    }
+
+    /// Parse a palette file, see the module documentation.
+    pub fn parse_file(s: &str) -> Result<Vec<Palette>, PaletteError> {
+        let file: PaletteFile = toml::from_str(s).map_err(PaletteError::Toml)?;
+
+        file.palettes
+            .iter()
+            .map(|p| Palette::from_hex(&p.name, &p.colors, p.buzzer.as_deref()))
+            .collect()
+    }
+
+    pub fn background(&self) -> [u8; 3] {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> [u8; 3] {
+        self.colors[1]
+    }
+
+    /// The color of the `i`-th entry of the palette, the ones missing fall
+    /// back to the foreground.
+    pub fn color(&self, i: usize) -> [u8; 3] {
+        self.colors
+            .get(i)
+            .copied()
+            .unwrap_or_else(|| self.foreground())
+    }
+
+    /// The background, replaced by the buzzer color while `buzzing` if the
+    /// palette has one.
+    pub fn background_while(&self, buzzing: bool) -> [u8; 3] {
+        match self.buzzer {
+            Some(buzzer) if buzzing => buzzer,
+            _ => self.background(),
+        }
+    }
+
+    /// Replace the background and the foreground, e.g. with the colors given
+    /// on the command line.
+    pub fn with_colors(mut self, bg: Option<[u8; 3]>, fg: Option<[u8; 3]>) -> Self {
+        if let Some(bg) = bg {
+            self.colors[0] = bg;
+        }
+        if let Some(fg) = fg {
+            self.colors[1] = fg;
+        }
+        self
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::find_builtin("vip").unwrap()
+    }
+}
+
+/// Opaque RGBA version of `rgb`.
+pub fn rgba(rgb: [u8; 3]) -> [u8; 4] {
+    [rgb[0], rgb[1], rgb[2], 0xFF]
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Toml(e) => write!(f, "invalid palette file: {}", e),
+            PaletteError::InvalidColor(c) => write!(f, "invalid color {}, expected #RRGGBB", c),
+            PaletteError::ColorCount(name) => {
+                write!(f, "palette {} must have between 2 and 4 colors", name)
+            }
+        }
+    }
+}
+
+impl Error for PaletteError {}
@@ -0,0 +1,112 @@
+//! Behaviours that differ between CHIP-8 interpreters and that programs might
+//! depend on.
+
+use serde::{Deserialize, Serialize};
+
+/// Names of the quirks, in the order used by `Quirks::get` and `Quirks::set`.
+pub const QUIRK_NAMES: [&str; 5] = ["shift", "load_store", "jump", "vf_reset", "clip"];
+
+/// The default quirks are the ones this interpreter has always implemented,
+/// which are the ones expected by most of the classic game packs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    /// `8XY6` and `8XYE` shift VX in place instead of storing VY shifted in
+    /// VX.
+    pub shift: bool,
+    /// `FX55` and `FX65` leave I pointing right after the last register
+    /// stored or loaded.
+    pub load_store: bool,
+    /// `BNNN` jumps to NNN plus VX, where X is the highest nibble of NNN,
+    /// instead of NNN plus V0.
+    pub jump: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    /// around.
+    pub clip: bool,
+}
+
+impl Quirks {
+    /// Quirks of the original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift: false,
+            load_store: true,
+            jump: false,
+            vf_reset: true,
+            clip: true,
+        }
+    }
+
+    /// Quirks of the SUPER-CHIP interpreter for the HP48 calculators.
+    pub fn schip() -> Self {
+        Quirks {
+            shift: true,
+            load_store: false,
+            jump: true,
+            vf_reset: false,
+            clip: true,
+        }
+    }
+
+    /// Quirks of Octo, the reference XO-CHIP interpreter.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift: false,
+            load_store: true,
+            jump: false,
+            vf_reset: false,
+            clip: false,
+        }
+    }
+
+    /// Whether the `i`-th quirk of `QUIRK_NAMES` is enabled.
+    pub fn get(&self, i: usize) -> bool {
+        match i {
+            0 => self.shift,
+            1 => self.load_store,
+            2 => self.jump,
+            3 => self.vf_reset,
+            4 => self.clip,
+            _ => panic!("there are only {} quirks", QUIRK_NAMES.len()),
+        }
+    }
+
+    pub fn set(&mut self, i: usize, enabled: bool) {
+        let q = match i {
+            0 => &mut self.shift,
+            1 => &mut self.load_store,
+            2 => &mut self.jump,
+            3 => &mut self.vf_reset,
+            4 => &mut self.clip,
+            _ => panic!("there are only {} quirks", QUIRK_NAMES.len()),
+        };
+
+        *q = enabled;
+    }
+
+    /// Find the quirks preset with the given name, either `default`, `vip`,
+    /// `schip` or `xochip`.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "vip" => Some(Quirks::vip()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            load_store: false,
+            jump: false,
+            vf_reset: false,
+            clip: false,
+        }
+    }
+}
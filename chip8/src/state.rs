@@ -0,0 +1,294 @@
+//! Save states: snapshots of the whole machine that can be written to disk
+//! and restored later.
+//!
+//! The random number generator isn't part of the state, so a restored
+//! program doesn't replay the same random numbers.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::chip8::{RAM_SIZE, STACK_SIZE};
+use crate::framebuffer::Framebuffer;
+use crate::quirks::{Quirks, QUIRK_NAMES};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 2;
+
+/// Extension of the save state files.
+pub const EXTENSION: &str = "c8s";
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub(crate) registers: [u8; 16],
+    pub(crate) i_reg: u16,
+    /// The RPL user flags, zero in the states of version 1 that lacked them.
+    pub(crate) flags: [u8; 16],
+    pub(crate) ram: Vec<u8>,
+    pub(crate) vram: Framebuffer,
+    pub(crate) pc: usize,
+    pub(crate) stack: [usize; STACK_SIZE],
+    pub(crate) sp: usize,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) waiting_keypress_reg: Option<usize>,
+    pub(crate) quirks: Quirks,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data isn't a save state or it has been truncated.
+    Invalid,
+    /// The save state has been written by a newer version.
+    UnsupportedVersion(u8),
+}
+
+impl SaveState {
+    /// Serialize the state in a compact binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+        out.extend_from_slice(&self.flags);
+        out.extend_from_slice(&(self.pc as u16).to_le_bytes());
+        for addr in &self.stack {
+            out.extend_from_slice(&(*addr as u16).to_le_bytes());
+        }
+        out.push(self.sp as u8);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.push(self.waiting_keypress_reg.map_or(0xFF, |x| x as u8));
+
+        let mut quirks = 0_u8;
+        for i in 0..QUIRK_NAMES.len() {
+            quirks |= u8::from(self.quirks.get(i)) << i;
+        }
+        out.push(quirks);
+
+        out.push(u8::from(self.vram.is_hires()));
+        let row_bytes = self.vram.width() / 8;
+        for y in 0..self.vram.height() {
+            out.extend_from_slice(&self.vram.row(y).to_be_bytes()[16 - row_bytes..]);
+        }
+
+        out.extend_from_slice(&self.ram);
+
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut data = Reader(data);
+
+        if data.take(MAGIC.len())? != MAGIC {
+            return Err(StateError::Invalid);
+        }
+        let version = data.byte()?;
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut registers = [0; 16];
+        registers.copy_from_slice(data.take(16)?);
+        let i_reg = data.word()?;
+        let mut flags = [0; 16];
+        if version >= 2 {
+            flags.copy_from_slice(data.take(16)?);
+        }
+        let pc = usize::from(data.word()?);
+        let mut stack = [0; STACK_SIZE];
+        for addr in &mut stack {
+            *addr = usize::from(data.word()?);
+        }
+        let sp = usize::from(data.byte()?);
+        let delay_timer = data.byte()?;
+        let sound_timer = data.byte()?;
+        let waiting_keypress_reg = match data.byte()? {
+            0xFF => None,
+            x if x < 16 => Some(usize::from(x)),
+            _ => return Err(StateError::Invalid),
+        };
+
+        let bits = data.byte()?;
+        let mut quirks = Quirks::default();
+        for i in 0..QUIRK_NAMES.len() {
+            quirks.set(i, bits & (1 << i) != 0);
+        }
+
+        let mut vram = match data.byte()? {
+            0 => Framebuffer::new(),
+            1 => Framebuffer::hires(),
+            _ => return Err(StateError::Invalid),
+        };
+        let row_bytes = vram.width() / 8;
+        for y in 0..vram.height() {
+            let mut row = [0; 16];
+            row[16 - row_bytes..].copy_from_slice(data.take(row_bytes)?);
+            vram.set_row(y, u128::from_be_bytes(row));
+        }
+
+        let ram = data.take(RAM_SIZE)?.to_vec();
+
+        if sp > STACK_SIZE || pc >= RAM_SIZE || !data.0.is_empty() {
+            return Err(StateError::Invalid);
+        }
+
+        Ok(SaveState {
+            registers,
+            i_reg,
+            flags,
+            ram,
+            vram,
+            pc,
+            stack,
+            sp,
+            delay_timer,
+            sound_timer,
+            waiting_keypress_reg,
+            quirks,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < n {
+            return Err(StateError::Invalid);
+        }
+
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, StateError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Invalid => write!(f, "invalid save state"),
+            StateError::UnsupportedVersion(v) => {
+                write!(f, "unsupported save state version {}", v)
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::chip8::{ProgramError, FONT_SET};
+    use crate::Chip8;
+
+    // LD V0, 5 / LD R, V0 / LD I, 0x200 / LD [I], V0 / JP 0x208
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0xF0, 0x75, 0xA2, 0x00, 0xF0, 0x55, 0x12, 0x08];
+    // LD V0, R / JP 0x202
+    const LOAD_FLAGS: [u8; 4] = [0xF0, 0x85, 0x12, 0x02];
+
+    #[test]
+    fn flags_survive_a_round_trip() {
+        // LD V0, 5 / LD R, V0 / JP 0x204
+        let program = [0x60, 0x05, 0xF0, 0x75, 0x12, 0x04];
+        let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(0), &program).unwrap();
+        chip8.emulate_cycle();
+        chip8.emulate_cycle();
+
+        let state = SaveState::from_bytes(&chip8.save_state().to_bytes()).unwrap();
+        assert_eq!(state.flags[0], 5);
+
+        let mut restored = Chip8::with_program(StdRng::seed_from_u64(0), &program).unwrap();
+        restored.load_state(&state);
+        assert!(restored.same_state(&chip8));
+    }
+
+    #[test]
+    fn version_1_has_no_flags() {
+        let state = Chip8::with_program(StdRng::seed_from_u64(0), &[0x12, 0x00])
+            .unwrap()
+            .save_state();
+
+        let mut data = state.to_bytes();
+        data[MAGIC.len()] = 1;
+        let flags = MAGIC.len() + 1 + 16 + 2;
+        data.drain(flags..flags + 16);
+
+        assert!(SaveState::from_bytes(&data).unwrap() == state);
+    }
+
+    #[test]
+    fn soft_reset_starts_over() {
+        let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(0), &PROGRAM).unwrap();
+        chip8.set_quirks(Quirks::vip());
+        for _ in 0..5 {
+            chip8.emulate_cycle();
+        }
+        assert_eq!(chip8.ram()[0x200], 5);
+
+        chip8.reset();
+
+        // like a hard reset, which builds a new machine, but with the same
+        // quirks
+        let fresh = Chip8::with_program(StdRng::seed_from_u64(1), &PROGRAM).unwrap();
+        assert!(chip8.same_state(&fresh));
+        assert_eq!(chip8.quirks(), Quirks::vip());
+        assert_eq!(chip8.save_state().flags, [0; 16]);
+    }
+
+    #[test]
+    fn flags_survive_resets_when_persistent() {
+        for persistent in [false, true].iter().copied() {
+            let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(0), &PROGRAM).unwrap();
+            chip8.set_persistent_flags(persistent);
+            chip8.emulate_cycle();
+            chip8.emulate_cycle();
+
+            chip8.reset();
+            assert_eq!(chip8.save_state().flags[0], if persistent { 5 } else { 0 });
+
+            chip8.load_program(&LOAD_FLAGS).unwrap();
+            chip8.emulate_cycle();
+            assert_eq!(chip8.registers()[0], if persistent { 5 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn load_at_checks_the_address() {
+        let mut chip8 = Chip8::with_program(StdRng::seed_from_u64(0), &PROGRAM).unwrap();
+
+        chip8.load_at(0x600, &PROGRAM).unwrap();
+        assert_eq!(chip8.pc(), 0x600);
+        assert_eq!(chip8.ram()[0x600..0x60A], PROGRAM);
+
+        assert_eq!(
+            chip8.load_at(FONT_SET.len() - 1, &PROGRAM),
+            Err(ProgramError::InvalidAddress(FONT_SET.len() - 1))
+        );
+        assert_eq!(
+            chip8.load_at(RAM_SIZE, &[]),
+            Err(ProgramError::InvalidAddress(RAM_SIZE))
+        );
+        assert_eq!(
+            chip8.load_at(RAM_SIZE - 1, &PROGRAM[..2]),
+            Err(ProgramError::TooLarge { size: 2, max: 1 })
+        );
+
+        // the program loaded before is kept
+        assert_eq!(chip8.program(), (0x600, &PROGRAM[..]));
+        assert_eq!(chip8.load_at(FONT_SET.len(), &PROGRAM), Ok(()));
+    }
+}
@@ -0,0 +1,132 @@
+//! Summary statistics of a rom, computed statically.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::json;
+
+use crate::analysis::{Analysis, SuspiciousKind};
+use crate::chip8::{FONT_SET, PROGRAM_START_PC, RAM_SIZE, STACK_SIZE};
+use crate::disasm::Instr;
+use crate::hash;
+
+/// Number of bytes of the sprite of every font digit.
+const FONT_SPRITE_SIZE: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct RomStats {
+    pub size: usize,
+    pub sha1: String,
+    pub sha256: String,
+    /// Whether the program fits in the RAM after `PROGRAM_START_PC`.
+    pub fits: bool,
+    /// Number of reachable instructions by opcode pattern.
+    pub histogram: BTreeMap<&'static str, usize>,
+    /// Address and word of the reachable words that aren't valid
+    /// instructions.
+    pub invalid: Vec<(usize, u16)>,
+    /// Font digits whose sprite is referenced with a constant.
+    pub font_digits: BTreeSet<u8>,
+    /// Number of `FX29` whose digit is only known at runtime.
+    pub dynamic_font_refs: usize,
+    /// Number of subroutines, excluding the program entry.
+    pub subroutines: usize,
+    /// See `Analysis::max_call_depth`.
+    pub max_call_depth: Option<usize>,
+}
+
+impl RomStats {
+    pub fn new(program: &[u8]) -> Self {
+        let analysis = Analysis::new(program);
+
+        let mut histogram = BTreeMap::new();
+        let mut font_digits = BTreeSet::new();
+        let mut dynamic_font_refs = 0;
+
+        for block in analysis.blocks.values() {
+            for (i, (_, instr)) in block.instrs.iter().enumerate() {
+                *histogram.entry(instr.pattern()).or_insert(0) += 1;
+
+                match *instr {
+                    Instr::SetI(nnn) if usize::from(nnn) < FONT_SET.len() => {
+                        font_digits.insert((usize::from(nnn) / FONT_SPRITE_SIZE) as u8);
+                    }
+                    Instr::FontSpriteAddr(x) => {
+                        let digit =
+                            block.instrs[..i]
+                                .iter()
+                                .rev()
+                                .find_map(|(_, prev)| match prev {
+                                    Instr::Load(r, nn) if *r == x => Some(Some(*nn)),
+                                    prev if prev.writes_register(x) => Some(None),
+                                    _ => None,
+                                });
+
+                        match digit {
+                            Some(Some(d)) => {
+                                font_digits.insert(d & 0xF);
+                            }
+                            _ => dynamic_font_refs += 1,
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let invalid = analysis
+            .suspicious
+            .iter()
+            .filter(|s| s.kind == SuspiciousKind::InvalidInstruction)
+            .map(|s| {
+                let off = s.addr - PROGRAM_START_PC;
+                (
+                    s.addr,
+                    (u16::from(program[off]) << 8) | u16::from(program[off + 1]),
+                )
+            })
+            .collect();
+
+        let subroutines = analysis
+            .subroutines
+            .keys()
+            .filter(|e| **e != PROGRAM_START_PC)
+            .count();
+
+        RomStats {
+            size: program.len(),
+            sha1: hash::sha1(program),
+            sha256: hash::sha256(program),
+            fits: program.len() <= RAM_SIZE - PROGRAM_START_PC,
+            histogram,
+            invalid,
+            font_digits,
+            dynamic_font_refs,
+            subroutines,
+            max_call_depth: analysis.max_call_depth(),
+        }
+    }
+
+    /// Whether the calls might overflow the stack, recursion counts as such.
+    pub fn may_overflow_stack(&self) -> bool {
+        self.max_call_depth.is_none_or(|d| d > STACK_SIZE)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "size": self.size,
+            "sha1": self.sha1,
+            "sha256": self.sha256,
+            "fits": self.fits,
+            "histogram": self.histogram,
+            "invalid": self.invalid.iter().map(|(addr, word)| json!({
+                "addr": addr,
+                "word": word,
+            })).collect::<Vec<_>>(),
+            "font_digits": self.font_digits,
+            "dynamic_font_refs": self.dynamic_font_refs,
+            "subroutines": self.subroutines,
+            "max_call_depth": self.max_call_depth,
+            "stack_size": STACK_SIZE,
+        })
+    }
+}